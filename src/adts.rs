@@ -0,0 +1,128 @@
+//! AAC in ADTS framing, where every raw AAC frame gets a 7 byte header (9
+//! with a CRC) starting with a 12 bit sync word much like MP3's.
+
+use crate::carve::{Format, FrameInfo, Framed, Source};
+
+/// Sample rates by sampling frequency index; 13 to 15 are reserved.
+static SAMPLE_RATES: [u32; 13] = [
+    96000, 88200, 64000, 48000, 44100, 32000, 24000, 22050, 16000, 12000, 11025, 8000, 7350,
+];
+
+/// Length of the header without CRC.
+pub const HEADER_LEN: usize = 7;
+
+/// The parts of an ADTS header that matter for carving.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AdtsHeader {
+    /// Audio object type minus one, e.g. 1 for AAC LC
+    pub profile: u8,
+    pub sample_rate: u32,
+    /// Channel configuration; 0 if it is given in the stream instead
+    pub channels: u8,
+    /// Length of the whole frame, header included
+    pub length: usize,
+    /// Length of the header, 9 with a CRC
+    pub header_len: usize,
+    /// Samples (per channel) the frame decodes to
+    pub samples: u32,
+}
+
+impl AdtsHeader {
+    /// Bitrate of this frame alone, in bits per second.
+    pub fn bit_rate(&self) -> u32 {
+        (self.length as u64 * 8 * u64::from(self.sample_rate) / u64::from(self.samples)) as u32
+    }
+
+    /// Raw AAC frames in this ADTS frame, usually one.
+    pub fn blocks(&self) -> u32 {
+        self.samples / 1024
+    }
+
+    /// The AudioSpecificConfig of MP4 files for the stream: the audio object
+    /// type, the sampling frequency index and the channel configuration.
+    pub fn audio_specific_config(&self) -> [u8; 2] {
+        let index = SAMPLE_RATES
+            .iter()
+            .position(|&rate| rate == self.sample_rate)
+            .unwrap_or(0);
+        let config =
+            u16::from(self.profile + 1) << 11 | (index as u16) << 7 | u16::from(self.channels) << 3;
+        config.to_be_bytes()
+    }
+
+    /// Whether this frame can follow `previous` in the same stream, whose
+    /// audio configuration doesn't change.
+    pub fn continues(&self, previous: &AdtsHeader) -> bool {
+        self.profile == previous.profile
+            && self.sample_rate == previous.sample_rate
+            && self.channels == previous.channels
+    }
+}
+
+/// Check whether `bytes` start with an ADTS header, and parse it.
+pub fn parse_header(bytes: &[u8]) -> Option<AdtsHeader> {
+    let bytes = bytes.get(..HEADER_LEN)?;
+    // sync word, then the layer, which is always 0
+    if bytes[0] != 0xFF || bytes[1] & 0xF6 != 0xF0 {
+        return None;
+    }
+    let protection_absent = bytes[1] & 0x01 == 1;
+    let profile = bytes[2] >> 6;
+    let sample_rate = *SAMPLE_RATES.get(usize::from((bytes[2] >> 2) & 0x0F))?;
+    let channels = (bytes[2] & 0x01) << 2 | bytes[3] >> 6;
+    let length = usize::from(bytes[3] & 0x03) << 11
+        | usize::from(bytes[4]) << 3
+        | usize::from(bytes[5] >> 5);
+    let header_len = if protection_absent {
+        HEADER_LEN
+    } else {
+        HEADER_LEN + 2
+    };
+    if length <= header_len {
+        return None;
+    }
+    let blocks = u32::from(bytes[6] & 0x03) + 1;
+
+    Some(AdtsHeader {
+        profile,
+        sample_rate,
+        channels,
+        length,
+        header_len,
+        samples: 1024 * blocks,
+    })
+}
+
+/// AAC in ADTS frames, carved as runs of frames.
+pub struct Adts;
+
+impl Framed for Adts {
+    type Frame = AdtsHeader;
+
+    fn format(&self) -> Format {
+        Format::Adts
+    }
+
+    fn starts_with(&self, byte: u8) -> bool {
+        byte == 0xFF
+    }
+
+    fn frame(&self, source: &mut dyn Source, previous: Option<&AdtsHeader>) -> Option<AdtsHeader> {
+        let frame = parse_header(source.peek(HEADER_LEN)?)?;
+        match previous {
+            Some(previous) if !frame.continues(previous) => None,
+            _ => Some(frame),
+        }
+    }
+
+    fn info(&self, frame: &AdtsHeader) -> FrameInfo {
+        FrameInfo {
+            length: frame.length,
+            samples: frame.samples,
+            sample_rate: frame.sample_rate,
+            bit_rate: frame.bit_rate(),
+            channel_mode: None,
+            corrupt: false,
+        }
+    }
+}
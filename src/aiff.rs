@@ -0,0 +1,239 @@
+//! AIFF files: an IFF `FORM` chunk of form type `AIFF` (or `AIFC` for
+//! compressed audio) holding a `COMM` chunk that describes the audio and an
+//! `SSND` chunk with the audio itself, among others. Like WAV, but chunk
+//! lengths are big-endian.
+
+use std::convert::TryInto;
+use tracing::trace;
+
+use crate::carve::{Carver, Deadline, Format, ScanOptions, Source, Stopped, Stream};
+
+/// Length of a chunk header: its id and length.
+const CHUNK_HEADER_LEN: usize = 8;
+
+/// Length of the FORM header including the form type.
+const FORM_HEADER_LEN: usize = CHUNK_HEADER_LEN + 4;
+
+/// What the `COMM` chunk says about the audio.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Common {
+    pub channels: u16,
+    /// Samples (per channel) in the file
+    pub sample_frames: u32,
+    pub sample_size: u16,
+    pub sample_rate: u32,
+}
+
+/// The integer part of an 80-bit IEEE 754 extended precision number, which
+/// is how `COMM` gives the sample rate.
+fn extended_to_u32(bytes: &[u8; 10]) -> Option<u32> {
+    let exponent = i32::from(u16::from_be_bytes([bytes[0], bytes[1]]));
+    let mantissa = u64::from_be_bytes(bytes[2..].try_into().ok()?);
+    // the mantissa has an explicit integer bit, and the sign has to be clear
+    let shift = 16383 + 63 - exponent;
+    if !(0..64).contains(&shift) {
+        return None;
+    }
+    (mantissa >> shift).try_into().ok()
+}
+
+/// Parse the contents of a `COMM` chunk.
+pub fn parse_comm(bytes: &[u8]) -> Option<Common> {
+    let bytes = bytes.get(..18)?;
+    let common = Common {
+        channels: u16::from_be_bytes([bytes[0], bytes[1]]),
+        sample_frames: u32::from_be_bytes(bytes[2..6].try_into().ok()?),
+        sample_size: u16::from_be_bytes([bytes[6], bytes[7]]),
+        sample_rate: extended_to_u32(bytes[8..18].try_into().ok()?)?,
+    };
+    if common.channels == 0 || common.sample_rate == 0 {
+        return None;
+    }
+    Some(common)
+}
+
+/// Whether `id` looks like a chunk id: four printable characters.
+fn is_chunk_id(id: &[u8]) -> bool {
+    id.iter().all(|&c| c.is_ascii_graphic() || c == b' ')
+}
+
+/// AIFF files, carved chunk by chunk to the end of the FORM chunk.
+pub struct Aiff;
+
+impl Carver for Aiff {
+    fn starts_with(&self, byte: u8) -> bool {
+        byte == b'F'
+    }
+
+    /// The lengths of chunks are trusted, but a file cut short ends where the
+    /// input does and is truncated; `None` without a `COMM` chunk before the
+    /// `SSND` chunk.
+    fn carve(
+        &self,
+        source: &mut dyn Source,
+        _options: &ScanOptions,
+        _deadline: &mut Deadline,
+    ) -> Result<Option<Stream>, Stopped> {
+        let form_len = match source.peek(FORM_HEADER_LEN) {
+            Some(header)
+                if header.starts_with(b"FORM") && matches!(&header[8..12], b"AIFF" | b"AIFC") =>
+            {
+                let len = u32::from_be_bytes([header[4], header[5], header[6], header[7]]);
+                CHUNK_HEADER_LEN as u64 + u64::from(len)
+            }
+            _ => return Ok(None),
+        };
+        let offset = source.position();
+        let mut data = Vec::new();
+        source.take_into(FORM_HEADER_LEN, &mut data);
+
+        let mut common = None;
+        let mut audio_len = None;
+        let mut truncated = false;
+        while (data.len() as u64) < form_len {
+            let (id, len) = match source.peek(CHUNK_HEADER_LEN) {
+                Some(header) if is_chunk_id(&header[..4]) => {
+                    let len = u32::from_be_bytes([header[4], header[5], header[6], header[7]]);
+                    ([header[0], header[1], header[2], header[3]], len as usize)
+                }
+                _ => break,
+            };
+            match &id {
+                b"COMM" => {
+                    let chunk = source.peek(CHUNK_HEADER_LEN + len);
+                    common = chunk.and_then(|chunk| parse_comm(&chunk[CHUNK_HEADER_LEN..]));
+                }
+                b"SSND" if common.is_none() => {
+                    trace!("{:#x}: rejected AIFF file without COMM chunk", offset);
+                    return Ok(None);
+                }
+                _ => {}
+            }
+            let padded = CHUNK_HEADER_LEN + len + len % 2;
+            let taken = source.take_into(padded, &mut data);
+            if &id == b"SSND" {
+                // after the offset and block size fields
+                audio_len = Some(taken.saturating_sub(CHUNK_HEADER_LEN + 8).min(len));
+            }
+            if taken < padded {
+                trace!("{:#x}: AIFF file cut short", offset);
+                truncated = true;
+                break;
+            }
+        }
+        let (common, audio_len) = match (common, audio_len) {
+            (Some(common), Some(audio_len)) => (common, audio_len),
+            _ => {
+                trace!("{:#x}: rejected AIFF file without audio", offset);
+                return Ok(None);
+            }
+        };
+
+        // what a file cut short still holds, if it was PCM
+        let sample_size = usize::from(common.sample_size).div_ceil(8);
+        let frame_size = usize::from(common.channels) * sample_size;
+        let samples = match truncated {
+            true if frame_size != 0 => (audio_len / frame_size) as u64,
+            _ => u64::from(common.sample_frames),
+        };
+        let bit_rate = match samples {
+            0 => 0,
+            samples => audio_len as u64 * 8 * u64::from(common.sample_rate) / samples,
+        };
+        Ok(Some(Stream {
+            data,
+            format: Format::Aiff,
+            offset,
+            frames: samples as usize,
+            samples,
+            sample_rate: common.sample_rate,
+            bit_rate: bit_rate as u32,
+            channel_mode: None,
+            truncated,
+            tags: Vec::new(),
+            gapless: None,
+            corrupt_frames: 0,
+            gaps: Vec::new(),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::carve::extract;
+
+    /// 44.1 kHz as an 80-bit extended
+    const RATE_44100: [u8; 10] = [0x40, 0x0E, 0xAC, 0x44, 0, 0, 0, 0, 0, 0];
+
+    fn chunk(id: &[u8; 4], body: &[u8]) -> Vec<u8> {
+        let mut chunk = id.to_vec();
+        chunk.extend_from_slice(&(body.len() as u32).to_be_bytes());
+        chunk.extend_from_slice(body);
+        chunk
+    }
+
+    /// 16 bit stereo PCM at 44.1 kHz, half a second of noise.
+    fn aiff() -> Vec<u8> {
+        let mut comm = 2u16.to_be_bytes().to_vec();
+        comm.extend_from_slice(&22050u32.to_be_bytes());
+        comm.extend_from_slice(&16u16.to_be_bytes());
+        comm.extend_from_slice(&RATE_44100);
+        let mut ssnd = vec![0; 8];
+        ssnd.extend((0..88200usize).map(|i| (i.wrapping_mul(2_654_435_761) >> 16) as u8));
+        let mut body = b"AIFF".to_vec();
+        body.extend_from_slice(&chunk(b"COMM", &comm));
+        body.extend_from_slice(&chunk(b"SSND", &ssnd));
+        chunk(b"FORM", &body)
+    }
+
+    fn carve(input: Vec<u8>) -> Vec<Stream> {
+        let options = ScanOptions {
+            threshold: 0,
+            ..ScanOptions::default()
+        };
+        extract(input, &options).unwrap()
+    }
+
+    #[test]
+    fn sample_rates() {
+        assert_eq!(extended_to_u32(&RATE_44100), Some(44100));
+        // 8 kHz, and 0.5 Hz
+        assert_eq!(
+            extended_to_u32(&[0x40, 0x0B, 0xFA, 0, 0, 0, 0, 0, 0, 0]),
+            Some(8000)
+        );
+        assert_eq!(
+            extended_to_u32(&[0x3F, 0xFE, 0x80, 0, 0, 0, 0, 0, 0, 0]),
+            None
+        );
+    }
+
+    #[test]
+    fn file_carved_to_the_end_of_the_form_chunk() {
+        let aiff = aiff();
+        let mut input = vec![0xEE; 3333];
+        input.extend_from_slice(&aiff);
+        input.extend_from_slice(&[0xEE; 444]);
+        let streams = carve(input);
+        assert_eq!(streams.len(), 1);
+        let stream = &streams[0];
+        assert_eq!(stream.format, Format::Aiff);
+        assert_eq!((stream.offset, stream.data.len()), (3333, aiff.len()));
+        assert_eq!(stream.samples, 22050);
+        assert_eq!((stream.sample_rate, stream.bit_rate), (44100, 1_411_200));
+        assert!(!stream.truncated);
+    }
+
+    #[test]
+    fn file_cut_short_counts_the_samples_left() {
+        let mut input = vec![0xEE; 3333];
+        input.extend_from_slice(&aiff()[..40_000]);
+        let streams = carve(input);
+        assert_eq!(streams.len(), 1);
+        assert_eq!(streams[0].data.len(), 40_000);
+        // the headers take 54 bytes
+        assert_eq!(streams[0].samples, (40_000 - 54) / 4);
+        assert!(streams[0].truncated);
+    }
+}
@@ -0,0 +1,189 @@
+//! AMR files, as the app stores voice memos in: the `#!AMR` (narrowband) or
+//! `#!AMR-WB` (wideband) magic line, then 20 ms frames whose length follows
+//! from the frame type in their one byte header.
+
+use tracing::trace;
+
+use crate::carve::{Carver, Deadline, Format, ScanOptions, Source, Stopped, Stream};
+
+/// Lengths of narrowband frames, header included, by frame type; 0 for the
+/// reserved types.
+static NB_FRAME_LENGTHS: [usize; 16] = [13, 14, 16, 18, 20, 21, 27, 32, 6, 0, 0, 0, 0, 0, 0, 1];
+
+/// Lengths of wideband frames, header included, by frame type.
+static WB_FRAME_LENGTHS: [usize; 16] = [18, 24, 33, 37, 41, 47, 51, 59, 61, 6, 0, 0, 0, 0, 1, 1];
+
+/// One of the two AMR codecs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Band {
+    Narrow,
+    Wide,
+}
+
+impl Band {
+    fn magic(self) -> &'static [u8] {
+        match self {
+            Band::Narrow => b"#!AMR\n",
+            Band::Wide => b"#!AMR-WB\n",
+        }
+    }
+
+    fn sample_rate(self) -> u32 {
+        match self {
+            Band::Narrow => 8000,
+            Band::Wide => 16000,
+        }
+    }
+
+    /// Length of the frame whose header is `header`, if it is one.
+    fn frame_length(self, header: u8) -> Option<usize> {
+        // padding bits around the frame type and quality bit
+        if header & 0x83 != 0 {
+            return None;
+        }
+        let lengths = match self {
+            Band::Narrow => &NB_FRAME_LENGTHS,
+            Band::Wide => &WB_FRAME_LENGTHS,
+        };
+        Some(lengths[usize::from(header >> 3 & 0x0F)]).filter(|&length| length != 0)
+    }
+}
+
+/// AMR files, carved from the magic line to the last frame.
+pub struct Amr;
+
+impl Carver for Amr {
+    fn starts_with(&self, byte: u8) -> bool {
+        byte == b'#'
+    }
+
+    /// A file ends at the first byte that isn't a frame header; it is
+    /// truncated if the last frame is cut short. `None` without frames.
+    fn carve(
+        &self,
+        source: &mut dyn Source,
+        _options: &ScanOptions,
+        deadline: &mut Deadline,
+    ) -> Result<Option<Stream>, Stopped> {
+        let magic = source.peek_up_to(Band::Wide.magic().len());
+        let bands = [Band::Narrow, Band::Wide];
+        let band = match bands
+            .iter()
+            .copied()
+            .find(|band| magic.starts_with(band.magic()))
+        {
+            Some(band) => band,
+            None => return Ok(None),
+        };
+        let offset = source.position();
+        let mut data = Vec::new();
+        source.take_into(band.magic().len(), &mut data);
+
+        let mut frames = 0;
+        let mut truncated = false;
+        loop {
+            deadline.check(source.position())?;
+            let length = match source
+                .peek(1)
+                .and_then(|header| band.frame_length(header[0]))
+            {
+                Some(length) => length,
+                None => break,
+            };
+            if source.take_into(length, &mut data) < length {
+                trace!("{:#x}: AMR file cut short", offset);
+                truncated = true;
+                break;
+            }
+            frames += 1;
+        }
+        if frames == 0 {
+            return Ok(None);
+        }
+
+        // every frame is 20 ms
+        let samples = frames as u64 * u64::from(band.sample_rate()) / 50;
+        let bit_rate = data.len() as u64 * 8 * u64::from(band.sample_rate()) / samples;
+        Ok(Some(Stream {
+            data,
+            format: Format::Amr,
+            offset,
+            frames,
+            samples,
+            sample_rate: band.sample_rate(),
+            bit_rate: bit_rate as u32,
+            channel_mode: None,
+            truncated,
+            tags: Vec::new(),
+            gapless: None,
+            corrupt_frames: 0,
+            gaps: Vec::new(),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::carve::extract;
+
+    /// `count` frames of type `frame_type` after the magic line of `band`.
+    fn amr(band: Band, frame_type: u8, count: usize) -> Vec<u8> {
+        let header = frame_type << 3 | 0x04;
+        let length = band.frame_length(header).unwrap();
+        let mut out = band.magic().to_vec();
+        for n in 0..count {
+            out.push(header);
+            out.extend(
+                (1..length).map(|i| ((n * length + i).wrapping_mul(2_654_435_761) >> 16) as u8),
+            );
+        }
+        out
+    }
+
+    fn carve(input: Vec<u8>) -> Vec<Stream> {
+        let options = ScanOptions {
+            threshold: 0,
+            ..ScanOptions::default()
+        };
+        extract(input, &options).unwrap()
+    }
+
+    #[test]
+    fn narrowband_file_ends_at_a_byte_that_isnt_a_header() {
+        // 12.2 kbps
+        let amr = amr(Band::Narrow, 7, 2000);
+        let mut input = vec![0xEE; 3333];
+        input.extend_from_slice(&amr);
+        input.extend_from_slice(&[0xEE; 444]);
+        let streams = carve(input);
+        assert_eq!(streams.len(), 1);
+        let stream = &streams[0];
+        assert_eq!(stream.format, Format::Amr);
+        assert_eq!((stream.offset, stream.data.len()), (3333, amr.len()));
+        assert_eq!((stream.frames, stream.samples), (2000, 320_000));
+        assert_eq!(stream.sample_rate, 8000);
+        assert!(!stream.truncated);
+    }
+
+    #[test]
+    fn wideband_file_cut_short() {
+        // 23.85 kbps
+        let amr = amr(Band::Wide, 8, 1500);
+        let mut input = vec![0xEE; 3333];
+        input.extend_from_slice(&amr[..amr.len() - 30]);
+        let streams = carve(input);
+        assert_eq!(streams.len(), 1);
+        let stream = &streams[0];
+        assert_eq!((stream.frames, stream.samples), (1499, 1499 * 320));
+        assert_eq!(stream.sample_rate, 16000);
+        assert!(stream.truncated);
+    }
+
+    #[test]
+    fn magic_line_without_frames_rejected() {
+        let mut input = b"#!AMR\n".to_vec();
+        input.extend_from_slice(&[0xEE; 444]);
+        assert!(carve(input).is_empty());
+    }
+}
@@ -0,0 +1,36 @@
+//! APEv2 tags, which some encoders put after (or rarely before) an MP3
+//! stream: a 32 byte header, the items and a 32 byte footer, both starting
+//! with `APETAGEX`. Only tags with a header can be recognized from the front.
+
+use std::convert::TryInto;
+
+/// Length of the header and of the footer.
+pub const HEADER_SIZE: usize = 32;
+
+/// Tags larger than this aren't taken; they hold text and the odd cover.
+const MAX_TAG_SIZE: usize = 16 << 20;
+
+// header and footer flags
+const HAS_HEADER: u32 = 1 << 31;
+const IS_HEADER: u32 = 1 << 29;
+
+/// Total size of the APEv2 tag whose header `data` starts with, header and
+/// footer included.
+pub fn tag_size(data: &[u8]) -> Option<usize> {
+    let header = data.get(..HEADER_SIZE)?;
+    if !header.starts_with(b"APETAGEX") {
+        return None;
+    }
+    let le_u32 = |at: usize| header[at..at + 4].try_into().ok().map(u32::from_le_bytes);
+    let version = le_u32(8)?;
+    // the size counts the items and the footer, but not the header
+    let size = le_u32(12)? as usize;
+    let flags = le_u32(20)?;
+    if version != 2000 || flags & (HAS_HEADER | IS_HEADER) != HAS_HEADER | IS_HEADER {
+        return None;
+    }
+    if !(HEADER_SIZE..=MAX_TAG_SIZE).contains(&size) {
+        return None;
+    }
+    Some(HEADER_SIZE + size)
+}
@@ -0,0 +1,181 @@
+use crate::output;
+use flate2::write::GzEncoder;
+use std::collections::HashSet;
+use std::convert::TryFrom;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+/// A single archive all extracted files are written into, given with
+/// `--archive`. The format (zip, tar or gzipped tar) is picked from the file
+/// extension.
+///
+/// Entries are written out as they are added, so the archive is never held in
+/// memory.
+pub struct Archive {
+    path: PathBuf,
+    inner: Mutex<Inner>,
+}
+
+struct Inner {
+    writer: Option<Writer>,
+    names: HashSet<String>,
+}
+
+enum Writer {
+    Zip(Box<ZipWriter<File>>),
+    Tar(tar::Builder<File>),
+    TarGz(tar::Builder<GzEncoder<File>>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Zip,
+    Tar,
+    TarGz,
+}
+
+impl Format {
+    fn from_path(path: &Path) -> Option<Self> {
+        let name = path.file_name()?.to_string_lossy().to_ascii_lowercase();
+        if name.ends_with(".zip") {
+            Some(Format::Zip)
+        } else if name.ends_with(".tar") {
+            Some(Format::Tar)
+        } else if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+            Some(Format::TarGz)
+        } else {
+            None
+        }
+    }
+}
+
+impl Archive {
+    pub fn create(path: &Path, overwrite: bool) -> io::Result<Self> {
+        let format = Format::from_path(path).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "unsupported archive format, expected .zip, .tar, .tar.gz or .tgz",
+            )
+        })?;
+
+        let file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .create_new(!overwrite)
+            .open(path)?;
+        output::record_written(path);
+        Ok(Self {
+            path: path.to_path_buf(),
+            inner: Mutex::new(Inner {
+                writer: Some(match format {
+                    Format::Zip => Writer::Zip(Box::new(ZipWriter::new(file))),
+                    Format::Tar => Writer::Tar(tar::Builder::new(file)),
+                    Format::TarGz => Writer::TarGz(tar::Builder::new(GzEncoder::new(
+                        file,
+                        flate2::Compression::default(),
+                    ))),
+                }),
+                names: HashSet::new(),
+            }),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Add a file called `name`, failing if the archive already has one.
+    pub fn add(&self, name: &str, data: &[u8], mtime: Option<SystemTime>) -> io::Result<()> {
+        let mut inner = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        if inner.names.contains(name) {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{:?} is already in the archive", name),
+            ));
+        }
+
+        match inner.writer.as_mut() {
+            Some(Writer::Zip(zip)) => {
+                // mp3s don't compress, so don't bother trying
+                let mut options = SimpleFileOptions::default()
+                    .compression_method(CompressionMethod::Stored)
+                    .large_file(data.len() as u64 >= u64::from(u32::MAX));
+                if let Some(time) = mtime.and_then(zip_time) {
+                    options = options.last_modified_time(time);
+                }
+                zip.start_file(name, options).map_err(io::Error::other)?;
+                zip.write_all(data)?;
+            }
+            Some(Writer::Tar(tar)) => tar.append_data(&mut tar_header(data, mtime), name, data)?,
+            Some(Writer::TarGz(tar)) => {
+                tar.append_data(&mut tar_header(data, mtime), name, data)?
+            }
+            None => return Err(io::Error::other("archive already finished")),
+        }
+        inner.names.insert(name.to_owned());
+        Ok(())
+    }
+
+    /// Write out the archive index; nothing can be added afterwards.
+    pub fn finish(&self) -> io::Result<()> {
+        let mut inner = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        match inner.writer.take() {
+            Some(Writer::Zip(zip)) => {
+                zip.finish().map_err(io::Error::other)?.flush()?;
+            }
+            Some(Writer::Tar(tar)) => tar.into_inner()?.flush()?,
+            Some(Writer::TarGz(tar)) => tar.into_inner()?.finish()?.flush()?,
+            None => {}
+        }
+        Ok(())
+    }
+}
+
+fn tar_header(data: &[u8], mtime: Option<SystemTime>) -> tar::Header {
+    let mut header = tar::Header::new_gnu();
+    header.set_size(data.len() as u64);
+    header.set_mode(0o644);
+    let mtime = mtime
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |d| d.as_secs());
+    header.set_mtime(mtime);
+    header.set_entry_type(tar::EntryType::Regular);
+    header.set_cksum();
+    header
+}
+
+fn zip_time(time: SystemTime) -> Option<zip::DateTime> {
+    let secs = time.duration_since(UNIX_EPOCH).ok()?.as_secs();
+    let (year, month, day) = civil_from_days((secs / 86400) as i64);
+    let secs = secs % 86400;
+    zip::DateTime::from_date_and_time(
+        u16::try_from(year).ok()?,
+        month,
+        day,
+        (secs / 3600) as u8,
+        (secs / 60 % 60) as u8,
+        (secs % 60) as u8,
+    )
+    .ok()
+}
+
+/// Convert days since the unix epoch to a (year, month, day) UTC date.
+// from http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+fn civil_from_days(days: i64) -> (i64, u8, u8) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u8;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u8;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
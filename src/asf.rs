@@ -0,0 +1,297 @@
+//! ASF files, i.e. WMA: a sequence of top-level objects, each starting with
+//! a GUID and its 64-bit little-endian size. A file is a Header object
+//! describing the streams, the Data object with the packets and optionally
+//! index objects.
+
+use std::convert::{TryFrom, TryInto};
+use tracing::trace;
+
+use crate::carve::{Carver, Deadline, Format, ScanOptions, Source, Stopped, Stream};
+
+/// GUIDs as stored, i.e. with their first three fields little-endian.
+type Guid = [u8; 16];
+
+const HEADER_OBJECT: Guid = guid(
+    0x75B22630,
+    0x668E,
+    0x11CF,
+    [0xA6, 0xD9, 0x00, 0xAA, 0x00, 0x62, 0xCE, 0x6C],
+);
+const DATA_OBJECT: Guid = guid(
+    0x75B22636,
+    0x668E,
+    0x11CF,
+    [0xA6, 0xD9, 0x00, 0xAA, 0x00, 0x62, 0xCE, 0x6C],
+);
+const SIMPLE_INDEX_OBJECT: Guid = guid(
+    0x33000890,
+    0xE5B1,
+    0x11CF,
+    [0x89, 0xF4, 0x00, 0xA0, 0xC9, 0x03, 0x49, 0xCB],
+);
+const INDEX_OBJECT: Guid = guid(
+    0xD6E229D3,
+    0x35DA,
+    0x11D1,
+    [0x90, 0x34, 0x00, 0xA0, 0xC9, 0x03, 0x49, 0xBE],
+);
+const FILE_PROPERTIES_OBJECT: Guid = guid(
+    0x8CABDCA1,
+    0xA947,
+    0x11CF,
+    [0x8E, 0xE4, 0x00, 0xC0, 0x0C, 0x20, 0x53, 0x65],
+);
+const STREAM_PROPERTIES_OBJECT: Guid = guid(
+    0xB7DC0791,
+    0xA9B7,
+    0x11CF,
+    [0x8E, 0xE6, 0x00, 0xC0, 0x0C, 0x20, 0x53, 0x65],
+);
+const AUDIO_MEDIA: Guid = guid(
+    0xF8699E40,
+    0x5B4D,
+    0x11CF,
+    [0xA8, 0xFD, 0x00, 0x80, 0x5F, 0x5C, 0x44, 0x2B],
+);
+
+const fn guid(a: u32, b: u16, c: u16, d: [u8; 8]) -> Guid {
+    let a = a.to_le_bytes();
+    let b = b.to_le_bytes();
+    let c = c.to_le_bytes();
+    [
+        a[0], a[1], a[2], a[3], b[0], b[1], c[0], c[1], d[0], d[1], d[2], d[3], d[4], d[5], d[6],
+        d[7],
+    ]
+}
+
+/// Length of an object header: its GUID and size.
+const OBJECT_HEADER_LEN: usize = 24;
+
+/// Length of the Header object's own fields: the object count and two
+/// reserved bytes.
+const HEADER_FIELDS_LEN: usize = 6;
+
+/// Largest Header object taken; it holds the metadata, cover art included,
+/// but not the audio.
+const MAX_HEADER: u64 = 16 << 20;
+
+/// The GUID and size of the object at the start of `bytes`.
+fn parse_object_header(bytes: &[u8]) -> Option<(Guid, u64)> {
+    let guid = bytes.get(..16)?.try_into().ok()?;
+    let size = u64::from_le_bytes(bytes.get(16..24)?.try_into().ok()?);
+    Some((guid, size)).filter(|&(_, size)| size >= OBJECT_HEADER_LEN as u64)
+}
+
+fn le_u32(data: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(data.get(at..at + 4)?.try_into().ok()?))
+}
+
+fn le_u64(data: &[u8], at: usize) -> Option<u64> {
+    Some(u64::from_le_bytes(data.get(at..at + 8)?.try_into().ok()?))
+}
+
+/// What the Header object says about the file.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Info {
+    /// Number of data packets
+    pub packets: u64,
+    /// Playing time, in seconds
+    pub duration: f64,
+    /// Sample rate of the first audio stream
+    pub sample_rate: u32,
+}
+
+/// Parse the objects inside the Header object, `objects`.
+pub fn parse_header_objects(objects: &[u8]) -> Info {
+    let mut info = Info::default();
+    let mut rest = objects;
+    while let Some((guid, size)) = parse_object_header(rest) {
+        let size = (size as usize).min(rest.len());
+        let (object, next) = rest.split_at(size);
+        let data = &object[OBJECT_HEADER_LEN.min(size)..];
+        rest = next;
+        match guid {
+            FILE_PROPERTIES_OBJECT => {
+                info.packets = le_u64(data, 32).unwrap_or(0);
+                // play duration in 100 ns units, including the preroll in ms
+                let duration = le_u64(data, 40).unwrap_or(0) as f64 / 1e7;
+                let preroll = le_u64(data, 56).unwrap_or(0) as f64 / 1e3;
+                info.duration = (duration - preroll).max(0.0);
+            }
+            // the type-specific data of an audio stream is a WAVEFORMATEX
+            STREAM_PROPERTIES_OBJECT
+                if info.sample_rate == 0 && data.get(..16) == Some(&AUDIO_MEDIA[..]) =>
+            {
+                info.sample_rate = le_u32(data, 54 + 4).unwrap_or(0);
+            }
+            _ => {}
+        }
+    }
+    info
+}
+
+/// ASF files, carved object by object: the Header object, the Data object and
+/// any index objects after it.
+pub struct Asf;
+
+impl Carver for Asf {
+    fn starts_with(&self, byte: u8) -> bool {
+        byte == HEADER_OBJECT[0]
+    }
+
+    /// The sizes of objects are trusted, but a file cut short ends where the
+    /// input does and is truncated; `None` without a Data object.
+    fn carve(
+        &self,
+        source: &mut dyn Source,
+        _options: &ScanOptions,
+        _deadline: &mut Deadline,
+    ) -> Result<Option<Stream>, Stopped> {
+        let min_header = (OBJECT_HEADER_LEN + HEADER_FIELDS_LEN) as u64;
+        let header_len = match source.peek(OBJECT_HEADER_LEN).and_then(parse_object_header) {
+            Some((HEADER_OBJECT, size)) if (min_header..=MAX_HEADER).contains(&size) => {
+                size as usize
+            }
+            _ => return Ok(None),
+        };
+        let offset = source.position();
+        let mut data = Vec::new();
+        if source.take_into(header_len, &mut data) < header_len {
+            trace!("{:#x}: ASF file cut short in its header", offset);
+            return Ok(None);
+        }
+        let info = parse_header_objects(&data[OBJECT_HEADER_LEN + HEADER_FIELDS_LEN..]);
+
+        let mut has_data = false;
+        let mut truncated = false;
+        while let Some(object) = source.peek(OBJECT_HEADER_LEN) {
+            let (guid, size) = match parse_object_header(object) {
+                Some(object) => object,
+                None => break,
+            };
+            let expected = match guid {
+                DATA_OBJECT => !has_data,
+                SIMPLE_INDEX_OBJECT | INDEX_OBJECT => has_data,
+                _ => false,
+            };
+            if !expected {
+                break;
+            }
+            has_data = true;
+            let size = usize::try_from(size).unwrap_or(usize::MAX);
+            if source.take_into(size, &mut data) < size {
+                trace!("{:#x}: ASF file cut short", offset);
+                truncated = true;
+                break;
+            }
+        }
+        if !has_data {
+            trace!("{:#x}: rejected ASF file without Data object", offset);
+            return Ok(None);
+        }
+
+        let samples = (info.duration * f64::from(info.sample_rate)) as u64;
+        let bit_rate = match info.duration {
+            d if d > 0.0 => (data.len() as f64 * 8.0 / d) as u32,
+            _ => 0,
+        };
+        Ok(Some(Stream {
+            data,
+            format: Format::Wma,
+            offset,
+            frames: info.packets as usize,
+            samples,
+            sample_rate: info.sample_rate,
+            bit_rate,
+            channel_mode: None,
+            truncated,
+            tags: Vec::new(),
+            gapless: None,
+            corrupt_frames: 0,
+            gaps: Vec::new(),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::carve::extract;
+
+    fn object(guid: Guid, body: &[u8]) -> Vec<u8> {
+        let mut object = guid.to_vec();
+        object.extend_from_slice(&((OBJECT_HEADER_LEN + body.len()) as u64).to_le_bytes());
+        object.extend_from_slice(body);
+        object
+    }
+
+    /// A Header object for 50 packets and 12.5 s of WMA at 44.1 kHz.
+    fn header() -> Vec<u8> {
+        let mut file = vec![0; 32];
+        // packets, play and send duration (with 3 s of preroll), preroll
+        for field in &[50u64, 155_000_000, 0, 3000] {
+            file.extend_from_slice(&field.to_le_bytes());
+        }
+        file.extend_from_slice(&[0; 16]);
+        // WMA v2, stereo, 44.1 kHz
+        let mut format = vec![0x61, 0x01, 2, 0];
+        format.extend_from_slice(&44100u32.to_le_bytes());
+        format.extend_from_slice(&[0; 10]);
+        let mut stream = AUDIO_MEDIA.to_vec();
+        stream.extend_from_slice(&[0; 38]);
+        stream.extend_from_slice(&format);
+
+        let mut body = vec![2, 0, 0, 0, 1, 2];
+        body.extend_from_slice(&object(FILE_PROPERTIES_OBJECT, &file));
+        body.extend_from_slice(&object(STREAM_PROPERTIES_OBJECT, &stream));
+        object(HEADER_OBJECT, &body)
+    }
+
+    fn asf() -> Vec<u8> {
+        let mut packets = vec![0; 26];
+        packets.extend((0..160_000usize).map(|i| (i.wrapping_mul(2_654_435_761) >> 16) as u8));
+        let mut asf = header();
+        asf.extend_from_slice(&object(DATA_OBJECT, &packets));
+        asf.extend_from_slice(&object(SIMPLE_INDEX_OBJECT, &[0x5A; 56]));
+        asf
+    }
+
+    fn carve(input: Vec<u8>) -> Vec<Stream> {
+        let options = ScanOptions {
+            threshold: 0,
+            ..ScanOptions::default()
+        };
+        extract(input, &options).unwrap()
+    }
+
+    #[test]
+    fn file_with_its_index_carved() {
+        let asf = asf();
+        let mut input = vec![0xEE; 3333];
+        input.extend_from_slice(&asf);
+        input.extend_from_slice(&[0xEE; 444]);
+        let streams = carve(input);
+        assert_eq!(streams.len(), 1);
+        let stream = &streams[0];
+        assert_eq!(stream.format, Format::Wma);
+        assert_eq!((stream.offset, stream.data.len()), (3333, asf.len()));
+        assert_eq!((stream.frames, stream.samples), (50, 551_250));
+        assert_eq!(stream.sample_rate, 44100);
+        assert!(!stream.truncated);
+    }
+
+    #[test]
+    fn file_cut_short() {
+        let mut input = vec![0xEE; 3333];
+        input.extend_from_slice(&asf()[..100_000]);
+        let streams = carve(input);
+        assert_eq!(streams.len(), 1);
+        assert_eq!(streams[0].data.len(), 100_000);
+        assert!(streams[0].truncated);
+    }
+
+    #[test]
+    fn header_without_data_rejected() {
+        assert!(carve(header()).is_empty());
+    }
+}
@@ -0,0 +1,158 @@
+//! `amp-extract bench`: throughput of the hot paths, to catch performance
+//! regressions in the scanner.
+
+use std::fs;
+use std::hint::black_box;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+use tracing::error;
+
+use crate::carve::{extract, ScanOptions};
+use crate::deobfs::{self, Scheme, SwapAdjacent};
+use crate::size;
+
+#[derive(clap::Args, Debug)]
+pub struct BenchArgs {
+    /// Amount of synthetic data to run on when no files are given, e.g. `64m`
+    #[arg(long, value_name = "SIZE", default_value = "64m", value_parser = size::parse_size)]
+    size: u64,
+
+    /// How many times to run over the data; the fastest run counts
+    #[arg(short, long, value_name = "N", default_value_t = 3, value_parser = clap::value_parser!(u32).range(1..))]
+    iterations: u32,
+
+    /// Files to use as the corpus instead of synthetic data
+    files: Vec<PathBuf>,
+}
+
+/// Time for one stage over the whole corpus.
+#[derive(Debug, Default, Clone, Copy)]
+struct Timings {
+    read: Duration,
+    deobfs: Duration,
+    scan: Duration,
+}
+
+/// Run the benchmark and return the exit code.
+pub fn run(args: &BenchArgs) -> i32 {
+    let corpus = if args.files.is_empty() {
+        vec![synthetic(args.size as usize)]
+    } else {
+        let mut corpus = Vec::new();
+        for path in &args.files {
+            match fs::read(path) {
+                Ok(data) => corpus.push(data),
+                Err(err) => {
+                    error!("Error reading {:?}: {}", path, err);
+                    return crate::EXIT_FAILURE;
+                }
+            }
+        }
+        corpus
+    };
+    let bytes: usize = corpus.iter().map(Vec::len).sum();
+    if bytes < 2 {
+        error!("Nothing to benchmark");
+        return crate::EXIT_FAILURE;
+    }
+
+    let options = ScanOptions::default();
+    let candidates = Scheme::default().candidates(&[]);
+    let mut best: Option<Timings> = None;
+    for _ in 0..args.iterations {
+        let mut timings = Timings::default();
+
+        if !args.files.is_empty() {
+            let start = Instant::now();
+            for path in &args.files {
+                let _ = black_box(fs::read(path));
+            }
+            timings.read = start.elapsed();
+        }
+
+        for data in &corpus {
+            for deobfuscator in &candidates {
+                let start = Instant::now();
+                let deobfuscated = black_box(deobfs::deobfuscate(&**deobfuscator, data));
+                timings.deobfs += start.elapsed();
+
+                let start = Instant::now();
+                let _ = black_box(extract(deobfuscated, &options));
+                timings.scan += start.elapsed();
+            }
+        }
+
+        best = Some(match best {
+            None => timings,
+            Some(best) => Timings {
+                read: best.read.min(timings.read),
+                deobfs: best.deobfs.min(timings.deobfs),
+                scan: best.scan.min(timings.scan),
+            },
+        });
+    }
+    let best = best.unwrap_or_default();
+
+    let mb = bytes as f64 / f64::from(1 << 20);
+    println!(
+        "{:.1} MiB {}, best of {} run(s)",
+        mb,
+        if args.files.is_empty() {
+            "of synthetic data"
+        } else {
+            "corpus"
+        },
+        args.iterations
+    );
+    // deobfuscation and scanning run once per candidate
+    let passes = candidates.len() as f64;
+    let stages = [
+        ("read", best.read, 1.0),
+        ("deobfuscate", best.deobfs, passes),
+        ("scan", best.scan, passes),
+    ];
+    for (name, time, passes) in stages {
+        if time.is_zero() {
+            continue;
+        }
+        println!(
+            "{:>12}: {:>9.1} MiB/s",
+            name,
+            mb * passes / time.as_secs_f64()
+        );
+    }
+    crate::EXIT_SUCCESS
+}
+
+/// Obfuscated-looking data with a few MP3 streams mixed into noise.
+fn synthetic(size: usize) -> Vec<u8> {
+    // xorshift, so runs are comparable without pulling in a RNG crate
+    let mut state: u64 = 0x2545_f491_4f6c_dd1d;
+    let mut next = move || {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        state
+    };
+
+    // 128 kbps, 44.1 kHz, no padding: 417 byte frames
+    const HEADER: [u8; 4] = [0xFF, 0xFB, 0x90, 0x64];
+    const FRAME_LEN: usize = 417;
+    let mut data = Vec::with_capacity(size + FRAME_LEN);
+    while data.len() < size {
+        let noise = (next() % (256 << 10)) as usize;
+        data.extend((0..noise).map(|_| next() as u8));
+        let frames = (next() % 2000) as usize;
+        for _ in 0..frames {
+            data.extend_from_slice(&HEADER);
+            data.extend((0..FRAME_LEN - 4).map(|_| next() as u8));
+        }
+    }
+    data.truncate(size);
+    let swap = SwapAdjacent {
+        period: 4,
+        offset: 1,
+    };
+    // swapping is its own inverse
+    deobfs::deobfuscate(&swap, &data)
+}
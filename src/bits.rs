@@ -0,0 +1,21 @@
+//! Bit-level reading, for headers that don't keep to byte boundaries.
+
+/// Reads a big-endian bit string.
+pub struct Bits<'a> {
+    pub data: &'a [u8],
+    /// In bits
+    pub pos: usize,
+}
+
+impl Bits<'_> {
+    /// The next `n` bits, up to 32; `None` past the end of the data.
+    pub fn read(&mut self, n: usize) -> Option<u32> {
+        let mut value = 0;
+        for _ in 0..n {
+            let byte = self.data.get(self.pos / 8)?;
+            value = value << 1 | u32::from(byte >> (7 - self.pos % 8) & 1);
+            self.pos += 1;
+        }
+        Some(value)
+    }
+}
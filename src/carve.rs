@@ -0,0 +1,851 @@
+//! The scanner: finds audio streams of every known format in a deobfuscated
+//! input and carves them out.
+//!
+//! Each format has a `Carver`. The scan goes through the input byte by byte
+//! and hands every position a stream of some format could start at to that
+//! format's carver, which takes as much of the input as the stream covers.
+//! Formats made of self-delimiting frames (MP3, ADTS, ...) implement `Framed`
+//! instead and are carved as runs of back-to-back frames by `Frames`.
+
+use std::fmt;
+use std::time::Instant;
+use tracing::trace;
+
+use crate::{adts, aiff, amr, ape, asf, flac, id3, loas, mp3, mp4, ogg, wav};
+
+/// Streams this size or smaller are assumed to be coincidental sync patterns.
+pub const DEFAULT_THRESHOLD: usize = 50 * (1 << 10); // 50 KiB
+
+/// Frames a stream of a framed format has to start with, back to back and
+/// of the same stream, for it to be taken for audio.
+pub const DEFAULT_MIN_FRAMES: usize = 8;
+
+/// What kind of audio a carved stream holds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// MPEG audio, any layer
+    Mp3,
+    /// AAC in ADTS frames
+    Adts,
+    /// AAC in LOAS/LATM frames
+    Loas,
+    /// A whole MP4 container, usually AAC
+    Mp4,
+    Flac,
+    /// An Ogg stream, e.g. Vorbis
+    Ogg,
+    /// An Ogg stream of Opus audio
+    Opus,
+    /// A WAV file, usually PCM
+    Wav,
+    /// An AIFF or AIFF-C file
+    Aiff,
+    /// An ASF file, usually WMA
+    Wma,
+    /// An AMR-NB or AMR-WB file
+    Amr,
+}
+
+impl Format {
+    /// File name extension for streams of this format.
+    pub fn extension(self) -> &'static str {
+        match self {
+            Format::Mp3 => "mp3",
+            Format::Adts => "aac",
+            Format::Loas => "loas",
+            Format::Mp4 => "m4a",
+            Format::Flac => "flac",
+            Format::Ogg => "ogg",
+            Format::Opus => "opus",
+            Format::Wav => "wav",
+            Format::Aiff => "aiff",
+            Format::Wma => "wma",
+            Format::Amr => "amr",
+        }
+    }
+
+    /// Whether tags in front of and after a stream belong to it.
+    fn takes_tags(self) -> bool {
+        matches!(self, Format::Mp3 | Format::Adts)
+    }
+
+    /// Whether streams are runs of frames rather than containers, whose
+    /// structure alone makes them unlikely to be coincidence.
+    fn is_framed(self) -> bool {
+        matches!(self, Format::Mp3 | Format::Adts | Format::Loas)
+    }
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.extension())
+    }
+}
+
+/// A kind of tag carved along with a stream.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Tag {
+    Id3v2,
+    Ape,
+    Id3v1,
+}
+
+impl fmt::Display for Tag {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Tag::Id3v2 => write!(f, "ID3v2"),
+            Tag::Ape => write!(f, "APEv2"),
+            Tag::Id3v1 => write!(f, "ID3v1"),
+        }
+    }
+}
+
+/// How the channels of a stream are coded, where its format says.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChannelMode {
+    Stereo,
+    /// Stereo with the channels coded together, e.g. as mid and side
+    JointStereo,
+    /// Two independent mono channels
+    DualChannel,
+    Mono,
+}
+
+impl ChannelMode {
+    pub fn channels(self) -> u8 {
+        match self {
+            ChannelMode::Mono => 1,
+            _ => 2,
+        }
+    }
+}
+
+impl fmt::Display for ChannelMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ChannelMode::Stereo => write!(f, "stereo"),
+            ChannelMode::JointStereo => write!(f, "joint stereo"),
+            ChannelMode::DualChannel => write!(f, "dual channel"),
+            ChannelMode::Mono => write!(f, "mono"),
+        }
+    }
+}
+
+/// Encoder delay and padding: the samples at the start and the end of a
+/// stream that aren't part of the audio, which players cut for gapless
+/// playback.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Gapless {
+    pub delay: u32,
+    pub padding: u32,
+}
+
+/// An audio stream carved out of a buffer.
+#[derive(Debug, Clone)]
+pub struct Stream {
+    pub data: Vec<u8>,
+    pub format: Format,
+    /// Position of the stream in the scanned buffer, tags included
+    pub offset: usize,
+    pub frames: usize,
+    /// Decoded samples (per channel) over all frames
+    pub samples: u64,
+    /// Sample rate of the first frame, in Hz
+    pub sample_rate: u32,
+    /// Average bitrate over all frames, in bits per second
+    pub bit_rate: u32,
+    /// Channel mode of the first frame, for MPEG audio
+    pub channel_mode: Option<ChannelMode>,
+    /// The stream ends before its format says it should
+    pub truncated: bool,
+    /// Tags carved along with the stream, in the order they are in
+    pub tags: Vec<Tag>,
+    /// Encoder delay and padding, where the stream gives them
+    pub gapless: Option<Gapless>,
+    /// Frames found damaged, by a CRC that doesn't match or a bad header,
+    /// carved as they are
+    pub corrupt_frames: usize,
+    /// Bytes of the input left out of `data` from between its frames, with
+    /// `ScanOptions::clean` or `ScanOptions::repair`, in the order they are in
+    pub gaps: Vec<Gap>,
+}
+
+/// Bytes of the input left out of a stream.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Gap {
+    /// Position in the input
+    pub offset: usize,
+    pub len: usize,
+}
+
+impl Stream {
+    /// Estimated playing time in seconds.
+    pub fn duration(&self) -> f64 {
+        if self.sample_rate == 0 {
+            return 0.0;
+        }
+        self.samples as f64 / f64::from(self.sample_rate)
+    }
+}
+
+/// Settings for `extract`.
+#[derive(Debug, Clone)]
+pub struct ScanOptions {
+    /// Streams this size or smaller are dropped
+    pub threshold: usize,
+    /// Runs of fewer frames than this at the start of a stream of a framed
+    /// format aren't streams at all (a lone frame never is)
+    pub min_frames: usize,
+    /// Give up on the scan after this point in time
+    pub deadline: Option<Instant>,
+    /// Stop with `Stopped::NoFrames` if no run of frames starts in this many
+    /// leading bytes, as a wrong deobfuscation leaves nothing but noise
+    pub abandon_after: Option<usize>,
+    /// Drop junk between the frames of a stream and carry on with the frames
+    /// after it, instead of ending the stream there
+    pub clean: bool,
+    /// Like `clean`, but across longer stretches of damage (up to
+    /// `MAX_GAP`), and damaged frames are left out too instead of being
+    /// carved as they are
+    pub repair: bool,
+}
+
+impl Default for ScanOptions {
+    fn default() -> Self {
+        Self {
+            threshold: DEFAULT_THRESHOLD,
+            min_frames: DEFAULT_MIN_FRAMES,
+            deadline: None,
+            abandon_after: None,
+            clean: false,
+            repair: false,
+        }
+    }
+}
+
+/// Why `extract` stopped before the end of its input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stopped {
+    /// The scan ran past `ScanOptions::deadline`
+    TimedOut,
+    /// Nothing like audio frames within `ScanOptions::abandon_after`
+    NoFrames,
+}
+
+impl fmt::Display for Stopped {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Stopped::TimedOut => write!(f, "timed out"),
+            Stopped::NoFrames => write!(f, "no audio frames near the start"),
+        }
+    }
+}
+
+// how many bytes to scan between deadline checks
+const DEADLINE_CHECK_INTERVAL: usize = 1 << 16;
+
+// how many checks without getting that far before the clock is read anyway,
+// so a scan stuck in one place still times out
+const DEADLINE_CHECK_CALLS: usize = 1 << 12;
+
+// most junk dropped in one place with `ScanOptions::clean`; more than this
+// between frames is taken for the end of the stream
+const MAX_JUNK: usize = 4 << 10;
+
+// most damage skipped in one place with `ScanOptions::repair`, a few
+// corrupted flash blocks' worth
+const MAX_GAP: usize = 64 << 10;
+
+// back-to-back frames that count as a run for `ScanOptions::abandon_after`; a
+// single frame header can appear by chance, runs of three rarely do
+const ABANDON_RUN: usize = 3;
+
+/// `ScanOptions::deadline`, checked every so often as the scan goes on.
+pub struct Deadline {
+    deadline: Option<Instant>,
+    next_check: usize,
+    /// Checks since the clock was last read
+    calls: usize,
+}
+
+impl Deadline {
+    fn new(deadline: Option<Instant>) -> Self {
+        Self {
+            deadline,
+            next_check: DEADLINE_CHECK_INTERVAL,
+            calls: 0,
+        }
+    }
+
+    /// Fail once the deadline has passed; `position` is how far the scan got.
+    pub fn check(&mut self, position: usize) -> Result<(), Stopped> {
+        if let Some(deadline) = self.deadline {
+            self.calls += 1;
+            if position > self.next_check || self.calls >= DEADLINE_CHECK_CALLS {
+                if Instant::now() > deadline {
+                    return Err(Stopped::TimedOut);
+                }
+                self.next_check = position + DEADLINE_CHECK_INTERVAL;
+                self.calls = 0;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The input of a scan as carvers see it: a window of the upcoming bytes
+/// that can be looked at before they are consumed.
+pub trait Source {
+    /// Position of the window in the scanned input.
+    fn position(&self) -> usize;
+
+    /// Up to the next `n` bytes, fewer at the end of the input.
+    fn peek_up_to(&mut self, n: usize) -> &[u8];
+
+    /// Move the next `n` bytes to `out`; fewer at the end of the input.
+    fn take_into(&mut self, n: usize, out: &mut Vec<u8>) -> usize;
+}
+
+impl dyn Source + '_ {
+    /// The next `n` bytes, unless the input ends before.
+    pub fn peek(&mut self, n: usize) -> Option<&[u8]> {
+        let bytes = self.peek_up_to(n);
+        if bytes.len() < n {
+            return None;
+        }
+        Some(bytes)
+    }
+}
+
+/// A view of a source `skip` bytes further on, to look at what follows
+/// before anything is consumed. Nothing can be taken from it.
+struct Ahead<'a> {
+    source: &'a mut dyn Source,
+    skip: usize,
+}
+
+impl Source for Ahead<'_> {
+    fn position(&self) -> usize {
+        self.source.position() + self.skip
+    }
+
+    fn peek_up_to(&mut self, n: usize) -> &[u8] {
+        let bytes = self.source.peek_up_to(self.skip + n);
+        &bytes[self.skip.min(bytes.len())..]
+    }
+
+    fn take_into(&mut self, _n: usize, _out: &mut Vec<u8>) -> usize {
+        0
+    }
+}
+
+/// Finds and carves the streams of one format.
+pub trait Carver: Sync {
+    /// Whether a stream of this format can start with `byte`.
+    fn starts_with(&self, byte: u8) -> bool;
+
+    /// Carve the stream starting at the window of `source`, if one does.
+    /// Whatever is consumed without returning a stream is skipped.
+    fn carve(
+        &self,
+        source: &mut dyn Source,
+        options: &ScanOptions,
+        deadline: &mut Deadline,
+    ) -> Result<Option<Stream>, Stopped>;
+}
+
+/// What the scanner needs to know about a single frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameInfo {
+    /// Length of the whole frame, header included
+    pub length: usize,
+    /// Samples (per channel) the frame decodes to
+    pub samples: u32,
+    pub sample_rate: u32,
+    /// Bitrate of the frame, in bits per second
+    pub bit_rate: u32,
+    /// `None` if the frame header doesn't say
+    pub channel_mode: Option<ChannelMode>,
+    /// The frame is damaged, e.g. its CRC doesn't match its contents
+    pub corrupt: bool,
+}
+
+/// A format made of self-delimiting frames.
+pub trait Framed: Sync {
+    type Frame: Copy;
+
+    fn format(&self) -> Format;
+
+    /// Whether a frame can start with `byte`.
+    fn starts_with(&self, byte: u8) -> bool;
+
+    /// The frame at the start of the window of `source`, if there is one that
+    /// can follow `previous` in the same stream (or start one).
+    fn frame(&self, source: &mut dyn Source, previous: Option<&Self::Frame>)
+        -> Option<Self::Frame>;
+
+    fn info(&self, frame: &Self::Frame) -> FrameInfo;
+
+    /// Whether `frame` keeps to what `previous` says about the stream, e.g.
+    /// its sample rate; the bitrate may change from frame to frame.
+    fn continues(&self, _frame: &Self::Frame, _previous: &Self::Frame) -> bool {
+        true
+    }
+
+    /// Frames that could stand in for a damaged one after `previous`, guessed
+    /// from it and marked corrupt; none by default.
+    fn stand_ins(&self, _previous: &Self::Frame) -> Vec<Self::Frame> {
+        Vec::new()
+    }
+}
+
+/// Carves a `Framed` format as runs of back-to-back frames.
+pub struct Frames<F>(pub F);
+
+impl<F: Framed> Carver for Frames<F> {
+    fn starts_with(&self, byte: u8) -> bool {
+        self.0.starts_with(byte)
+    }
+
+    fn carve(
+        &self,
+        source: &mut dyn Source,
+        options: &ScanOptions,
+        deadline: &mut Deadline,
+    ) -> Result<Option<Stream>, Stopped> {
+        let offset = source.position();
+        let mut data = Vec::new();
+        let mut previous: Option<F::Frame> = None;
+        let (mut frames, mut bits, mut samples, mut sample_rate) = (0, 0, 0, 0);
+        let mut channel_mode = None;
+        let mut corrupt_frames = 0;
+        let mut gaps = Vec::new();
+        let mut truncated = false;
+        let max_gap = match (options.repair, options.clean) {
+            (true, _) => MAX_GAP,
+            (false, true) => MAX_JUNK,
+            (false, false) => 0,
+        };
+        loop {
+            deadline.check(source.position())?;
+            // the frames a stream starts with have to be sound, without
+            // anything patched over
+            let leading = frames < options.min_frames;
+            // a frame of another version, layer or sample rate isn't part of
+            // the stream, if it isn't a damaged header
+            let frame = self
+                .0
+                .frame(source, previous.as_ref())
+                .filter(|frame| previous.is_none_or(|previous| self.0.continues(frame, &previous)));
+            let frame = match frame {
+                Some(frame) => frame,
+                None if leading => break,
+                None => match previous.and_then(|previous| self.stand_in(source, &previous)) {
+                    Some(frame) => frame,
+                    None => match previous
+                        .and_then(|previous| self.resync(source, &previous, max_gap))
+                    {
+                        Some(junk) => {
+                            trace!("{:#x}: dropping {} junk bytes", source.position(), junk);
+                            skip(source, junk, &mut gaps);
+                            continue;
+                        }
+                        None => break,
+                    },
+                },
+            };
+            let info = self.0.info(&frame);
+            if leading && previous.is_some() && info.corrupt {
+                break;
+            }
+            if frames == 0 && info.corrupt {
+                // a stream doesn't start with a damaged frame, chance does
+                return Ok(None);
+            }
+            if options.repair && info.corrupt {
+                trace!("{:#x}: dropping a damaged frame", source.position());
+                if skip(source, info.length, &mut gaps) < info.length {
+                    truncated = true;
+                    break;
+                }
+                continue;
+            }
+            if frames == 0 {
+                // a lone frame header is likely chance, and taking the frame
+                // could swallow the start of a real stream
+                let next = &mut Ahead {
+                    source: &mut *source,
+                    skip: info.length,
+                };
+                match self.0.frame(next, Some(&frame)) {
+                    Some(next) if self.0.continues(&next, &frame) => (),
+                    _ => return Ok(None),
+                }
+            }
+            let before = data.len();
+            if source.take_into(info.length, &mut data) < info.length {
+                // cut short by the end of the input; the whole frames before
+                // still make a stream
+                data.truncate(before);
+                truncated = true;
+                break;
+            }
+            if frames == 0 {
+                sample_rate = info.sample_rate;
+                channel_mode = info.channel_mode;
+            }
+            previous = Some(frame);
+            frames += 1;
+            corrupt_frames += usize::from(info.corrupt);
+            bits += u64::from(info.bit_rate);
+            samples += u64::from(info.samples);
+        }
+        if frames < options.min_frames.max(1) {
+            if frames > 0 {
+                trace!("{:#x}: rejected run of only {} frames", offset, frames);
+            }
+            return Ok(None);
+        }
+        Ok(Some(Stream {
+            data,
+            format: self.0.format(),
+            offset,
+            frames,
+            samples,
+            sample_rate,
+            bit_rate: (bits / frames as u64) as u32,
+            channel_mode,
+            truncated,
+            tags: Vec::new(),
+            gapless: None,
+            corrupt_frames,
+            gaps,
+        }))
+    }
+}
+
+impl<F: Framed> Frames<F> {
+    /// A frame standing in for the damaged one at the start of the window of
+    /// `source`, so a single bad header doesn't end the stream: one whose
+    /// next frame continues the stream. Never two in a row.
+    fn stand_in(&self, source: &mut dyn Source, previous: &F::Frame) -> Option<F::Frame> {
+        if self.0.info(previous).corrupt {
+            return None;
+        }
+        let found = self.0.stand_ins(previous).into_iter().find(|stand_in| {
+            let next = &mut Ahead {
+                source: &mut *source,
+                skip: self.0.info(stand_in).length,
+            };
+            match self.0.frame(next, Some(stand_in)) {
+                Some(frame) => self.0.continues(&frame, stand_in),
+                None => false,
+            }
+        });
+        if found.is_some() {
+            trace!("{:#x}: carving on past a damaged frame", source.position());
+        }
+        found
+    }
+
+    /// How many bytes of junk at the start of the window of `source`, up to
+    /// `max`, to drop for the stream to go on after `previous`: up to the next
+    /// frame that continues the stream and is followed by another.
+    fn resync(&self, source: &mut dyn Source, previous: &F::Frame, max: usize) -> Option<usize> {
+        (1..=max).find(|&skip| {
+            let at = &mut Ahead {
+                source: &mut *source,
+                skip,
+            };
+            let frame = match self.0.frame(at, Some(previous)) {
+                Some(frame) if self.0.continues(&frame, previous) => frame,
+                _ => return false,
+            };
+            let info = self.0.info(&frame);
+            let next = &mut Ahead {
+                source: &mut *source,
+                skip: skip + info.length,
+            };
+            !info.corrupt && self.0.frame(next, Some(&frame)).is_some()
+        })
+    }
+}
+
+/// Consume the next `n` bytes of `source` without carving them, keeping
+/// track of them in `gaps`. Returns how many there were.
+fn skip(source: &mut dyn Source, n: usize, gaps: &mut Vec<Gap>) -> usize {
+    let offset = source.position();
+    let len = source.take_into(n, &mut Vec::new());
+    match gaps.last_mut() {
+        Some(gap) if gap.offset + gap.len == offset => gap.len += len,
+        _ => gaps.push(Gap { offset, len }),
+    }
+    len
+}
+
+/// Every format the scanner looks for. New formats are added here; where
+/// more than one could start at the same byte, the first one wins.
+static CARVERS: &[&dyn Carver] = &[
+    &mp4::Mp4,
+    &flac::Flac,
+    &ogg::Ogg,
+    &wav::Wav,
+    &aiff::Aiff,
+    &asf::Asf,
+    &amr::Amr,
+    &Frames(mp3::Mpeg),
+    &Frames(adts::Adts),
+    &Frames(loas::Loas),
+];
+
+/// The bytes of the wrapped iterator, with a window of the next ones that can
+/// be looked at before they are consumed.
+struct Lookahead<I> {
+    inner: I,
+    /// The window is `buffer[start..]`
+    buffer: Vec<u8>,
+    start: usize,
+    /// Bytes consumed so far, i.e. the position of the window
+    consumed: usize,
+}
+
+// bytes before the window are dropped once there are this many
+const COMPACT_SIZE: usize = 8 << 10;
+
+impl<I: Iterator<Item = u8>> Lookahead<I> {
+    fn new(inner: I) -> Self {
+        Self {
+            inner,
+            buffer: Vec::new(),
+            start: 0,
+            consumed: 0,
+        }
+    }
+
+    /// Drop bytes before the window once there are enough to be worth it.
+    fn compact(&mut self) {
+        if self.start == self.buffer.len() {
+            self.buffer.clear();
+            self.start = 0;
+        } else if self.start >= COMPACT_SIZE {
+            self.buffer.drain(..self.start);
+            self.start = 0;
+        }
+    }
+
+    fn advance(&mut self, n: usize) {
+        let buffered = n.min(self.buffer.len() - self.start);
+        self.start += buffered;
+        self.consumed += buffered;
+        self.compact();
+        for _ in buffered..n {
+            if self.inner.next().is_none() {
+                break;
+            }
+            self.consumed += 1;
+        }
+    }
+}
+
+impl<I: Iterator<Item = u8>> Source for Lookahead<I> {
+    fn position(&self) -> usize {
+        self.consumed
+    }
+
+    fn peek_up_to(&mut self, n: usize) -> &[u8] {
+        while self.buffer.len() - self.start < n {
+            match self.inner.next() {
+                Some(byte) => self.buffer.push(byte),
+                None => break,
+            }
+        }
+        let end = self.buffer.len().min(self.start + n);
+        &self.buffer[self.start..end]
+    }
+
+    fn take_into(&mut self, n: usize, out: &mut Vec<u8>) -> usize {
+        let buffered = n.min(self.buffer.len() - self.start);
+        out.extend_from_slice(&self.buffer[self.start..self.start + buffered]);
+        self.start += buffered;
+        self.compact();
+        let before = out.len();
+        out.extend(self.inner.by_ref().take(n - buffered));
+        let taken = buffered + out.len() - before;
+        self.consumed += taken;
+        taken
+    }
+}
+
+/// Where the frames start in a carved stream, after the tags in front.
+pub fn audio_start(data: &[u8]) -> usize {
+    let mut pos = 0;
+    while let Some(size) = id3::tag_size(&data[pos..]).or_else(|| ape::tag_size(&data[pos..])) {
+        pos += size;
+    }
+    pos
+}
+
+/// Take the tag of kind `tag` at the start of the window to `out`, if
+/// there is one.
+fn take_tag<I: Iterator<Item = u8>>(
+    source: &mut Lookahead<I>,
+    tag: Tag,
+    out: &mut Vec<u8>,
+) -> bool {
+    let size = match tag {
+        Tag::Id3v2 => id3::tag_size(source.peek_up_to(id3::HEADER_SIZE))
+            .filter(|&size| size <= id3::MAX_TAG_SIZE),
+        Tag::Ape => ape::tag_size(source.peek_up_to(ape::HEADER_SIZE)),
+        Tag::Id3v1 => Some(id3::V1_SIZE).filter(|_| id3::is_v1(source.peek_up_to(id3::V1_SIZE))),
+    };
+    match size {
+        Some(size) if source.peek_up_to(size).len() == size => {
+            source.take_into(size, out);
+            true
+        }
+        _ => false,
+    }
+}
+
+/// Carve every audio stream out of `s`.
+///
+/// `s` is consumed front to back exactly once, so it can be an in-memory
+/// buffer as well as a file streamed in chunks.
+pub fn extract(
+    s: impl IntoIterator<Item = u8>,
+    options: &ScanOptions,
+) -> Result<Vec<Stream>, Stopped> {
+    // extract all streams found in data stream
+    // adapted from https://gist.github.com/RavuAlHemio/9376cf495c82be9c8778
+    let source = &mut Lookahead::new(s.into_iter());
+
+    // the carvers to try at each first byte
+    let by_first_byte: Vec<Vec<&dyn Carver>> = (0..=u8::MAX)
+        .map(|byte| {
+            CARVERS
+                .iter()
+                .copied()
+                .filter(|c| c.starts_with(byte))
+                .collect()
+        })
+        .collect();
+
+    // return value
+    let mut extracted: Vec<Stream> = Vec::new();
+
+    let mut deadline = Deadline::new(options.deadline);
+    let mut seen_run = false;
+
+    loop {
+        deadline.check(source.consumed)?;
+
+        if let Some(limit) = options.abandon_after {
+            if !seen_run && source.consumed > limit {
+                return Err(Stopped::NoFrames);
+            }
+        }
+
+        let start = source.consumed;
+        // tags right in front of a stream go with it
+        let mut leading = (Vec::new(), Vec::new());
+        for &tag in &[Tag::Id3v2, Tag::Ape] {
+            if take_tag(source, tag, &mut leading.0) {
+                leading.1.push(tag);
+            }
+        }
+        let byte = match source.peek_up_to(1).first() {
+            Some(&byte) => byte,
+            None => break,
+        };
+        let carve_start = source.consumed;
+        for carver in &by_first_byte[usize::from(byte)] {
+            if let Some(mut stream) = carver.carve(source, options, &mut deadline)? {
+                seen_run |= !stream.format.is_framed() || stream.frames >= ABANDON_RUN;
+                if stream.data.len() > options.threshold {
+                    if stream.format.takes_tags() {
+                        let (data, tags) = std::mem::take(&mut leading);
+                        if !tags.is_empty() {
+                            stream.data.splice(..0, data);
+                            stream.offset = start;
+                            stream.tags = tags;
+                        }
+                        // and so do the ones right after it
+                        for &tag in &[Tag::Ape, Tag::Id3v1] {
+                            if take_tag(source, tag, &mut stream.data) {
+                                stream.tags.push(tag);
+                            }
+                        }
+                    }
+                    extracted.push(stream);
+                }
+            }
+            if source.consumed != carve_start {
+                break;
+            }
+        }
+        if source.consumed == start {
+            source.advance(1);
+        }
+    }
+
+    Ok(extracted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // stream starts, to be cut short: boxes, chunks, pages and frame headers
+    const HEADS: &[&[u8]] = &[
+        b"\0\0\0\x18ftypM4A ",
+        b"fLaC\0\0\0\x22",
+        b"OggS\0\x02",
+        b"RIFF\x24\0\0\0WAVE",
+        b"FORM\0\0\0\x2eAIFF",
+        b"\x30\x26\xb2\x75\x8e\x66\xcf\x11",
+        b"#!AMR\n",
+        b"\xff\xfb\x90\x64",
+        b"\xff\xf1\x50\x80",
+        b"\x56\xe0\x20\x20",
+    ];
+
+    #[test]
+    fn carvers_find_nothing_in_a_few_bytes() {
+        let options = ScanOptions {
+            threshold: 0,
+            min_frames: 1,
+            ..ScanOptions::default()
+        };
+        let mut inputs: Vec<Vec<u8>> = Vec::new();
+        for len in 0..=4 {
+            for head in HEADS {
+                inputs.push(head[..len].to_vec());
+            }
+            for byte in 0..=u8::MAX {
+                for &fill in &[0x00, 0xff] {
+                    let mut input = vec![fill; len];
+                    if let Some(first) = input.first_mut() {
+                        *first = byte;
+                    }
+                    inputs.push(input);
+                }
+            }
+        }
+        for carver in CARVERS {
+            for input in &inputs {
+                let source = &mut Lookahead::new(input.iter().copied());
+                let deadline = &mut Deadline::new(None);
+                let carved = carver.carve(source, &options, deadline);
+                assert!(matches!(carved, Ok(None)), "{:02x?}", input);
+            }
+        }
+    }
+
+    #[test]
+    fn deadline_passes_without_the_scan_moving_on() {
+        let deadline = &mut Deadline::new(Some(Instant::now()));
+        let stopped = (0..DEADLINE_CHECK_CALLS).map(|_| deadline.check(0)).last();
+        assert_eq!(stopped, Some(Err(Stopped::TimedOut)));
+        assert_eq!(Deadline::new(None).check(usize::MAX), Ok(()));
+    }
+}
@@ -0,0 +1,127 @@
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::ffi::{OsStr, OsString};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use crate::input;
+use crate::output;
+
+/// Checksum algorithm asked for with `--checksums`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Algorithm {
+    Sha256,
+}
+
+impl FromStr for Algorithm {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "sha256" => Ok(Algorithm::Sha256),
+            _ => Err(format!("unknown checksum algorithm {:?}", s)),
+        }
+    }
+}
+
+impl Algorithm {
+    pub fn digest(self, data: &[u8]) -> String {
+        match self {
+            Algorithm::Sha256 => hex::encode(Sha256::digest(data)),
+        }
+    }
+
+    /// Name of the file listing the checksums of a whole directory.
+    pub fn sums_file(self) -> &'static str {
+        match self {
+            Algorithm::Sha256 => "SHA256SUMS",
+        }
+    }
+
+    /// Extension of a checksum file covering a single file.
+    pub fn extension(self) -> &'static str {
+        match self {
+            Algorithm::Sha256 => "sha256",
+        }
+    }
+}
+
+/// A line of a checksum file, in the format `sha256sum -c` understands.
+pub fn line(hash: &str, file_name: &OsStr) -> Vec<u8> {
+    let mut line = format!("{}  ", hash).into_bytes();
+    line.extend(input::os_str_to_bytes(file_name));
+    line.push(b'\n');
+    line
+}
+
+/// Where the single-file checksum of `path` goes.
+pub fn sidecar_path(path: &Path, algorithm: Algorithm) -> PathBuf {
+    let mut name = OsString::from(path.as_os_str());
+    name.push(".");
+    name.push(algorithm.extension());
+    PathBuf::from(name)
+}
+
+/// Write a checksum file next to `path`.
+pub fn write_sidecar(path: &Path, hash: &str, algorithm: Algorithm) -> io::Result<()> {
+    let file_name = path.file_name().unwrap_or_default();
+    let sidecar = sidecar_path(path, algorithm);
+    fs::write(&sidecar, line(hash, file_name))?;
+    output::record_written(&sidecar);
+    Ok(())
+}
+
+/// Add `files` to the checksum file of the directory each is in, replacing
+/// any earlier entries for the same names.
+pub fn update_sums<'a>(
+    files: impl IntoIterator<Item = (&'a Path, &'a str)>,
+    algorithm: Algorithm,
+) -> io::Result<()> {
+    let mut dirs: BTreeMap<PathBuf, BTreeMap<OsString, String>> = BTreeMap::new();
+    for (path, hash) in files {
+        let dir = path.parent().unwrap_or_else(|| Path::new("")).to_path_buf();
+        let name = path.file_name().unwrap_or_default();
+        dirs.entry(dir)
+            .or_default()
+            .insert(name.to_owned(), hash.to_owned());
+    }
+
+    for (dir, entries) in dirs {
+        let sums_path = dir.join(algorithm.sums_file());
+        let mut all = match fs::read(&sums_path) {
+            Ok(existing) => parse_sums(&existing),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(err) => return Err(err),
+        };
+        all.extend(entries);
+        fs::write(&sums_path, sums(&all))?;
+        output::record_written(&sums_path);
+    }
+    Ok(())
+}
+
+/// The contents of a checksum file listing `entries` (name to hash).
+pub fn sums(entries: &BTreeMap<OsString, String>) -> Vec<u8> {
+    entries
+        .iter()
+        .flat_map(|(name, hash)| line(hash, name))
+        .collect()
+}
+
+fn parse_sums(data: &[u8]) -> BTreeMap<OsString, String> {
+    data.split(|&b| b == b'\n')
+        .filter_map(|line| {
+            let space = line.iter().position(|&b| b == b' ')?;
+            let hash = std::str::from_utf8(&line[..space]).ok()?;
+            // binary mode entries are marked with `*` instead of a second space
+            let name = line.get(space + 1..)?;
+            let name = name
+                .strip_prefix(b" ")
+                .or_else(|| name.strip_prefix(b"*"))?;
+            let name = input::bytes_to_path(name).ok()?;
+            Some((name.into_os_string(), hash.to_owned()))
+        })
+        .collect()
+}
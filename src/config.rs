@@ -0,0 +1,123 @@
+use crate::naming::Template;
+use crate::size;
+use serde::de::{self, Deserializer};
+use serde::Deserialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Config file looked for in the current directory when `--config` isn't given.
+pub const DEFAULT_PATH: &str = "amp-extract.toml";
+
+/// Defaults loaded from `amp-extract.toml`; anything given on the command line
+/// takes precedence.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    pub output_dir: Option<PathBuf>,
+    #[serde(default, deserialize_with = "size")]
+    pub min_stream_size: Option<u64>,
+    pub min_frames: Option<usize>,
+    pub name: Option<Template>,
+    pub jobs: Option<u16>,
+    /// Like `--include`, which replaces them
+    #[serde(default, deserialize_with = "patterns")]
+    pub include: Vec<glob::Pattern>,
+    /// Like `--exclude`, likewise
+    #[serde(default, deserialize_with = "patterns")]
+    pub exclude: Vec<glob::Pattern>,
+}
+
+/// A size as the command line takes it, e.g. `"50k"`, or a number of bytes.
+fn size<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<u64>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Size {
+        Bytes(u64),
+        Text(String),
+    }
+    match Size::deserialize(deserializer)? {
+        Size::Bytes(n) => Ok(Some(n)),
+        Size::Text(s) => size::parse_size(&s).map(Some).map_err(de::Error::custom),
+    }
+}
+
+fn patterns<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<glob::Pattern>, D::Error> {
+    Vec::<String>::deserialize(deserializer)?
+        .iter()
+        .map(|pattern| glob::Pattern::new(pattern).map_err(de::Error::custom))
+        .collect()
+}
+
+impl Config {
+    /// Load the config file at `path`, or `amp-extract.toml` in the current
+    /// directory if it exists.
+    pub fn load(path: Option<&Path>) -> Result<Self, String> {
+        let path = match path {
+            Some(path) => path,
+            None if Path::new(DEFAULT_PATH).is_file() => Path::new(DEFAULT_PATH),
+            None => return Ok(Self::default()),
+        };
+
+        let contents = fs::read_to_string(path)
+            .map_err(|err| format!("Error reading config {:?}: {}", path, err))?;
+        let config: Self = toml::from_str(&contents)
+            .map_err(|err| format!("Error parsing config {:?}: {}", path, err))?;
+        if config.jobs == Some(0) {
+            return Err(format!(
+                "Error parsing config {:?}: jobs must be at least 1",
+                path
+            ));
+        }
+        Ok(config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(toml: &str) -> Result<Config, String> {
+        toml::from_str(toml).map_err(|err| err.to_string())
+    }
+
+    #[test]
+    fn sizes_as_on_the_command_line() {
+        let config = parse("min_stream_size = \"50k\"").unwrap();
+        assert_eq!(config.min_stream_size, Some(50 << 10));
+        let config = parse("min_stream_size = 51200").unwrap();
+        assert_eq!(config.min_stream_size, Some(51200));
+        assert_eq!(parse("").unwrap().min_stream_size, None);
+        assert!(parse("min_stream_size = \"lots\"").is_err());
+    }
+
+    #[test]
+    fn filters_and_other_defaults() {
+        let config = parse(
+            "output_dir = \"extracted\"\n\
+             name = \"{stem}_{index:02}.mp3\"\n\
+             jobs = 2\n\
+             min_frames = 4\n\
+             include = [\"*.amp\", \"*.dat\"]\n\
+             exclude = [\"*.json\"]\n",
+        )
+        .unwrap();
+        assert_eq!(config.output_dir, Some(PathBuf::from("extracted")));
+        assert!(config.name.is_some());
+        assert_eq!((config.jobs, config.min_frames), (Some(2), Some(4)));
+        assert!(config.include.iter().any(|p| p.matches("cache.amp")));
+        assert!(!config.include.iter().any(|p| p.matches("cache.json")));
+        assert!(config.exclude[0].matches("cache.json"));
+
+        assert!(parse("include = [\"[\"]").is_err());
+        assert!(parse("name = \"{nope}\"").is_err());
+        assert!(parse("threshold = 5").is_err());
+    }
+
+    #[test]
+    fn load_checks_jobs() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        std::io::Write::write_all(&mut file, b"jobs = 0\n").unwrap();
+        let err = Config::load(Some(file.path())).unwrap_err();
+        assert!(err.ends_with("jobs must be at least 1"), "{}", err);
+    }
+}
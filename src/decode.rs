@@ -0,0 +1,222 @@
+//! Decoding carved streams with symphonia, for `--verify` and
+//! `--trim-silence`; the `decode` feature. MPEG audio is decoded a frame at a
+//! time, as the carver found the frames, so what a frame decodes to can be
+//! told from where it is in the stream. Streams of other formats symphonia
+//! reads are decoded packet by packet through its own demuxers.
+
+use std::io::Cursor;
+use symphonia::core::codecs::audio::well_known::{CODEC_ID_MP1, CODEC_ID_MP2, CODEC_ID_MP3};
+use symphonia::core::codecs::audio::{AudioCodecParameters, AudioDecoder, AudioDecoderOptions};
+use symphonia::core::errors::Error;
+use symphonia::core::formats::probe::Hint;
+use symphonia::core::formats::{FormatOptions, TrackType};
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::meta::MetadataOptions;
+use symphonia::core::packet::PacketRef;
+use symphonia::core::units::{Duration, Timestamp};
+use symphonia::default::codecs::MpaDecoder;
+use tracing::trace;
+
+use crate::carve::{audio_start, Format, Stream};
+use crate::mp3::{self, Layer};
+use crate::side_info::Verdict;
+
+/// Decodes the frames of an MPEG audio stream, one at a time and in order:
+/// Layer III frames take part of their audio from the frames before.
+pub struct MpegDecoder {
+    decoder: MpaDecoder,
+    samples: Vec<f32>,
+}
+
+impl MpegDecoder {
+    pub fn new(layer: Layer) -> Self {
+        let codec = match layer {
+            Layer::L1 => CODEC_ID_MP1,
+            Layer::L2 => CODEC_ID_MP2,
+            Layer::L3 => CODEC_ID_MP3,
+        };
+        let mut params = AudioCodecParameters::new();
+        params.for_codec(codec);
+        Self {
+            decoder: MpaDecoder::try_new(&params, &AudioDecoderOptions::default())
+                .expect("the decoder takes every layer"),
+            samples: Vec::new(),
+        }
+    }
+
+    /// Decode `frame`, header and all, to the loudest of its samples (1.0
+    /// being full scale); `None` if it doesn't decode.
+    pub fn peak(&mut self, frame: &[u8]) -> Option<f32> {
+        let packet = PacketRef::new(0, Timestamp::ZERO, Duration::ZERO, frame);
+        let decoded = self.decoder.decode_ref(&packet).ok()?;
+        decoded.copy_to_vec_interleaved(&mut self.samples);
+        Some(self.samples.iter().fold(0.0, |peak, s| s.abs().max(peak)))
+    }
+}
+
+/// Decode `stream`, counting the frames (or packets) that fail to; `None`
+/// for streams that can't be decoded here: free format MP3s, and LOAS, Opus,
+/// WMA and AMR streams.
+pub fn verify(stream: &Stream) -> Option<Verdict> {
+    match stream.format {
+        Format::Mp3 => verify_mpeg(stream),
+        Format::Adts | Format::Mp4 | Format::Flac | Format::Ogg | Format::Wav | Format::Aiff => {
+            verify_packets(stream)
+        }
+        Format::Loas | Format::Opus | Format::Wma | Format::Amr => None,
+    }
+}
+
+/// The frames of an MP3 stream, up to the first header that doesn't parse.
+fn verify_mpeg(stream: &Stream) -> Option<Verdict> {
+    let data = &stream.data;
+    let mut pos = audio_start(data);
+    let mut verdict = Verdict { frames: 0, bad: 0 };
+    let mut decoder = None;
+    while let Some(bytes) = data.get(pos..pos + 4) {
+        let header = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        let frame = match mp3::parse_header(header) {
+            Ok(frame) if frame.length <= data.len() - pos => frame,
+            _ => break,
+        };
+        if frame.is_free_format() {
+            return None;
+        }
+        let decoder = decoder.get_or_insert_with(|| MpegDecoder::new(frame.layer));
+        verdict.frames += 1;
+        if decoder.peak(&data[pos..pos + frame.length]).is_none() {
+            trace!("{:#x}: frame doesn't decode", stream.offset + pos);
+            verdict.bad += 1;
+        }
+        pos += frame.length;
+    }
+    Some(verdict).filter(|verdict| verdict.frames > 0)
+}
+
+/// The packets of the audio track of a container (or ADTS) stream, read by
+/// symphonia's demuxer for the format; a demuxer that fails partway counts
+/// as a bad packet, and the rest of the stream isn't looked at.
+fn verify_packets(stream: &Stream) -> Option<Verdict> {
+    let source =
+        MediaSourceStream::new(Box::new(Cursor::new(&stream.data[..])), Default::default());
+    let mut hint = Hint::new();
+    hint.with_extension(stream.format.extension());
+    let mut format = symphonia::default::get_probe()
+        .probe(
+            &hint,
+            source,
+            FormatOptions::default(),
+            MetadataOptions::default(),
+        )
+        .map_err(|err| trace!("{:#x}: can't be read to decode: {}", stream.offset, err))
+        .ok()?;
+    let track = format.default_track(TrackType::Audio)?;
+    let track_id = track.id;
+    let params = track.codec_params.as_ref()?.audio()?;
+    let mut decoder = symphonia::default::get_codecs()
+        .make_audio_decoder(params, &AudioDecoderOptions::default())
+        .map_err(|err| trace!("{:#x}: no decoder: {}", stream.offset, err))
+        .ok()?;
+
+    let mut verdict = Verdict { frames: 0, bad: 0 };
+    loop {
+        let packet = match format.next_packet() {
+            Ok(Some(packet)) => packet,
+            Ok(None) => break,
+            Err(err) => {
+                trace!("{:#x}: reading packets failed: {}", stream.offset, err);
+                verdict.bad += 1;
+                break;
+            }
+        };
+        if packet.track_id != track_id {
+            continue;
+        }
+        verdict.frames += 1;
+        match decoder.decode(&packet) {
+            Ok(_) => (),
+            Err(Error::DecodeError(err)) => {
+                trace!("{:#x}: packet doesn't decode: {}", stream.offset, err);
+                verdict.bad += 1;
+            }
+            Err(_) => {
+                verdict.bad += 1;
+                break;
+            }
+        }
+    }
+    Some(verdict).filter(|verdict| verdict.frames + verdict.bad > 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::carve::{extract, ScanOptions};
+
+    /// 200 MPEG-1 Layer III frames at 128 kbps and 44.1 kHz, stereo, each
+    /// made of `fill`.
+    fn mp3(fill: impl Fn(usize) -> u8) -> Stream {
+        let mut input = Vec::new();
+        for n in 0..200 {
+            let mut frame: Vec<u8> = (0..417).map(|i| fill(n * 417 + i)).collect();
+            frame[..4].copy_from_slice(&0xFFFB_9000u32.to_be_bytes());
+            input.extend_from_slice(&frame);
+        }
+        let mut streams = extract(input, &ScanOptions::default()).unwrap();
+        assert_eq!(streams.len(), 1);
+        streams.remove(0)
+    }
+
+    #[test]
+    fn silent_frames_decode() {
+        let verdict = verify(&mp3(|_| 0)).unwrap();
+        assert_eq!(
+            verdict,
+            Verdict {
+                frames: 200,
+                bad: 0
+            }
+        );
+    }
+
+    #[test]
+    fn garbage_behind_frame_headers_fails() {
+        let verdict = verify(&mp3(|i| (i.wrapping_mul(2_654_435_761) >> 16) as u8)).unwrap();
+        assert_eq!(verdict.frames, 200);
+        assert!(verdict.fails(), "{:?}", verdict);
+    }
+
+    #[test]
+    fn pcm_in_a_wav_decodes() {
+        let samples: Vec<u8> = (0..22050i16)
+            .flat_map(|n| (n.wrapping_mul(300)).to_le_bytes())
+            .collect();
+        let mut wav = b"RIFF".to_vec();
+        wav.extend_from_slice(&(36 + samples.len() as u32).to_le_bytes());
+        wav.extend_from_slice(b"WAVEfmt ");
+        wav.extend_from_slice(&16u32.to_le_bytes());
+        // PCM, mono, 44.1 kHz, 16 bits
+        for field in &[1u16, 1] {
+            wav.extend_from_slice(&field.to_le_bytes());
+        }
+        wav.extend_from_slice(&44100u32.to_le_bytes());
+        wav.extend_from_slice(&88200u32.to_le_bytes());
+        for field in &[2u16, 16] {
+            wav.extend_from_slice(&field.to_le_bytes());
+        }
+        wav.extend_from_slice(b"data");
+        wav.extend_from_slice(&(samples.len() as u32).to_le_bytes());
+        wav.extend_from_slice(&samples);
+
+        let options = ScanOptions {
+            threshold: 0,
+            ..ScanOptions::default()
+        };
+        let mut streams = extract(wav, &options).unwrap();
+        assert_eq!(streams.len(), 1);
+        assert_eq!(streams[0].format, Format::Wav);
+        let verdict = verify(&streams.remove(0)).unwrap();
+        assert!(verdict.frames > 0);
+        assert_eq!(verdict.bad, 0);
+    }
+}
@@ -0,0 +1,229 @@
+//! AES decryption of inputs with a user-supplied key (`--aes-key`), run before
+//! deobfuscation, for the encrypted variant of the cache format.
+
+use aes::{Aes128, Aes192, Aes256};
+use cbc::cipher::block_padding::NoPadding;
+use cbc::cipher::{BlockModeDecrypt, KeyIvInit, StreamCipher};
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+const BLOCK_SIZE: usize = 16;
+
+/// Block cipher mode given with `--aes-mode`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    /// Counter mode, with a 128-bit big-endian counter starting at the IV
+    Ctr,
+    /// Cipher block chaining; PKCS#7 padding is removed if present
+    Cbc,
+}
+
+impl FromStr for Mode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "ctr" => Ok(Mode::Ctr),
+            "cbc" => Ok(Mode::Cbc),
+            _ => Err(format!("unknown AES mode {:?}, expected ctr or cbc", s)),
+        }
+    }
+}
+
+/// How to decrypt every input.
+#[derive(Debug, Clone)]
+pub struct Decryption {
+    mode: Mode,
+    key: Vec<u8>,
+    iv: Vec<u8>,
+}
+
+impl Decryption {
+    /// `iv` defaults to all zeros.
+    pub fn new(mode: Mode, key: Vec<u8>, iv: Option<Vec<u8>>) -> Result<Self, String> {
+        if ![16, 24, 32].contains(&key.len()) {
+            return Err(format!(
+                "AES keys are 16, 24 or 32 bytes, got {}",
+                key.len()
+            ));
+        }
+        let iv = iv.unwrap_or_else(|| vec![0; BLOCK_SIZE]);
+        if iv.len() != BLOCK_SIZE {
+            return Err(format!(
+                "AES IVs are {} bytes, got {}",
+                BLOCK_SIZE,
+                iv.len()
+            ));
+        }
+        Ok(Self { mode, key, iv })
+    }
+
+    /// Decrypt `data` in place.
+    pub fn apply(&self, data: &mut Vec<u8>) -> Result<(), String> {
+        // key and IV lengths were checked in `new`
+        macro_rules! with_cipher {
+            ($f:ident) => {
+                match self.key.len() {
+                    16 => $f!(Aes128),
+                    24 => $f!(Aes192),
+                    _ => $f!(Aes256),
+                }
+            };
+        }
+        macro_rules! ctr {
+            ($cipher:ty) => {
+                ctr::Ctr128BE::<$cipher>::new_from_slices(&self.key, &self.iv)
+                    .map_err(|err| err.to_string())?
+                    .apply_keystream(data)
+            };
+        }
+        macro_rules! cbc {
+            ($cipher:ty) => {
+                cbc::Decryptor::<$cipher>::new_from_slices(&self.key, &self.iv)
+                    .map_err(|err| err.to_string())?
+                    .decrypt_padded::<NoPadding>(data)
+                    .map(|_| ())
+                    .map_err(|err| err.to_string())?
+            };
+        }
+
+        match self.mode {
+            Mode::Ctr => with_cipher!(ctr),
+            Mode::Cbc => {
+                if !data.len().is_multiple_of(BLOCK_SIZE) {
+                    return Err(format!(
+                        "{} bytes is not a whole number of AES blocks, can't be CBC",
+                        data.len()
+                    ));
+                }
+                with_cipher!(cbc);
+                strip_padding(data);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Decryption {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mode = match self.mode {
+            Mode::Ctr => "ctr",
+            Mode::Cbc => "cbc",
+        };
+        write!(f, "aes-{}-{}", self.key.len() * 8, mode)
+    }
+}
+
+/// Remove PKCS#7 padding from the end of `data`, if it has some.
+fn strip_padding(data: &mut Vec<u8>) {
+    let n = match data.last() {
+        Some(&n) => usize::from(n),
+        None => return,
+    };
+    if (1..=BLOCK_SIZE).contains(&n)
+        && n <= data.len()
+        && data[data.len() - n..].iter().all(|&b| usize::from(b) == n)
+    {
+        data.truncate(data.len() - n);
+    }
+}
+
+/// The key in a `--aes-key-file`: either the raw key, or its hex digits
+/// (optionally prefixed with `hex:`).
+pub fn read_key_file(path: &Path) -> Result<Vec<u8>, String> {
+    let data = fs::read(path).map_err(|err| err.to_string())?;
+    if let Ok(text) = std::str::from_utf8(&data) {
+        let text = text.trim();
+        let text = text.strip_prefix("hex:").unwrap_or(text);
+        if let Ok(key) = hex::decode(text) {
+            if !key.is_empty() {
+                return Ok(key);
+            }
+        }
+    }
+    if data.is_empty() {
+        return Err("empty key file".to_owned());
+    }
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the AES-128 examples of NIST SP 800-38A, first block
+    const KEY: &str = "2b7e151628aed2a6abf7158809cf4f3c";
+    const PLAINTEXT: &str = "6bc1bee22e409f96e93d7e117393172a";
+
+    fn decrypt(mode: Mode, iv: &str, ciphertext: &str) -> Result<Vec<u8>, String> {
+        let decryption = Decryption::new(
+            mode,
+            hex::decode(KEY).unwrap(),
+            Some(hex::decode(iv).unwrap()),
+        )?;
+        let mut data = hex::decode(ciphertext).unwrap();
+        decryption.apply(&mut data)?;
+        Ok(data)
+    }
+
+    #[test]
+    fn nist_examples() {
+        let ctr = decrypt(
+            Mode::Ctr,
+            "f0f1f2f3f4f5f6f7f8f9fafbfcfdfeff",
+            "874d6191b620e3261bef6864990db6ce",
+        );
+        assert_eq!(ctr, Ok(hex::decode(PLAINTEXT).unwrap()));
+        let cbc = decrypt(
+            Mode::Cbc,
+            "000102030405060708090a0b0c0d0e0f",
+            "7649abac8119b246cee98e9b12e9197d",
+        );
+        assert_eq!(cbc, Ok(hex::decode(PLAINTEXT).unwrap()));
+    }
+
+    #[test]
+    fn cbc_takes_whole_blocks_only() {
+        let iv = "000102030405060708090a0b0c0d0e0f";
+        assert!(decrypt(Mode::Cbc, iv, "7649abac8119b246cee98e9b12e919").is_err());
+        // CTR decrypts any length
+        let ctr = decrypt(Mode::Ctr, iv, "").unwrap();
+        assert!(ctr.is_empty());
+    }
+
+    #[test]
+    fn padding_is_stripped_only_if_there_is_some() {
+        let mut data = vec![7; 13];
+        data.extend_from_slice(&[3, 3, 3]);
+        strip_padding(&mut data);
+        assert_eq!(data, [7; 13]);
+
+        for unpadded in [vec![1, 2, 3, 0], vec![5; 3], vec![17; 32], vec![]] {
+            let mut data = unpadded.clone();
+            strip_padding(&mut data);
+            assert_eq!(data, unpadded);
+        }
+    }
+
+    #[test]
+    fn key_and_iv_lengths() {
+        assert!(Decryption::new(Mode::Ctr, vec![0; 15], None).is_err());
+        assert!(Decryption::new(Mode::Ctr, vec![0; 24], Some(vec![0; 8])).is_err());
+        let decryption = Decryption::new(Mode::Cbc, vec![0; 32], None).unwrap();
+        assert_eq!(decryption.to_string(), "aes-256-cbc");
+    }
+
+    #[test]
+    fn key_files_in_hex_or_raw() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("key");
+        fs::write(&path, format!("hex:{}\n", KEY)).unwrap();
+        assert_eq!(read_key_file(&path), Ok(hex::decode(KEY).unwrap()));
+        fs::write(&path, [0xFF; 16]).unwrap();
+        assert_eq!(read_key_file(&path), Ok(vec![0xFF; 16]));
+        fs::write(&path, "").unwrap();
+        assert!(read_key_file(&path).is_err());
+    }
+}
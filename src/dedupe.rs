@@ -0,0 +1,203 @@
+//! Weeding out streams carved more than once from one input. The same audio
+//! often comes out of more than one deobfuscation variant, with slightly
+//! different boundaries where the variants' noise happens to look like a
+//! frame or two, and a variant that is wrong for part of the input can still
+//! carve a run of frames out of bytes another variant carved a stream from.
+
+use tracing::debug;
+
+use crate::carve::Stream;
+
+/// Bytes from the middle of the shorter of two streams that are looked for in
+/// the longer one; far too many to match by chance.
+const PROBE_LEN: usize = 4 << 10;
+
+/// Keep the streams `keep` says to keep, returning how many were dropped.
+fn retain(streams: &mut Vec<(usize, Stream)>, keep: Vec<bool>) -> usize {
+    let dropped = keep.iter().filter(|&&keep| !keep).count();
+    let mut keep = keep.into_iter();
+    streams.retain(|_| keep.next().unwrap_or(true));
+    dropped
+}
+
+/// Whether `short` holds the same audio as `long`, which is at least as long:
+/// the middle of it is somewhere in `long`.
+fn same_audio(short: &Stream, long: &Stream) -> bool {
+    if short.format != long.format {
+        return false;
+    }
+    let len = short.data.len().min(PROBE_LEN);
+    let start = (short.data.len() - len) / 2;
+    let probe = &short.data[start..start + len];
+    len > 0 && long.data.windows(len).any(|window| window == probe)
+}
+
+/// Drop every stream of `streams` (by variant) that holds the same audio as a
+/// longer one carved with another variant; of two that are as long, the
+/// first is kept. Returns how many were dropped.
+pub fn drop_duplicates(streams: &mut Vec<(usize, Stream)>) -> usize {
+    let mut keep = vec![true; streams.len()];
+    for i in 0..streams.len() {
+        for j in i + 1..streams.len() {
+            let ((a_variant, a), (b_variant, b)) = (&streams[i], &streams[j]);
+            if !keep[i] || !keep[j] || a_variant == b_variant {
+                continue;
+            }
+            let (short, long, dropped) = if a.data.len() < b.data.len() {
+                (a, b, i)
+            } else {
+                (b, a, j)
+            };
+            if same_audio(short, long) {
+                debug!(
+                    "{:#x}: dropping a copy of the stream at {:#x} from another variant",
+                    short.offset, long.offset
+                );
+                keep[dropped] = false;
+            }
+        }
+    }
+    retain(streams, keep)
+}
+
+/// How sure the scan is of `stream`: its frames that aren't damaged, then
+/// its length.
+fn confidence(stream: &Stream) -> (usize, usize) {
+    (
+        stream.frames.saturating_sub(stream.corrupt_frames),
+        stream.data.len(),
+    )
+}
+
+/// Drop every stream of `streams` (by variant) carved from bytes of the input
+/// a stream carved with another variant also covers, unless the scan is surer
+/// of it than of that one; a stream covering only part of another's bytes
+/// always goes. Returns how many were dropped.
+pub fn drop_overlaps(streams: &mut Vec<(usize, Stream)>) -> usize {
+    // the bytes of the input the stream was carved from, including those
+    // `--clean` or `--repair` left out
+    let range = |stream: &Stream| {
+        let gaps: usize = stream.gaps.iter().map(|gap| gap.len).sum();
+        stream.offset..stream.offset + stream.data.len() + gaps
+    };
+    let mut keep = vec![true; streams.len()];
+    for i in 0..streams.len() {
+        for j in i + 1..streams.len() {
+            let ((a_variant, a), (b_variant, b)) = (&streams[i], &streams[j]);
+            let (a_range, b_range) = (range(a), range(b));
+            let overlap = a_range.start < b_range.end && b_range.start < a_range.end;
+            if !keep[i] || !keep[j] || a_variant == b_variant || !overlap {
+                continue;
+            }
+            let a_in_b = b_range.start <= a_range.start && a_range.end <= b_range.end;
+            let b_in_a = a_range.start <= b_range.start && b_range.end <= a_range.end;
+            let (dropped, kept) = if a_in_b && !b_in_a {
+                ((i, a), b)
+            } else if b_in_a && !a_in_b {
+                ((j, b), a)
+            } else if confidence(a) < confidence(b) {
+                ((i, a), b)
+            } else {
+                ((j, b), a)
+            };
+            debug!(
+                "{:#x}: dropping a stream overlapping the one at {:#x} from another variant",
+                dropped.1.offset, kept.offset
+            );
+            keep[dropped.0] = false;
+        }
+    }
+    retain(streams, keep)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::carve::{Format, Gap};
+
+    fn stream(format: Format, offset: usize, data: Vec<u8>, frames: usize) -> Stream {
+        Stream {
+            data,
+            format,
+            offset,
+            frames,
+            samples: 0,
+            sample_rate: 0,
+            bit_rate: 0,
+            channel_mode: None,
+            truncated: false,
+            tags: Vec::new(),
+            gapless: None,
+            corrupt_frames: 0,
+            gaps: Vec::new(),
+        }
+    }
+
+    /// Bytes that don't repeat, unlike frames of silence.
+    fn noise(len: usize) -> Vec<u8> {
+        let mut state = 1u32;
+        (0..len)
+            .map(|_| {
+                state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                (state >> 16) as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn copies_from_other_variants_are_dropped() {
+        let audio = noise(20_000);
+        let mut streams = vec![
+            (0, stream(Format::Mp3, 0, audio.clone(), 40)),
+            (
+                1,
+                stream(Format::Mp3, 2000, audio[2000..15_000].to_vec(), 30),
+            ),
+            (0, stream(Format::Mp3, 3000, audio[3000..9000].to_vec(), 10)),
+            (
+                2,
+                stream(Format::Adts, 4000, audio[4000..8000].to_vec(), 10),
+            ),
+            (
+                3,
+                stream(Format::Mp3, 0, noise(30_000)[20_000..].to_vec(), 20),
+            ),
+        ];
+        assert_eq!(drop_duplicates(&mut streams), 1);
+        let left: Vec<_> = streams.iter().map(|(variant, _)| *variant).collect();
+        assert_eq!(left, [0, 0, 2, 3]);
+    }
+
+    #[test]
+    fn overlaps_go_to_the_surer_stream() {
+        let mut streams = vec![
+            // inside the first, so it goes however many frames it has
+            (1, stream(Format::Mp3, 1500, vec![0; 1000], 50)),
+            (0, stream(Format::Mp3, 1000, vec![0; 2000], 10)),
+            // partly over the first, with more sound frames
+            (2, stream(Format::Mp3, 2500, vec![0; 2500], 20)),
+            // inside the last, but of the same variant
+            (2, stream(Format::Mp3, 4000, vec![0; 100], 1)),
+        ];
+        assert_eq!(drop_overlaps(&mut streams), 2);
+        let left: Vec<_> = streams.iter().map(|(_, s)| s.offset).collect();
+        assert_eq!(left, [2500, 4000]);
+    }
+
+    #[test]
+    fn overlaps_count_the_bytes_left_out() {
+        let mut cleaned = stream(Format::Mp3, 0, vec![0; 1000], 100);
+        cleaned.gaps.push(Gap {
+            offset: 500,
+            len: 2000,
+        });
+        let mut streams = vec![
+            (0, cleaned),
+            (1, stream(Format::Mp3, 2800, vec![0; 100], 10)),
+            (2, stream(Format::Mp3, 3000, vec![0; 100], 10)),
+        ];
+        assert_eq!(drop_overlaps(&mut streams), 1);
+        let left: Vec<_> = streams.iter().map(|(_, s)| s.offset).collect();
+        assert_eq!(left, [0, 3000]);
+    }
+}
@@ -0,0 +1,995 @@
+//! Obfuscation schemes and how to undo them.
+//!
+//! A scheme (picked with `--scheme`) comes with one or more candidate
+//! `Deobfuscator`s, e.g. every possible offset of the byte swap; by default
+//! they are all tried and the ones that turn out to produce audio are used.
+
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read};
+use std::ops::Range;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::mpsc::Receiver;
+use std::sync::Arc;
+
+/// A reversible transform that an app version uses to hide audio.
+///
+/// Transforms work on independent blocks of `block_size` bytes, the first of
+/// which starts at `phase` (bytes before it form a short block of their own),
+/// so that inputs can be deobfuscated piecewise.
+pub trait Deobfuscator: fmt::Display + Send + Sync {
+    fn block_size(&self) -> usize {
+        1
+    }
+
+    fn phase(&self) -> usize {
+        0
+    }
+
+    /// Deobfuscate `data` in place. `pos` is where `data` starts in the input;
+    /// both ends of `data` are on block boundaries (or the ends of the input).
+    fn apply(&self, data: &mut [u8], pos: usize);
+
+    /// The transform the app applied, for `--obfuscate`, if it is known.
+    fn inverse(&self) -> Option<Box<dyn Deobfuscator>>;
+}
+
+// deobfuscators shared by every input, e.g. plugins
+impl<T: Deobfuscator + ?Sized> Deobfuscator for std::sync::Arc<T> {
+    fn block_size(&self) -> usize {
+        (**self).block_size()
+    }
+
+    fn phase(&self) -> usize {
+        (**self).phase()
+    }
+
+    fn apply(&self, data: &mut [u8], pos: usize) {
+        (**self).apply(data, pos)
+    }
+
+    fn inverse(&self) -> Option<Box<dyn Deobfuscator>> {
+        (**self).inverse()
+    }
+}
+
+/// Adjacent bytes swapped at every position `offset` modulo `period`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SwapAdjacent {
+    pub period: usize,
+    pub offset: usize,
+}
+
+impl fmt::Display for SwapAdjacent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "swap(period {}, offset {})", self.period, self.offset)
+    }
+}
+
+impl Deobfuscator for SwapAdjacent {
+    fn block_size(&self) -> usize {
+        self.period
+    }
+
+    fn phase(&self) -> usize {
+        self.offset
+    }
+
+    fn apply(&self, data: &mut [u8], pos: usize) {
+        // first swapped pair at or after pos
+        let mut i = (self.offset + self.period - pos % self.period) % self.period;
+        while i + 1 < data.len() {
+            data.swap(i, i + 1);
+            i += self.period;
+        }
+    }
+
+    // swapping twice changes nothing
+    fn inverse(&self) -> Option<Box<dyn Deobfuscator>> {
+        Some(Box::new(*self))
+    }
+}
+
+/// Every byte XORed with a repeating key; an empty key leaves them as they
+/// are.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Xor {
+    pub key: Vec<u8>,
+}
+
+impl fmt::Display for Xor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "xor(key {})", hex::encode(&self.key))
+    }
+}
+
+impl Deobfuscator for Xor {
+    fn apply(&self, data: &mut [u8], pos: usize) {
+        let len = self.key.len();
+        if len == 0 {
+            return;
+        }
+        for (i, byte) in data.iter_mut().enumerate() {
+            *byte ^= self.key[(pos + i) % len];
+        }
+    }
+
+    fn inverse(&self) -> Option<Box<dyn Deobfuscator>> {
+        Some(Box::new(self.clone()))
+    }
+}
+
+/// Not obfuscated at all, for `--no-deobfs`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Plain;
+
+impl fmt::Display for Plain {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("none")
+    }
+}
+
+impl Deobfuscator for Plain {
+    fn apply(&self, _data: &mut [u8], _pos: usize) {}
+
+    fn inverse(&self) -> Option<Box<dyn Deobfuscator>> {
+        Some(Box::new(Plain))
+    }
+}
+
+/// Bits of every byte rotated left by `bits`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RotateBits {
+    pub bits: u32,
+}
+
+impl fmt::Display for RotateBits {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "rotate(bits {})", self.bits)
+    }
+}
+
+impl Deobfuscator for RotateBits {
+    fn apply(&self, data: &mut [u8], _pos: usize) {
+        for byte in data {
+            *byte = byte.rotate_right(self.bits);
+        }
+    }
+
+    fn inverse(&self) -> Option<Box<dyn Deobfuscator>> {
+        Some(Box::new(RotateBits {
+            bits: 8 - self.bits,
+        }))
+    }
+}
+
+/// Every transform `--bruteforce` tries: byte swaps with periods up to 16 at
+/// every offset, every single byte XOR key, every bit rotation, and nothing
+/// at all.
+pub fn bruteforce_candidates() -> Vec<Box<dyn Deobfuscator>> {
+    let mut candidates: Vec<Box<dyn Deobfuscator>> = Vec::new();
+    for period in 2..=16 {
+        for offset in 0..period {
+            candidates.push(Box::new(SwapAdjacent { period, offset }));
+        }
+    }
+    for byte in 1..=u8::MAX {
+        candidates.push(Box::new(Xor { key: vec![byte] }));
+    }
+    for bits in 1..8 {
+        candidates.push(Box::new(RotateBits { bits }));
+    }
+    candidates.push(Box::new(Plain));
+    candidates
+}
+
+/// Longest XOR key `derive_xor_keys` looks for.
+const MAX_DERIVED_KEY: usize = 16;
+
+/// Guess XOR keys from known plaintext at the start of an input (`head`):
+/// an ID3v2 header right at the start, long runs of zeros (tag padding,
+/// digital silence) that show the key itself, and, for single byte keys,
+/// MP3 frames. Shorter keys come first.
+pub fn derive_xor_keys(head: &[u8]) -> Vec<Vec<u8>> {
+    let mut keys: Vec<Vec<u8>> = Vec::new();
+    let mut add = |key: Vec<u8>| {
+        // a key repeating itself is found again as the shorter key
+        let len = key.len();
+        let minimal =
+            (1..=len).find(|&p| len.is_multiple_of(p) && (p..len).all(|i| key[i] == key[i - p]));
+        let key = key[..minimal.unwrap_or(len)].to_vec();
+        if !keys.contains(&key) {
+            keys.push(key);
+        }
+    };
+
+    // "ID3", major version 2 to 4, revision 0
+    for version in 2..=4 {
+        let plain = [b'I', b'D', b'3', version, 0];
+        if head.len() < plain.len() {
+            break;
+        }
+        // (a key as long as the known plaintext would always fit)
+        for len in 1..plain.len() {
+            let key: Vec<u8> = (0..len).map(|i| head[i] ^ plain[i]).collect();
+            if (len..plain.len()).all(|i| head[i] ^ key[i % len] == plain[i]) {
+                add(key);
+                break;
+            }
+        }
+    }
+
+    for len in 1..=MAX_DERIVED_KEY {
+        let wanted = (4 * len).max(32);
+        let mut run = 0;
+        for i in len..head.len() {
+            run = if head[i] == head[i - len] { run + 1 } else { 0 };
+            if run >= wanted {
+                // key[j] lines up with positions j modulo len
+                let start = i + 1 - len;
+                let mut key = vec![0; len];
+                for (j, &b) in head[start..=i].iter().enumerate() {
+                    key[(start + j) % len] = b;
+                }
+                add(key);
+                break;
+            }
+        }
+    }
+
+    // few enough single byte keys to just try them all
+    for byte in 0..=u8::MAX {
+        let plain: Vec<u8> = head.iter().map(|b| b ^ byte).collect();
+        if crate::mp3::count_frame_runs(&plain, SYNC_RUN) > 0 {
+            add(vec![byte]);
+        }
+    }
+
+    keys.sort_by_key(Vec::len);
+    keys
+}
+
+/// Back-to-back frames that make a single byte key worth trying.
+const SYNC_RUN: usize = 4;
+
+/// A scheme selectable by name with `--scheme` and listed by the `schemes`
+/// command. Both the `--scheme` parser and `auto` go through `REGISTRY`, so a
+/// new scheme is added there and nowhere else.
+pub struct Registered {
+    pub name: &'static str,
+    pub build: Build,
+    pub description: &'static str,
+}
+
+/// How a registered scheme is made from the `<option>=<value>` pairs given
+/// after its name.
+pub enum Build {
+    /// Built by `build` from the options, which are named in `options` for
+    /// the `schemes` command
+    Options {
+        options: &'static str,
+        build: BuildFn,
+    },
+    /// Another name for a scheme in `--scheme` syntax; options given with
+    /// the name add to the ones it stands for
+    Alias(&'static str),
+}
+
+/// Makes a scheme, given its name and options.
+pub type BuildFn = fn(&str, &[(&str, &str)]) -> Result<Scheme, String>;
+
+pub const REGISTRY: &[Registered] = &[
+    Registered {
+        name: "v1-swap4",
+        build: Build::Alias("swap:period=4"),
+        description: "adjacent bytes swapped in groups of 4, as in the classic app cache files",
+    },
+    Registered {
+        name: "v2-xor",
+        build: Build::Alias("xor"),
+        description: "repeating XOR key, guessed from each input unless given",
+    },
+    Registered {
+        name: "swap",
+        build: Build::Options {
+            options: "period, offset",
+            build: build_swap,
+        },
+        description: "adjacent bytes swapped in groups of the period, 4 unless given",
+    },
+    Registered {
+        name: "xor",
+        build: Build::Options {
+            options: "key",
+            build: build_xor,
+        },
+        description: "repeating XOR key, guessed from each input unless given",
+    },
+    Registered {
+        name: "rol",
+        build: Build::Options {
+            options: "bits",
+            build: build_rotate,
+        },
+        description: "bits of every byte rotated left, by any amount unless given",
+    },
+    Registered {
+        name: "ror",
+        build: Build::Options {
+            options: "bits",
+            build: build_rotate,
+        },
+        description: "bits of every byte rotated right, by any amount unless given",
+    },
+    Registered {
+        name: "nibble",
+        build: Build::Options {
+            options: "",
+            build: build_nibble,
+        },
+        description: "the two halves of every byte swapped",
+    },
+    Registered {
+        name: "none",
+        build: Build::Options {
+            options: "",
+            build: build_none,
+        },
+        description: "not obfuscated, scanned as it is",
+    },
+    Registered {
+        name: "auto",
+        build: Build::Options {
+            options: "",
+            build: build_auto,
+        },
+        description: "the candidates of every scheme above",
+    },
+];
+
+/// Schemes known to exist that can't be undone yet, each with what it is;
+/// `--scheme` says so for these instead of calling them unknown.
+pub const UNSUPPORTED: &[(&str, &str)] = &[(
+    "v3-rolling",
+    "the position-dependent rolling transform of recent app versions, not worked out yet",
+)];
+
+/// Obfuscation scheme given with `--scheme`, e.g. `swap`, `swap:offset=1`,
+/// `xor:key=hex:5a`, `rol:bits=3` or `auto`: a name from `REGISTRY` and its
+/// options.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Scheme {
+    /// `SwapAdjacent` with each of the periods; every offset is a candidate
+    /// unless one is given
+    Swap {
+        periods: Vec<usize>,
+        offset: Option<usize>,
+    },
+    /// `Xor` with the key, or keys guessed from each input
+    Xor { key: Option<Vec<u8>> },
+    /// `RotateBits`; every rotation is a candidate unless one is given
+    Rotate { bits: Option<u32> },
+    /// `Plain`: the inputs are scanned as they are
+    None,
+    /// Every other scheme in `REGISTRY`
+    Auto,
+}
+
+impl Default for Scheme {
+    fn default() -> Self {
+        Scheme::Swap {
+            periods: vec![4],
+            offset: None,
+        }
+    }
+}
+
+impl FromStr for Scheme {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, options) = s.split_once(':').unwrap_or((s, ""));
+        match REGISTRY.iter().find(|r| r.name == name) {
+            Some(registered) => registered.scheme(options),
+            None => match UNSUPPORTED.iter().find(|&&(n, _)| n == name) {
+                Some((name, what)) => Err(format!("{} is not supported: {}", name, what)),
+                None => Err(format!("unknown scheme {:?}", name)),
+            },
+        }
+    }
+}
+
+impl Registered {
+    /// This scheme with `options`, as given after its name.
+    pub fn scheme(&self, options: &str) -> Result<Scheme, String> {
+        let build = match self.build {
+            Build::Options { build, .. } => build,
+            Build::Alias(spec) => {
+                return match (options, spec.contains(':')) {
+                    ("", _) => spec.parse(),
+                    (options, true) => format!("{},{}", spec, options).parse(),
+                    (options, false) => format!("{}:{}", spec, options).parse(),
+                }
+            }
+        };
+        let options = options
+            .split(',')
+            .filter(|o| !o.is_empty())
+            .map(|o| {
+                o.split_once('=')
+                    .ok_or_else(|| format!("expected <option>=<value>, got {:?}", o))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        build(self.name, &options)
+    }
+}
+
+fn number(key: &str, value: &str) -> Result<usize, String> {
+    value
+        .parse::<usize>()
+        .map_err(|_| format!("invalid {} {:?}", key, value))
+}
+
+fn build_swap(name: &str, options: &[(&str, &str)]) -> Result<Scheme, String> {
+    let (mut period, mut offset) = (4, None);
+    for &(key, value) in options {
+        match key {
+            "period" => period = number(key, value)?,
+            "offset" => offset = Some(number(key, value)?),
+            _ => return Err(format!("unknown option {:?} for {}", key, name)),
+        }
+    }
+    swap(vec![period], offset)
+}
+
+fn build_xor(name: &str, options: &[(&str, &str)]) -> Result<Scheme, String> {
+    let mut key = None;
+    for &(k, value) in options {
+        match k {
+            "key" => key = Some(value.parse::<Key>()?.0),
+            _ => return Err(format!("unknown option {:?} for {}", k, name)),
+        }
+    }
+    Ok(Scheme::Xor { key })
+}
+
+/// `rol` and `ror` name what the app did, which is undone by rotating the
+/// other way.
+fn build_rotate(name: &str, options: &[(&str, &str)]) -> Result<Scheme, String> {
+    let mut bits = None;
+    for &(key, value) in options {
+        match key {
+            "bits" => match value.parse::<u32>() {
+                Ok(n @ 1..=7) => bits = Some(n),
+                _ => return Err(format!("rotation must be 1 to 7 bits, got {:?}", value)),
+            },
+            _ => return Err(format!("unknown option {:?} for {}", key, name)),
+        }
+    }
+    if name == "ror" {
+        bits = bits.map(|bits| 8 - bits);
+    }
+    Ok(Scheme::Rotate { bits })
+}
+
+/// A scheme that takes no options.
+fn without_options(name: &str, options: &[(&str, &str)], scheme: Scheme) -> Result<Scheme, String> {
+    match options.first() {
+        Some((key, _)) => Err(format!("unknown option {:?} for {}", key, name)),
+        None => Ok(scheme),
+    }
+}
+
+/// Swapped nibbles are bytes rotated by 4 bits.
+fn build_nibble(name: &str, options: &[(&str, &str)]) -> Result<Scheme, String> {
+    without_options(name, options, Scheme::Rotate { bits: Some(4) })
+}
+
+fn build_none(name: &str, options: &[(&str, &str)]) -> Result<Scheme, String> {
+    without_options(name, options, Scheme::None)
+}
+
+fn build_auto(name: &str, options: &[(&str, &str)]) -> Result<Scheme, String> {
+    without_options(name, options, Scheme::Auto)
+}
+
+fn swap(periods: Vec<usize>, offset: Option<usize>) -> Result<Scheme, String> {
+    if periods.iter().any(|&period| period < 2) {
+        return Err("swap period must be at least 2".to_owned());
+    }
+    if offset.is_some_and(|offset| periods.iter().any(|&period| offset >= period)) {
+        return Err("swap offset must be less than the period".to_owned());
+    }
+    Ok(Scheme::Swap { periods, offset })
+}
+
+/// A key given with `--key` (or a scheme's `key` option) as `hex:<digits>`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Key(pub Vec<u8>);
+
+impl FromStr for Key {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let digits = s
+            .strip_prefix("hex:")
+            .ok_or_else(|| format!("expected hex:<digits>, got {:?}", s))?;
+        let key =
+            hex::decode(digits).map_err(|err| format!("invalid key {:?}: {}", digits, err))?;
+        if key.is_empty() {
+            return Err("empty key".to_owned());
+        }
+        Ok(Key(key))
+    }
+}
+
+/// How much of the start of each input `Scheme::candidates` wants to see.
+pub const HEAD_SIZE: usize = 64 << 10;
+
+impl Scheme {
+    /// This scheme using `key`, for schemes that take one.
+    pub fn with_key(self, key: Key) -> Result<Self, String> {
+        match self {
+            Scheme::Xor { .. } => Ok(Scheme::Xor { key: Some(key.0) }),
+            _ => Err("--key only applies to --scheme xor".to_owned()),
+        }
+    }
+
+    /// This scheme with the swap periods from `--swap-period`.
+    pub fn with_swap_periods(self, periods: Vec<usize>) -> Result<Self, String> {
+        match self {
+            Scheme::Swap { offset, .. } => swap(periods, offset),
+            _ => Err("--swap-period only applies to --scheme swap".to_owned()),
+        }
+    }
+
+    /// The deobfuscators to try for this scheme on an input starting with
+    /// `head`. Outputs refer to them by index as their "variant".
+    pub fn candidates(&self, head: &[u8]) -> Vec<Box<dyn Deobfuscator>> {
+        match *self {
+            Scheme::Auto => {
+                let mut candidates: Vec<Box<dyn Deobfuscator>> = Vec::new();
+                let schemes = REGISTRY.iter().filter_map(|r| r.scheme("").ok());
+                for scheme in schemes.filter(|scheme| *scheme != Scheme::Auto) {
+                    // schemes overlap, e.g. `nibble` is one of the rotations
+                    for candidate in scheme.candidates(head) {
+                        let name = candidate.to_string();
+                        if !candidates.iter().any(|c| c.to_string() == name) {
+                            candidates.push(candidate);
+                        }
+                    }
+                }
+                candidates
+            }
+            Scheme::None => vec![Box::new(Plain)],
+            Scheme::Rotate { bits } => match bits {
+                Some(bits) => vec![Box::new(RotateBits { bits })],
+                None => (1..8)
+                    .map(|bits| Box::new(RotateBits { bits }) as Box<dyn Deobfuscator>)
+                    .collect(),
+            },
+            Scheme::Xor { ref key } => match key {
+                Some(key) => vec![Box::new(Xor { key: key.clone() })],
+                None => derive_xor_keys(head)
+                    .into_iter()
+                    .map(|key| Box::new(Xor { key }) as Box<dyn Deobfuscator>)
+                    .collect(),
+            },
+            Scheme::Swap {
+                ref periods,
+                offset,
+            } => periods
+                .iter()
+                .flat_map(|&period| {
+                    let offsets = match offset {
+                        Some(offset) => offset..offset + 1,
+                        None => 0..period,
+                    };
+                    offsets.map(move |offset| {
+                        Box::new(SwapAdjacent { period, offset }) as Box<dyn Deobfuscator>
+                    })
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Deobfuscate a whole buffer.
+pub fn deobfuscate(d: &dyn Deobfuscator, data: &[u8]) -> Vec<u8> {
+    let mut out = data.to_vec();
+    d.apply(&mut out, 0);
+    out
+}
+
+/// Widen `range` of an input of `len` bytes to block boundaries of `d`.
+pub fn align(d: &dyn Deobfuscator, range: Range<usize>, len: usize) -> Range<usize> {
+    let size = d.block_size();
+    let phase = d.phase() % size;
+    let start = range
+        .start
+        .saturating_sub((range.start % size + size - phase) % size);
+    let end = (range.end + (phase + size - range.end % size) % size).min(len);
+    start..end
+}
+
+/// `range` of the input as `deobfuscate` would return it, without
+/// deobfuscating (or, with `streamed`, reading) all of it.
+pub fn deobfuscate_range(
+    d: &dyn Deobfuscator,
+    buffer: &[u8],
+    streamed: Option<&Path>,
+    range: Range<usize>,
+) -> io::Result<Vec<u8>> {
+    if range.is_empty() {
+        return Ok(Vec::new());
+    }
+    let len = match streamed {
+        Some(path) => std::fs::metadata(path)?.len() as usize,
+        None => buffer.len(),
+    };
+    let range = range.start.min(len)..range.end.min(len);
+    let aligned = align(d, range.clone(), len);
+    let mut data = match streamed {
+        Some(path) => crate::stream::read_range(path, aligned.clone())?,
+        None => buffer[aligned.clone()].to_vec(),
+    };
+    d.apply(&mut data, aligned.start);
+    Ok(data[range.start - aligned.start..range.end - aligned.start].to_vec())
+}
+
+/// Inputs are read this much at a time when they aren't all in memory, or
+/// when they are scanned in a single pass.
+pub const CHUNK_SIZE: usize = 1 << 20; // 1 MiB
+
+/// Small enough to stay in cache while it is scanned.
+const SLICE_CHUNK_SIZE: usize = 64 << 10;
+
+/// The deobfuscated bytes of an input in memory, deobfuscated a chunk at a
+/// time as they are read instead of copying the whole input up front.
+pub struct DeobfuscatedSlice<'a> {
+    data: &'a [u8],
+    deobfuscator: &'a dyn Deobfuscator,
+    chunk: Vec<u8>,
+    /// Position of `chunk` in `data`
+    start: usize,
+    pos: usize,
+}
+
+impl<'a> DeobfuscatedSlice<'a> {
+    pub fn new(data: &'a [u8], deobfuscator: &'a dyn Deobfuscator) -> Self {
+        Self {
+            data,
+            deobfuscator,
+            chunk: Vec::with_capacity(SLICE_CHUNK_SIZE),
+            start: 0,
+            pos: 0,
+        }
+    }
+
+    fn refill(&mut self) -> bool {
+        self.start += self.chunk.len();
+        self.pos = 0;
+        self.chunk.clear();
+        if self.start >= self.data.len() {
+            return false;
+        }
+        let next = self.start + SLICE_CHUNK_SIZE.max(self.deobfuscator.block_size());
+        let boundary = align(self.deobfuscator, next..next, usize::MAX).start;
+        let end = if boundary > self.start {
+            boundary
+        } else {
+            next
+        };
+        let end = end.min(self.data.len());
+        self.chunk.extend_from_slice(&self.data[self.start..end]);
+        self.deobfuscator.apply(&mut self.chunk, self.start);
+        true
+    }
+}
+
+impl Iterator for DeobfuscatedSlice<'_> {
+    type Item = u8;
+
+    fn next(&mut self) -> Option<u8> {
+        if self.pos == self.chunk.len() && !self.refill() {
+            return None;
+        }
+        let byte = self.chunk[self.pos];
+        self.pos += 1;
+        Some(byte)
+    }
+}
+
+/// The deobfuscated bytes of an input handed over a chunk at a time through
+/// a channel, by a reader feeding one of these per deobfuscator so the input
+/// is only read once. The input ends when the sender is dropped.
+pub struct DeobfuscatedChunks<'a> {
+    chunks: Receiver<Arc<Vec<u8>>>,
+    deobfuscator: &'a dyn Deobfuscator,
+    chunk: Vec<u8>,
+    /// Position of `chunk` in the input
+    start: usize,
+    pos: usize,
+    /// Received past the last block boundary in `chunk`, for the next one
+    tail: Vec<u8>,
+}
+
+impl<'a> DeobfuscatedChunks<'a> {
+    pub fn new(chunks: Receiver<Arc<Vec<u8>>>, deobfuscator: &'a dyn Deobfuscator) -> Self {
+        Self {
+            chunks,
+            deobfuscator,
+            chunk: Vec::new(),
+            start: 0,
+            pos: 0,
+            tail: Vec::new(),
+        }
+    }
+
+    fn refill(&mut self) -> bool {
+        self.start += self.chunk.len();
+        self.chunk.clear();
+        self.chunk.append(&mut self.tail);
+        self.pos = 0;
+
+        // at the end of the input, what is left is its last block
+        while let Ok(received) = self.chunks.recv() {
+            self.chunk.extend_from_slice(&received);
+            let end = self.start + self.chunk.len();
+            let boundary = align(self.deobfuscator, end..end, usize::MAX).start;
+            if boundary > self.start {
+                self.tail = self.chunk.split_off(boundary - self.start);
+                break;
+            }
+        }
+        if self.chunk.is_empty() {
+            return false;
+        }
+        self.deobfuscator.apply(&mut self.chunk, self.start);
+        true
+    }
+}
+
+impl Iterator for DeobfuscatedChunks<'_> {
+    type Item = u8;
+
+    fn next(&mut self) -> Option<u8> {
+        if self.pos == self.chunk.len() && !self.refill() {
+            return None;
+        }
+        let byte = self.chunk[self.pos];
+        self.pos += 1;
+        Some(byte)
+    }
+}
+
+/// The deobfuscated bytes of a file, read one chunk at a time.
+///
+/// Iteration stops at the first read error, which is kept for `take_error`.
+pub struct DeobfuscatedFile<'a> {
+    file: File,
+    deobfuscator: &'a dyn Deobfuscator,
+    chunk: Vec<u8>,
+    /// Position of `chunk` in the file
+    start: usize,
+    pos: usize,
+    /// Read past the last block boundary in `chunk`, for the next one
+    tail: Vec<u8>,
+    eof: bool,
+    error: Option<io::Error>,
+}
+
+impl<'a> DeobfuscatedFile<'a> {
+    pub fn open(path: &Path, deobfuscator: &'a dyn Deobfuscator) -> io::Result<Self> {
+        Ok(Self {
+            file: File::open(path)?,
+            deobfuscator,
+            chunk: Vec::new(),
+            start: 0,
+            pos: 0,
+            tail: Vec::new(),
+            eof: false,
+            error: None,
+        })
+    }
+
+    pub fn take_error(&mut self) -> Option<io::Error> {
+        self.error.take()
+    }
+
+    fn refill(&mut self) -> bool {
+        self.start += self.chunk.len();
+        self.chunk.clear();
+        self.chunk.append(&mut self.tail);
+        self.pos = 0;
+
+        while !self.eof {
+            let file = &mut self.file;
+            match file.take(CHUNK_SIZE as u64).read_to_end(&mut self.chunk) {
+                Ok(n) => self.eof = n < CHUNK_SIZE,
+                Err(err) => {
+                    self.error = Some(err);
+                    return false;
+                }
+            }
+            if self.eof {
+                break;
+            }
+            let end = self.start + self.chunk.len();
+            let boundary = align(self.deobfuscator, end..end, usize::MAX).start;
+            if boundary > self.start {
+                self.tail = self.chunk.split_off(boundary - self.start);
+                break;
+            }
+        }
+
+        self.deobfuscator.apply(&mut self.chunk, self.start);
+        !self.chunk.is_empty()
+    }
+}
+
+impl Read for DeobfuscatedFile<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos == self.chunk.len() && (self.error.is_some() || !self.refill()) {
+            return self.take_error().map_or(Ok(0), Err);
+        }
+        let n = buf.len().min(self.chunk.len() - self.pos);
+        buf[..n].copy_from_slice(&self.chunk[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+impl Iterator for DeobfuscatedFile<'_> {
+    type Item = u8;
+
+    fn next(&mut self) -> Option<u8> {
+        if self.pos == self.chunk.len() && (self.error.is_some() || !self.refill()) {
+            return None;
+        }
+        let byte = self.chunk[self.pos];
+        self.pos += 1;
+        Some(byte)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use std::sync::mpsc::sync_channel;
+
+    // every length of a degenerate input
+    const TINY: &[&[u8]] = &[
+        b"",
+        b"\x01",
+        b"\x01\x02",
+        b"\x01\x02\x03",
+        b"\x01\x02\x03\x04",
+    ];
+
+    #[test]
+    fn xor_with_empty_key_leaves_data_alone() {
+        let xor = Xor { key: Vec::new() };
+        let mut data = b"\xff\xfb\x90\x64 some frame".to_vec();
+        for pos in 0..4 {
+            xor.apply(&mut data, pos);
+            assert_eq!(data, b"\xff\xfb\x90\x64 some frame");
+        }
+        xor.apply(&mut [], 0);
+    }
+
+    #[test]
+    fn swap_on_a_few_bytes() {
+        for period in 2..=16 {
+            for offset in 0..period {
+                let swap = SwapAdjacent { period, offset };
+                for input in TINY {
+                    for pos in 0..period {
+                        let mut expected = input.to_vec();
+                        for i in 0..input.len().saturating_sub(1) {
+                            if (pos + i) % period == offset {
+                                expected.swap(i, i + 1);
+                            }
+                        }
+                        let mut data = input.to_vec();
+                        swap.apply(&mut data, pos);
+                        assert_eq!(data, expected, "{} at {}", swap, pos);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn rotate_on_a_few_bytes() {
+        for bits in 0..8 {
+            let rotate = RotateBits { bits };
+            for input in TINY {
+                let mut data = input.to_vec();
+                rotate.apply(&mut data, 0);
+                let expected: Vec<u8> = input.iter().map(|b| b.rotate_right(bits)).collect();
+                assert_eq!(data, expected);
+                rotate.inverse().unwrap().apply(&mut data, 0);
+                assert_eq!(&data, input);
+            }
+        }
+    }
+
+    #[test]
+    fn iterators_on_a_few_bytes() {
+        let deobfuscators: Vec<Box<dyn Deobfuscator>> = vec![
+            Box::new(SwapAdjacent {
+                period: 4,
+                offset: 1,
+            }),
+            Box::new(SwapAdjacent {
+                period: 16,
+                offset: 15,
+            }),
+            Box::new(Xor { key: vec![1, 2, 3] }),
+            Box::new(RotateBits { bits: 3 }),
+            Box::new(Plain),
+        ];
+        for d in &deobfuscators {
+            for input in TINY {
+                let expected = deobfuscate(&**d, input);
+
+                let slice: Vec<u8> = DeobfuscatedSlice::new(input, &**d).collect();
+                assert_eq!(slice, expected, "{}", d);
+
+                // a byte at a time, the way blocks are least likely to line up
+                let (sender, receiver) = sync_channel(input.len());
+                for &byte in input.iter() {
+                    sender.send(Arc::new(vec![byte])).unwrap();
+                }
+                drop(sender);
+                let chunks: Vec<u8> = DeobfuscatedChunks::new(receiver, &**d).collect();
+                assert_eq!(chunks, expected, "{}", d);
+
+                let mut file = tempfile::NamedTempFile::new().unwrap();
+                file.write_all(input).unwrap();
+                let mut deobfuscated = DeobfuscatedFile::open(file.path(), &**d).unwrap();
+                let read: Vec<u8> = (&mut deobfuscated).collect();
+                assert_eq!(read, expected, "{}", d);
+                assert!(deobfuscated.take_error().is_none());
+            }
+        }
+    }
+
+    #[test]
+    fn every_scheme_round_trips() {
+        // an odd length, to end in a short block
+        let plain: Vec<u8> = (0..=u8::MAX).cycle().take(1001).collect();
+        for registered in REGISTRY {
+            let scheme = match registered.scheme("").unwrap() {
+                // otherwise the keys are guessed from the input
+                xor @ Scheme::Xor { .. } => xor.with_key(Key(vec![0x5A, 0x3C, 0x01])).unwrap(),
+                scheme => scheme,
+            };
+            let candidates = scheme.candidates(&[]);
+            assert!(!candidates.is_empty(), "{}", registered.name);
+            for d in &candidates {
+                let obfuscated = deobfuscate(&*d.inverse().unwrap(), &plain);
+                assert_eq!(
+                    deobfuscate(&**d, &obfuscated),
+                    plain,
+                    "{}: {}",
+                    registered.name,
+                    d
+                );
+                let streamed: Vec<u8> = DeobfuscatedSlice::new(&obfuscated, &**d).collect();
+                assert_eq!(streamed, plain, "{}: {}", registered.name, d);
+            }
+        }
+    }
+
+    #[test]
+    fn unsupported_schemes_say_so() {
+        let err = "v3-rolling".parse::<Scheme>().unwrap_err();
+        assert!(err.starts_with("v3-rolling is not supported"), "{}", err);
+        let err = "v4".parse::<Scheme>().unwrap_err();
+        assert_eq!(err, "unknown scheme \"v4\"");
+    }
+}
@@ -0,0 +1,33 @@
+//! Byte statistics, to tell encrypted inputs from ones that just have no audio.
+
+/// Inputs smaller than this are too small to judge by their statistics.
+const MIN_SAMPLE: usize = 16 << 10;
+
+/// Above this, data is indistinguishable from random. Even MP3 data falls
+/// short of it, as frame headers and side info are quite predictable; a 64 KiB
+/// sample of random data comes out at about 7.997.
+const ENCRYPTED_BITS: f64 = 7.99;
+
+/// Shannon entropy of `data` in bits per byte, from 0 (a single repeated
+/// byte) to 8.
+pub fn bits_per_byte(data: &[u8]) -> f64 {
+    let mut counts = [0usize; 256];
+    for &b in data {
+        counts[usize::from(b)] += 1;
+    }
+    let len = data.len() as f64;
+    counts
+        .iter()
+        .filter(|&&n| n > 0)
+        .map(|&n| {
+            let p = n as f64 / len;
+            -p * p.log2()
+        })
+        .sum()
+}
+
+/// Whether `data` looks like the output of a cipher rather than of an
+/// obfuscation scheme, if there's enough of it to tell.
+pub fn looks_encrypted(data: &[u8]) -> bool {
+    data.len() >= MIN_SAMPLE && bits_per_byte(data) > ENCRYPTED_BITS
+}
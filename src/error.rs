@@ -0,0 +1,93 @@
+//! Why an input couldn't be processed, or one of its outputs couldn't be
+//! written. A failure only fails its own input: it is kept in the input's
+//! report and the run goes on with the others.
+
+use serde::{Serialize, Serializer};
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::carve::Stopped;
+
+#[derive(Debug)]
+pub enum ExtractError {
+    /// The input path isn't a regular file
+    NotAFile,
+    /// Reading the input failed
+    Io(io::Error),
+    /// The input couldn't be decrypted with the `--aes-key` given
+    Decrypt(String),
+    /// The scheme can't do what was asked of it, e.g. be reversed for
+    /// `--obfuscate`
+    Scheme(String),
+    /// A `--plugin` module couldn't be loaded
+    #[cfg(feature = "plugin")]
+    Plugin(String),
+    /// The scan stopped before the end of the input
+    Scan { stopped: Stopped, after: Duration },
+    /// `--stdout` takes exactly one stream, and this many were found
+    StreamCount(usize),
+    /// The `--name` template doesn't parse
+    Name(String),
+    /// Writing an output failed; `None` for stdout
+    Output {
+        path: Option<PathBuf>,
+        source: io::Error,
+    },
+    /// `--remove-source` or `--move-source` couldn't check the outputs or
+    /// clean up the input
+    CleanUp(io::Error),
+}
+
+impl fmt::Display for ExtractError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExtractError::NotAFile => write!(f, "not a file"),
+            ExtractError::Io(err) => write!(f, "{}", err),
+            ExtractError::Decrypt(err) => write!(f, "can't decrypt: {}", err),
+            ExtractError::Scheme(err) => write!(f, "{}", err),
+            #[cfg(feature = "plugin")]
+            ExtractError::Plugin(err) => write!(f, "can't load plugin: {}", err),
+            ExtractError::Scan { stopped, after } => {
+                write!(f, "gave up after {:.1}s: {}", after.as_secs_f64(), stopped)
+            }
+            ExtractError::StreamCount(n) => write!(f, "found {} streams, expected 1", n),
+            ExtractError::Name(err) => write!(f, "bad --name template: {}", err),
+            ExtractError::Output {
+                path: Some(path),
+                source,
+            } => {
+                write!(f, "writing {:?}: {}", path, source)
+            }
+            ExtractError::Output { path: None, source } => {
+                write!(f, "writing to stdout: {}", source)
+            }
+            ExtractError::CleanUp(err) => write!(f, "cleaning up the input: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for ExtractError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ExtractError::Io(err)
+            | ExtractError::Output { source: err, .. }
+            | ExtractError::CleanUp(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for ExtractError {
+    fn from(err: io::Error) -> Self {
+        ExtractError::Io(err)
+    }
+}
+
+/// As its message, like the rest of a report.
+impl Serialize for ExtractError {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
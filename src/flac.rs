@@ -0,0 +1,439 @@
+//! FLAC streams: the `fLaC` marker, metadata blocks starting with
+//! STREAMINFO, then frames. Frame headers carry a CRC-8 and frames end with a
+//! CRC-16, but nothing says how long a frame is, so the end of each is found
+//! by checking the CRC-16 at every position until a valid next header follows.
+
+use crate::carve::{Carver, Deadline, Format, ScanOptions, Source, Stopped, Stream};
+
+const MARKER: &[u8; 4] = b"fLaC";
+
+/// Length of a metadata block header.
+const BLOCK_HEADER_LEN: usize = 4;
+
+const STREAMINFO: u8 = 0;
+const STREAMINFO_LEN: usize = 34;
+
+// metadata block type 127 is invalid, so a stream can't be confused with
+// the frame sync
+const INVALID_BLOCK: u8 = 127;
+
+/// Longest frame header: sync, codes, a 7 byte coded number, a 16 bit block
+/// size, a 16 bit sample rate and the CRC-8.
+const MAX_HEADER_LEN: usize = 16;
+
+/// What STREAMINFO says about the stream.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StreamInfo {
+    pub max_block_size: u32,
+    /// Largest frame in bytes; 0 if unknown
+    pub max_frame_size: u32,
+    pub sample_rate: u32,
+    pub channels: u32,
+    pub bits_per_sample: u32,
+    /// Samples (per channel) in the stream; 0 if unknown
+    pub total_samples: u64,
+}
+
+impl StreamInfo {
+    /// Longest frame to look for the end of.
+    fn frame_limit(&self) -> usize {
+        match self.max_frame_size {
+            0 => {
+                // a frame stored verbatim, with room for the subframe headers
+                let bytes = self.bits_per_sample.div_ceil(8);
+                (self.max_block_size * self.channels * bytes) as usize + 64
+            }
+            size => size as usize,
+        }
+    }
+}
+
+/// Parse the contents of a STREAMINFO block.
+pub fn parse_stream_info(bytes: &[u8]) -> Option<StreamInfo> {
+    let bytes = bytes.get(..STREAMINFO_LEN)?;
+    let be = |range: std::ops::Range<usize>| {
+        bytes[range]
+            .iter()
+            .fold(0u64, |value, &byte| value << 8 | u64::from(byte))
+    };
+    let min_block_size = be(0..2) as u32;
+    let max_block_size = be(2..4) as u32;
+    let max_frame_size = be(7..10) as u32;
+    // 20 bits of sample rate, 3 of channels minus one, 5 of bits per sample
+    // minus one and 36 of total samples
+    let packed = be(10..18);
+    let sample_rate = (packed >> 44) as u32;
+    let channels = (packed >> 41 & 0x07) as u32 + 1;
+    let bits_per_sample = (packed >> 36 & 0x1F) as u32 + 1;
+    let total_samples = packed & 0x0F_FFFF_FFFF;
+    if min_block_size < 16 || max_block_size < min_block_size || sample_rate == 0 {
+        return None;
+    }
+    Some(StreamInfo {
+        max_block_size,
+        max_frame_size,
+        sample_rate,
+        channels,
+        bits_per_sample,
+        total_samples,
+    })
+}
+
+/// The parts of a frame header that matter for carving.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameHeader {
+    /// Whether `number` counts samples rather than frames
+    pub variable_block_size: bool,
+    /// Number of the frame, or of its first sample
+    pub number: u64,
+    /// Samples (per channel) in the frame
+    pub block_size: u32,
+    /// Length of the header, CRC-8 included
+    pub length: usize,
+}
+
+impl FrameHeader {
+    /// `number` of the frame that follows this one.
+    fn next_number(&self) -> u64 {
+        match self.variable_block_size {
+            true => self.number + u64::from(self.block_size),
+            false => self.number + 1,
+        }
+    }
+}
+
+/// Decode the UTF-8 style coded number at the start of `bytes`, and how many
+/// bytes it takes.
+fn coded_number(bytes: &[u8]) -> Option<(u64, usize)> {
+    let first = *bytes.first()?;
+    let len = match first.leading_ones() {
+        0 => 1,
+        1 => return None,
+        ones @ 2..=7 => ones as usize,
+        _ => return None,
+    };
+    let mut value = match len {
+        1 => u64::from(first),
+        _ => u64::from(first & (0x7F >> len)),
+    };
+    for &byte in bytes.get(1..len)? {
+        if byte & 0xC0 != 0x80 {
+            return None;
+        }
+        value = value << 6 | u64::from(byte & 0x3F);
+    }
+    Some((value, len))
+}
+
+/// Check whether `bytes` start with a frame header of the stream `info`
+/// describes, and parse it.
+pub fn parse_frame_header(bytes: &[u8], info: &StreamInfo) -> Option<FrameHeader> {
+    let fixed = bytes.get(..4)?;
+    // sync, then a reserved bit and the blocking strategy
+    if fixed[0] != 0xFF || fixed[1] & 0xFE != 0xF8 {
+        return None;
+    }
+    let variable_block_size = fixed[1] & 0x01 == 1;
+    let block_size_code = fixed[2] >> 4;
+    let sample_rate_code = fixed[2] & 0x0F;
+    let channels = fixed[3] >> 4;
+    let sample_size_code = (fixed[3] >> 1) & 0x07;
+    if block_size_code == 0
+        || sample_rate_code == 0x0F
+        || channels > 10
+        || sample_size_code == 3
+        || fixed[3] & 0x01 != 0
+    {
+        return None;
+    }
+
+    let (number, number_len) = coded_number(&bytes[4..])?;
+    let mut pos = 4 + number_len;
+    let mut read = |n: usize| {
+        let value = bytes
+            .get(pos..pos + n)?
+            .iter()
+            .fold(0, |v, &b| v << 8 | u32::from(b));
+        pos += n;
+        Some(value)
+    };
+    let block_size = match block_size_code {
+        1 => 192,
+        2..=5 => 576 << (block_size_code - 2),
+        6 => read(1)? + 1,
+        7 => read(2)? + 1,
+        _ => 256 << (block_size_code - 8),
+    };
+    match sample_rate_code {
+        12 => read(1)?,
+        13 | 14 => read(2)?,
+        _ => 0,
+    };
+    if block_size > info.max_block_size {
+        return None;
+    }
+
+    let crc = *bytes.get(pos)?;
+    if crc8(&bytes[..pos]) != crc {
+        return None;
+    }
+    Some(FrameHeader {
+        variable_block_size,
+        number,
+        block_size,
+        length: pos + 1,
+    })
+}
+
+fn crc8(bytes: &[u8]) -> u8 {
+    bytes.iter().fold(0, |mut crc, &byte| {
+        crc ^= byte;
+        for _ in 0..8 {
+            crc = match crc & 0x80 {
+                0 => crc << 1,
+                _ => crc << 1 ^ 0x07,
+            };
+        }
+        crc
+    })
+}
+
+const fn crc16_table() -> [u16; 256] {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = (i as u16) << 8;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = match crc & 0x8000 {
+                0 => crc << 1,
+                _ => crc << 1 ^ 0x8005,
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+static CRC16_TABLE: [u16; 256] = crc16_table();
+
+/// Length of the frame whose header `frame` is at the start of `window`: the
+/// first length its CRC-16 checks out for and either the next frame follows,
+/// the stream is complete or the input ends.
+fn frame_length(
+    window: &[u8],
+    frame: &FrameHeader,
+    info: &StreamInfo,
+    last: bool,
+    at_end: bool,
+) -> Option<usize> {
+    let mut crc: u16 = 0;
+    for (i, &byte) in window.iter().enumerate() {
+        crc = crc << 8 ^ CRC16_TABLE[usize::from((crc >> 8) as u8 ^ byte)];
+        // a header, at least a byte of subframe and the CRC-16 itself
+        let length = i + 1;
+        if crc != 0 || length < frame.length + 3 {
+            continue;
+        }
+        if last || (at_end && length == window.len()) {
+            return Some(length);
+        }
+        let next = parse_frame_header(&window[length..], info);
+        if next.is_some_and(|next| {
+            next.variable_block_size == frame.variable_block_size
+                && next.number == frame.next_number()
+        }) {
+            return Some(length);
+        }
+    }
+    None
+}
+
+/// FLAC streams, carved from the marker to the last frame that follows on.
+pub struct Flac;
+
+impl Carver for Flac {
+    fn starts_with(&self, byte: u8) -> bool {
+        byte == MARKER[0]
+    }
+
+    /// `None` if the stream has no frames.
+    fn carve(
+        &self,
+        source: &mut dyn Source,
+        _options: &ScanOptions,
+        deadline: &mut Deadline,
+    ) -> Result<Option<Stream>, Stopped> {
+        let header = source.peek(MARKER.len() + BLOCK_HEADER_LEN + STREAMINFO_LEN);
+        let info = match header {
+            Some(header)
+                if header.starts_with(MARKER)
+                    && header[4] & 0x7F == STREAMINFO
+                    && header[5..8] == [0, 0, STREAMINFO_LEN as u8] =>
+            {
+                parse_stream_info(&header[8..])
+            }
+            _ => None,
+        };
+        let info = match info {
+            Some(info) => info,
+            None => return Ok(None),
+        };
+        let offset = source.position();
+
+        // the marker and all metadata blocks
+        let mut data = Vec::new();
+        source.take_into(MARKER.len(), &mut data);
+        loop {
+            let block = match source.peek(BLOCK_HEADER_LEN) {
+                Some(block) if block[0] & 0x7F != INVALID_BLOCK => block,
+                _ => return Ok(None),
+            };
+            let is_last = block[0] & 0x80 != 0;
+            let length = BLOCK_HEADER_LEN
+                + (usize::from(block[1]) << 16
+                    | usize::from(block[2]) << 8
+                    | usize::from(block[3]));
+            if source.take_into(length, &mut data) < length {
+                return Ok(None);
+            }
+            if is_last {
+                break;
+            }
+        }
+
+        let limit = info.frame_limit() + MAX_HEADER_LEN;
+        let (mut frames, mut samples) = (0, 0);
+        loop {
+            deadline.check(source.position())?;
+            let window = source.peek_up_to(limit);
+            let frame = match parse_frame_header(window, &info) {
+                Some(frame) => frame,
+                None => break,
+            };
+            let last = info.total_samples != 0
+                && samples + u64::from(frame.block_size) >= info.total_samples;
+            let at_end = window.len() < limit;
+            let length = match frame_length(window, &frame, &info, last, at_end) {
+                Some(length) => length,
+                None => break,
+            };
+            source.take_into(length, &mut data);
+            frames += 1;
+            samples += u64::from(frame.block_size);
+            if last {
+                break;
+            }
+        }
+        if frames == 0 {
+            return Ok(None);
+        }
+
+        let bit_rate = data.len() as u64 * 8 * u64::from(info.sample_rate) / samples;
+        Ok(Some(Stream {
+            data,
+            format: Format::Flac,
+            offset,
+            frames,
+            samples,
+            sample_rate: info.sample_rate,
+            bit_rate: bit_rate as u32,
+            channel_mode: None,
+            // STREAMINFO says how many samples there are, where it's known
+            truncated: info.total_samples != 0 && samples < info.total_samples,
+            tags: Vec::new(),
+            gapless: None,
+            corrupt_frames: 0,
+            gaps: Vec::new(),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::carve::extract;
+
+    fn crc16(bytes: &[u8]) -> u16 {
+        bytes.iter().fold(0, |crc, &byte| {
+            crc << 8 ^ CRC16_TABLE[usize::from((crc >> 8) as u8 ^ byte)]
+        })
+    }
+
+    /// Frame `number` of 4096 samples, `length` bytes long.
+    fn frame(number: u8, length: usize) -> Vec<u8> {
+        // 4096 samples, 44.1 kHz, stereo, 16 bits
+        let mut frame = vec![0xFF, 0xF8, 0xC9, 0x18, number];
+        frame.push(crc8(&frame));
+        frame.resize(length - 2, 0x5A);
+        let crc = crc16(&frame);
+        frame.extend_from_slice(&crc.to_be_bytes());
+        frame
+    }
+
+    /// The marker, STREAMINFO for `total_samples` and a padding block, then
+    /// `frames` frames.
+    fn stream(total_samples: u64, frames: usize) -> Vec<u8> {
+        let mut out = b"fLaC".to_vec();
+        out.extend_from_slice(&[0, 0, 0, 34]);
+        out.extend_from_slice(&[0x10, 0, 0x10, 0, 0, 0, 0, 0, 0, 0]);
+        let packed = 44100 << 44 | 1 << 41 | 15 << 36 | total_samples;
+        out.extend_from_slice(&packed.to_be_bytes());
+        out.extend_from_slice(&[0; 16]);
+        out.extend_from_slice(&[0x81, 0, 0, 8]);
+        out.extend_from_slice(&[0; 8]);
+        for n in 0..frames {
+            out.extend_from_slice(&frame(n as u8, 3000 + n * 7));
+        }
+        out
+    }
+
+    fn carve(input: Vec<u8>) -> Vec<Stream> {
+        let options = ScanOptions {
+            threshold: 0,
+            ..ScanOptions::default()
+        };
+        extract(input, &options).unwrap()
+    }
+
+    #[test]
+    fn stream_with_every_frame() {
+        let flac = stream(60 * 4096, 60);
+        let mut input = vec![0xEE; 1234];
+        input.extend_from_slice(&flac);
+        input.extend_from_slice(&[0xEE; 999]);
+        let streams = carve(input);
+        assert_eq!(streams.len(), 1);
+        let stream = &streams[0];
+        assert_eq!(stream.format, Format::Flac);
+        assert_eq!((stream.offset, stream.data.len()), (1234, flac.len()));
+        assert_eq!((stream.frames, stream.samples), (60, 60 * 4096));
+        assert_eq!(stream.sample_rate, 44100);
+        assert!(!stream.truncated);
+    }
+
+    #[test]
+    fn stream_cut_short() {
+        let flac = stream(60 * 4096, 40);
+        let mut input = vec![0xEE; 1234];
+        input.extend_from_slice(&flac);
+        let streams = carve(input);
+        assert_eq!(streams.len(), 1);
+        assert_eq!(streams[0].data.len(), flac.len());
+        assert_eq!(streams[0].frames, 40);
+        assert!(streams[0].truncated);
+    }
+
+    #[test]
+    fn stream_of_unknown_length_to_the_end_of_the_input() {
+        let flac = stream(0, 60);
+        let mut input = vec![0xEE; 1234];
+        input.extend_from_slice(&flac);
+        let streams = carve(input);
+        assert_eq!(streams.len(), 1);
+        assert_eq!((streams[0].frames, streams[0].data.len()), (60, flac.len()));
+        assert!(!streams[0].truncated);
+    }
+}
@@ -0,0 +1,201 @@
+//! Just enough ID3v2 to name files after the tracks they contain, and to
+//! carve tags along with the streams they are in front of.
+//! See <https://id3.org/id3v2.4.0-structure>; v2.2 and v2.3 are read too.
+
+/// Tags are never looked for further back than this from the stream.
+pub const MAX_TAG_SIZE: usize = 16 << 20; // 16 MiB
+
+pub const HEADER_SIZE: usize = 10;
+
+/// ID3v1 tags are a fixed size block after the stream, starting with `TAG`.
+pub const V1_SIZE: usize = 128;
+
+/// Whether `data` starts with an ID3v1 tag.
+pub fn is_v1(data: &[u8]) -> bool {
+    data.len() >= V1_SIZE && data.starts_with(b"TAG")
+}
+
+/// The parts of an ID3v2 tag that matter for naming.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Tag {
+    pub artist: Option<String>,
+    pub title: Option<String>,
+}
+
+/// Find the ID3v2 tag that ends right where `data` does, i.e. the tag in front
+/// of an MP3 stream that starts just after `data`. Padding zeros between the
+/// tag and the stream are allowed.
+pub fn find_before(data: &[u8]) -> Option<Tag> {
+    let start = data.len().saturating_sub(MAX_TAG_SIZE);
+    let unpadded = data.len() - data.iter().rev().take_while(|&&b| b == 0).count();
+    (start..data.len().saturating_sub(HEADER_SIZE))
+        .rev()
+        .filter(|&pos| data[pos..].starts_with(b"ID3"))
+        .find_map(|pos| {
+            let size = tag_size(&data[pos..])?;
+            let end = pos + size;
+            if end < unpadded || end > data.len() {
+                return None;
+            }
+            parse(&data[pos..end])
+        })
+}
+
+/// The ID3v2 tag `data` starts with, as a stream carved with its tag does.
+pub fn find_at_start(data: &[u8]) -> Option<Tag> {
+    let size = tag_size(data)?;
+    parse(data.get(..size)?)
+}
+
+/// Total size of the tag starting at `data`, header (and footer) included.
+pub fn tag_size(data: &[u8]) -> Option<usize> {
+    let header = data.get(..HEADER_SIZE)?;
+    if &header[..3] != b"ID3" || header[3] == 0xFF || header[4] == 0xFF {
+        return None;
+    }
+    let size = syncsafe(&header[6..10])?;
+    let footer = if header[3] == 4 && header[5] & 0x10 != 0 {
+        HEADER_SIZE
+    } else {
+        0
+    };
+    Some(HEADER_SIZE + size + footer)
+}
+
+fn syncsafe(bytes: &[u8]) -> Option<usize> {
+    if bytes.iter().any(|&b| b & 0x80 != 0) {
+        return None;
+    }
+    Some(bytes.iter().fold(0, |n, &b| (n << 7) | usize::from(b)))
+}
+
+fn parse(tag: &[u8]) -> Option<Tag> {
+    let mut result = Tag::default();
+    for frame in frames(tag)? {
+        match &frame.id[..] {
+            b"TPE1" | b"TP1" => result.artist = text(&frame.data),
+            b"TIT2" | b"TT2" => result.title = text(&frame.data),
+            _ => {}
+        }
+    }
+
+    if result.artist.is_none() && result.title.is_none() {
+        None
+    } else {
+        Some(result)
+    }
+}
+
+/// A frame of an ID3v2 tag.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    /// Frame id, e.g. `TIT2`; three characters in ID3v2.2
+    pub id: Vec<u8>,
+    /// Contents of the frame, resynchronised
+    pub data: Vec<u8>,
+}
+
+/// The readable frames of `tag`, which starts with its header; compressed
+/// and encrypted frames are left out.
+pub fn frames(tag: &[u8]) -> Option<Vec<Frame>> {
+    let version = *tag.get(3)?;
+    let flags = *tag.get(5)?;
+    let size = syncsafe(tag.get(6..10)?)?;
+    let mut body = tag.get(HEADER_SIZE..HEADER_SIZE + size)?.to_vec();
+    // v2.4 unsynchronises frame by frame, with a frame flag; handled below
+    if flags & 0x80 != 0 && version < 4 {
+        body = resynchronise(&body);
+    }
+
+    let mut pos = 0;
+    if flags & 0x40 != 0 && version >= 3 {
+        let ext = body.get(..4)?;
+        pos = match version {
+            3 => 4 + u32::from_be_bytes([ext[0], ext[1], ext[2], ext[3]]) as usize,
+            _ => syncsafe(ext)?,
+        };
+    }
+
+    let mut frames = Vec::new();
+    let (id_len, header_len) = if version == 2 { (3, 6) } else { (4, 10) };
+    while let Some(header) = body.get(pos..pos + header_len) {
+        if header[0] == 0 {
+            // padding
+            break;
+        }
+        let id = header[..id_len].to_vec();
+        let frame_size = match version {
+            2 => {
+                usize::from(header[3]) << 16 | usize::from(header[4]) << 8 | usize::from(header[5])
+            }
+            3 => u32::from_be_bytes([header[4], header[5], header[6], header[7]]) as usize,
+            _ => syncsafe(&header[4..8])?,
+        };
+        let frame_flags = if version == 2 { 0 } else { header[9] };
+        let mut data = body
+            .get(pos + header_len..pos + header_len + frame_size)?
+            .to_vec();
+        pos += header_len + frame_size;
+
+        if version == 4 && frame_flags & 0x02 != 0 {
+            data = resynchronise(&data);
+        }
+        // compressed or encrypted frames can't be read as is
+        let unreadable = match version {
+            2 => false,
+            3 => frame_flags & 0xC0 != 0,
+            _ => frame_flags & 0x0C != 0,
+        };
+        if !unreadable {
+            frames.push(Frame { id, data });
+        }
+    }
+    Some(frames)
+}
+
+/// Undo unsynchronisation: every `FF 00` was written for a plain `FF`.
+fn resynchronise(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len());
+    let mut prev = 0;
+    for &b in data {
+        if !(prev == 0xFF && b == 0x00) {
+            out.push(b);
+        }
+        prev = b;
+    }
+    out
+}
+
+/// First value of a text frame, `None` if empty.
+fn text(frame: &[u8]) -> Option<String> {
+    let (&encoding, data) = frame.split_first()?;
+    let text = match encoding {
+        0 => data.iter().map(|&b| char::from(b)).collect(),
+        1 | 2 => {
+            let mut units: Vec<u16> = data
+                .chunks_exact(2)
+                .map(|c| u16::from_be_bytes([c[0], c[1]]))
+                .collect();
+            match units.first() {
+                Some(0xFFFE) => {
+                    units.remove(0);
+                    units.iter_mut().for_each(|u| *u = u.swap_bytes());
+                }
+                Some(0xFEFF) => {
+                    units.remove(0);
+                }
+                _ => {}
+            }
+            let end = units.iter().position(|&u| u == 0).unwrap_or(units.len());
+            String::from_utf16_lossy(&units[..end])
+        }
+        3 => String::from_utf8_lossy(data).into_owned(),
+        _ => return None,
+    };
+    let text = text.split('\0').next().unwrap_or("").trim().to_owned();
+    if text.is_empty() {
+        None
+    } else {
+        Some(text)
+    }
+}
@@ -0,0 +1,271 @@
+use std::ffi::{OsStr, OsString};
+use std::fmt;
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use tracing::error;
+use walkdir::WalkDir;
+
+/// A source of obfuscated data.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Input {
+    File(PathBuf),
+    Stdin,
+}
+
+impl Input {
+    /// Read the whole input into memory.
+    ///
+    /// Stdin is read at most up to `max_size`; anything larger is an error.
+    /// (File sizes are checked before reading.)
+    pub fn read(&self, max_size: Option<u64>) -> io::Result<Vec<u8>> {
+        match self {
+            Input::File(path) => fs::read(path),
+            Input::Stdin => {
+                let mut buffer = Vec::new();
+                let stdin = io::stdin().lock();
+                match max_size {
+                    Some(max) => {
+                        stdin.take(max.saturating_add(1)).read_to_end(&mut buffer)?;
+                        if buffer.len() as u64 > max {
+                            return Err(io::Error::other(format!(
+                                "input is larger than --max-input-size ({} bytes)",
+                                max
+                            )));
+                        }
+                    }
+                    None => {
+                        let mut stdin = stdin;
+                        stdin.read_to_end(&mut buffer)?;
+                    }
+                }
+                Ok(buffer)
+            }
+        }
+    }
+
+    /// Name that extracted files are derived from.
+    pub fn file_name(&self) -> OsString {
+        match self {
+            Input::File(path) => path
+                .file_name()
+                .unwrap_or_else(|| path.as_os_str())
+                .to_owned(),
+            Input::Stdin => OsString::from("stdin"),
+        }
+    }
+
+    /// Where an extracted file called `file_name` should be written.
+    ///
+    /// Without an output directory, files extracted from a path go next to it and
+    /// files extracted from stdin go to the current directory.
+    pub fn output_path(&self, file_name: &OsStr, output_dir: Option<&Path>) -> PathBuf {
+        match (output_dir, self) {
+            (Some(dir), _) => dir.join(file_name),
+            (None, Input::File(path)) => path.with_file_name(file_name),
+            (None, Input::Stdin) => PathBuf::from(file_name),
+        }
+    }
+}
+
+impl Input {
+    /// Directory of this input relative to the deepest of `bases` it is in,
+    /// `None` if it isn't in any.
+    pub fn relative_dir(&self, bases: &[PathBuf]) -> Option<PathBuf> {
+        let path = match self {
+            Input::File(path) => path,
+            Input::Stdin => return None,
+        };
+        let relative = bases
+            .iter()
+            .filter_map(|base| path.strip_prefix(base).ok())
+            .min_by_key(|relative| relative.components().count())?;
+        Some(relative.parent().unwrap_or(Path::new("")).to_path_buf())
+    }
+}
+
+impl fmt::Display for Input {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Input::File(path) => write!(f, "{:?}", path),
+            Input::Stdin => write!(f, "<stdin>"),
+        }
+    }
+}
+
+/// File name patterns deciding which files found in directories get processed.
+#[derive(Debug, Clone, Default)]
+pub struct Filter {
+    pub include: Vec<glob::Pattern>,
+    pub exclude: Vec<glob::Pattern>,
+}
+
+impl Filter {
+    /// Whether a file called `name` should be processed: it has to match one of
+    /// the include patterns, if there are any, and none of the exclude patterns.
+    pub fn matches(&self, name: &OsStr) -> bool {
+        let name = name.to_string_lossy();
+        (self.include.is_empty() || self.include.iter().any(|p| p.matches(&name)))
+            && !self.exclude.iter().any(|p| p.matches(&name))
+    }
+}
+
+/// Expand the paths given on the command line into the list of inputs to process.
+///
+/// `-` stands for standard input, which is read at most once. Arguments that
+/// don't exist but look like glob patterns (e.g. `cache/**/*.dat`) are expanded
+/// first, since not every shell does this for us. Directories are walked
+/// recursively and every regular file found inside that passes `filter` is
+/// included. Anything else is passed through as is, so `process_file` can
+/// report it.
+pub fn collect_inputs(paths: &[PathBuf], stdin: bool, filter: &Filter) -> Vec<Input> {
+    let mut inputs = Vec::new();
+    let mut stdin = stdin;
+    for path in paths {
+        if path.as_os_str() == "-" {
+            stdin = true;
+            continue;
+        }
+        match glob_pattern(path) {
+            Some(pattern) => expand_glob(pattern, &mut inputs, filter),
+            None => push_path(path, &mut inputs, filter),
+        }
+    }
+    if stdin {
+        inputs.push(Input::Stdin);
+    }
+    inputs
+}
+
+/// Directories that inputs found through `paths` (as given to
+/// `collect_inputs`) are relative to: directories themselves, and the part of
+/// glob patterns before the first wildcard.
+pub fn base_dirs(paths: &[PathBuf]) -> Vec<PathBuf> {
+    paths
+        .iter()
+        .filter_map(|path| match glob_pattern(path) {
+            Some(_) => Some(
+                path.components()
+                    .take_while(|c| !c.as_os_str().to_string_lossy().contains(['*', '?', '[']))
+                    .collect(),
+            ),
+            None if path.is_dir() => Some(path.clone()),
+            None => None,
+        })
+        .collect()
+}
+
+/// How entries in a file list are separated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ListFormat {
+    /// One path per line, `\r\n` line endings are accepted
+    Lines,
+    /// NUL-terminated paths, as produced by `find -print0`
+    Nul,
+}
+
+/// Read a list of paths from `list` (`-` for stdin).
+///
+/// Empty entries are ignored. Entries are returned as given so they can go
+/// through `collect_inputs` like command line arguments.
+pub fn read_file_list(list: &Path, format: ListFormat) -> io::Result<Vec<PathBuf>> {
+    let mut data = Vec::new();
+    if list.as_os_str() == "-" {
+        io::stdin().lock().read_to_end(&mut data)?;
+    } else {
+        data = fs::read(list)?;
+    }
+
+    let entries: Vec<&[u8]> = match format {
+        ListFormat::Lines => data
+            .split(|&b| b == b'\n')
+            .map(|line| line.strip_suffix(b"\r").unwrap_or(line))
+            .collect(),
+        ListFormat::Nul => data.split(|&b| b == 0).collect(),
+    };
+    entries
+        .into_iter()
+        .filter(|entry| !entry.is_empty())
+        .map(bytes_to_path)
+        .collect()
+}
+
+/// Paths in files we read and write are raw bytes on Unix, where file names
+/// don't have to be UTF-8, and UTF-8 elsewhere.
+#[cfg(unix)]
+pub fn bytes_to_path(bytes: &[u8]) -> io::Result<PathBuf> {
+    use std::os::unix::ffi::OsStrExt;
+    Ok(PathBuf::from(OsStr::from_bytes(bytes)))
+}
+
+#[cfg(not(unix))]
+pub fn bytes_to_path(bytes: &[u8]) -> io::Result<PathBuf> {
+    std::str::from_utf8(bytes)
+        .map(PathBuf::from)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
+
+/// The reverse of `bytes_to_path`.
+#[cfg(unix)]
+pub fn os_str_to_bytes(s: &OsStr) -> Vec<u8> {
+    use std::os::unix::ffi::OsStrExt;
+    s.as_bytes().to_vec()
+}
+
+#[cfg(not(unix))]
+pub fn os_str_to_bytes(s: &OsStr) -> Vec<u8> {
+    s.to_string_lossy().into_owned().into_bytes()
+}
+
+fn glob_pattern(path: &Path) -> Option<&str> {
+    if path.exists() {
+        return None;
+    }
+    let pattern = path.to_str()?;
+    if pattern.contains(['*', '?', '[']) {
+        Some(pattern)
+    } else {
+        None
+    }
+}
+
+fn expand_glob(pattern: &str, inputs: &mut Vec<Input>, filter: &Filter) {
+    let paths = match glob::glob(pattern) {
+        Ok(val) => val,
+        Err(err) => {
+            error!("Invalid glob pattern {:?}: {}", pattern, err);
+            return;
+        }
+    };
+
+    let mut matched = false;
+    for entry in paths {
+        match entry {
+            Ok(path) => {
+                matched = true;
+                push_path(&path, inputs, filter);
+            }
+            Err(err) => error!("Error expanding {:?}: {}", pattern, err),
+        }
+    }
+    if !matched {
+        error!("No files match {:?}", pattern);
+    }
+}
+
+fn push_path(path: &Path, inputs: &mut Vec<Input>, filter: &Filter) {
+    if !path.is_dir() {
+        inputs.push(Input::File(path.to_path_buf()));
+        return;
+    }
+
+    for entry in WalkDir::new(path) {
+        match entry {
+            Ok(entry) if entry.file_type().is_file() && filter.matches(entry.file_name()) => {
+                inputs.push(Input::File(entry.into_path()))
+            }
+            Ok(_) => {}
+            Err(err) => error!("Error walking {:?}: {}", path, err),
+        }
+    }
+}
@@ -0,0 +1,196 @@
+//! AAC in LOAS/LATM framing: every AudioMuxElement (LATM) gets a 3 byte
+//! AudioSyncStream header (LOAS) with an 11 bit sync word and its length.
+//!
+//! Unlike ADTS, the header says nothing about the audio; that is in a
+//! StreamMuxConfig at the start of the payload, which frames may leave out
+//! to reuse the previous one.
+
+use crate::bits::Bits;
+use crate::carve::{Format, FrameInfo, Framed, Source};
+
+/// Sample rates by sampling frequency index, as for ADTS.
+static SAMPLE_RATES: [u32; 13] = [
+    96000, 88200, 64000, 48000, 44100, 32000, 24000, 22050, 16000, 12000, 11025, 8000, 7350,
+];
+
+/// Length of the AudioSyncStream header.
+pub const HEADER_LEN: usize = 3;
+
+/// Enough of the payload to read a StreamMuxConfig from.
+pub const PEEK_LEN: usize = HEADER_LEN + 16;
+
+// AAC frames decode to 1024 samples, unless they are AAC-LD or -ELD, which
+// the app doesn't use
+const SAMPLES: u32 = 1024;
+
+/// The parts of a LOAS frame that matter for carving.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LoasHeader {
+    pub sample_rate: u32,
+    /// Channel configuration from the StreamMuxConfig
+    pub channels: u8,
+    /// Length of the whole frame, header included
+    pub length: usize,
+    /// Samples (per channel) the frame decodes to
+    pub samples: u32,
+}
+
+impl LoasHeader {
+    /// Bitrate of this frame alone, in bits per second.
+    pub fn bit_rate(&self) -> u32 {
+        (self.length as u64 * 8 * u64::from(self.sample_rate) / u64::from(self.samples)) as u32
+    }
+
+    /// Whether this frame can follow `previous` in the same stream.
+    pub fn continues(&self, previous: &LoasHeader) -> bool {
+        self.sample_rate == previous.sample_rate && self.channels == previous.channels
+    }
+}
+
+/// Check whether `bytes` start with a LOAS frame, and parse it. `previous` is
+/// the frame before, whose StreamMuxConfig frames without one reuse; a
+/// stream can't start with such a frame.
+pub fn parse_header(bytes: &[u8], previous: Option<&LoasHeader>) -> Option<LoasHeader> {
+    let header = bytes.get(..HEADER_LEN)?;
+    if header[0] != 0x56 || header[1] & 0xE0 != 0xE0 {
+        return None;
+    }
+    let length = HEADER_LEN + (usize::from(header[1] & 0x1F) << 8 | usize::from(header[2]));
+    let mut bits = Bits {
+        data: bytes.get(HEADER_LEN..)?,
+        pos: 0,
+    };
+
+    let use_same_stream_mux = bits.read(1)? == 1;
+    let (sample_rate, channels) = if use_same_stream_mux {
+        let previous = previous?;
+        (previous.sample_rate, previous.channels)
+    } else {
+        read_stream_mux_config(&mut bits)?
+    };
+    Some(LoasHeader {
+        sample_rate,
+        channels,
+        length,
+        samples: SAMPLES,
+    })
+}
+
+/// Sample rate and channel configuration from a StreamMuxConfig, if it is
+/// the usual single program, single layer one of `audioMuxVersion` 0.
+fn read_stream_mux_config(bits: &mut Bits) -> Option<(u32, u8)> {
+    let audio_mux_version = bits.read(1)?;
+    if audio_mux_version != 0 {
+        return None;
+    }
+    let _all_streams_same_time_framing = bits.read(1)?;
+    let _num_sub_frames = bits.read(6)?;
+    let num_program = bits.read(4)?;
+    let num_layer = bits.read(3)?;
+    if num_program != 0 || num_layer != 0 {
+        return None;
+    }
+
+    // AudioSpecificConfig
+    let audio_object_type = match bits.read(5)? {
+        31 => 32 + bits.read(6)?,
+        object_type => object_type,
+    };
+    if audio_object_type == 0 {
+        return None;
+    }
+    let sample_rate = match bits.read(4)? {
+        0x0F => bits.read(24)?,
+        index => *SAMPLE_RATES.get(index as usize)?,
+    };
+    if sample_rate == 0 {
+        return None;
+    }
+    let channels = bits.read(4)? as u8;
+    Some((sample_rate, channels))
+}
+
+/// AAC in LOAS frames, carved as runs of frames.
+pub struct Loas;
+
+impl Framed for Loas {
+    type Frame = LoasHeader;
+
+    fn format(&self) -> Format {
+        Format::Loas
+    }
+
+    fn starts_with(&self, byte: u8) -> bool {
+        byte == 0x56
+    }
+
+    fn frame(&self, source: &mut dyn Source, previous: Option<&LoasHeader>) -> Option<LoasHeader> {
+        let frame = parse_header(source.peek(PEEK_LEN)?, previous)?;
+        match previous {
+            Some(previous) if !frame.continues(previous) => None,
+            _ => Some(frame),
+        }
+    }
+
+    fn info(&self, frame: &LoasHeader) -> FrameInfo {
+        FrameInfo {
+            length: frame.length,
+            samples: frame.samples,
+            sample_rate: frame.sample_rate,
+            bit_rate: frame.bit_rate(),
+            channel_mode: None,
+            corrupt: false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::carve::{extract, ScanOptions};
+
+    /// A LOAS frame of `length` payload bytes, with a StreamMuxConfig for AAC
+    /// LC at 44.1 kHz, stereo, or reusing the one before.
+    fn frame(config: bool, length: usize) -> Vec<u8> {
+        let mut frame = vec![0x56, 0xE0 | (length >> 8) as u8, length as u8];
+        let mut payload = vec![0; length];
+        if config {
+            // same time framing, then object type 2, frequency index 4 and
+            // channel configuration 2
+            payload[..4].copy_from_slice(&[0x20, 0x00, 0x12, 0x10]);
+        } else {
+            payload[0] = 0x80;
+        }
+        frame.extend_from_slice(&payload);
+        frame
+    }
+
+    #[test]
+    fn header_and_config() {
+        let config = frame(true, 370);
+        let header = parse_header(&config, None).unwrap();
+        assert_eq!((header.sample_rate, header.channels), (44100, 2));
+        assert_eq!(header.length, 373);
+        let same = frame(false, 200);
+        assert_eq!(parse_header(&same, None), None);
+        let reused = parse_header(&same, Some(&header)).unwrap();
+        assert_eq!((reused.sample_rate, reused.length), (44100, 203));
+    }
+
+    #[test]
+    fn streams_start_with_a_config() {
+        let mut input = vec![0; 555];
+        for i in 1..400 {
+            input.extend_from_slice(&frame(i % 10 == 0, 370));
+        }
+        input.extend_from_slice(&[0; 300]);
+        let streams = extract(input, &ScanOptions::default()).unwrap();
+        assert_eq!(streams.len(), 1);
+        let stream = &streams[0];
+        assert_eq!(stream.format, Format::Loas);
+        // the frames before the first config can't be told apart from noise
+        assert_eq!(stream.offset, 555 + 9 * 373);
+        assert_eq!((stream.frames, stream.data.len()), (390, 390 * 373));
+        assert_eq!((stream.sample_rate, stream.samples), (44100, 390 * 1024));
+    }
+}
@@ -0,0 +1,204 @@
+//! Console and log file output, built on `tracing`.
+//!
+//! Every event is formatted into its own buffer and written to stderr in one
+//! go, so messages from parallel workers never interleave, and through the
+//! progress display when there is one so they don't garble it.
+//!
+//! With `capture` the output of a worker can also be held back and written
+//! later, to keep it in input order.
+
+use crate::output;
+use indicatif::MultiProgress;
+use std::cell::RefCell;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::{Mutex, OnceLock};
+use tracing::{Event, Subscriber};
+use tracing_subscriber::filter::{EnvFilter, LevelFilter};
+use tracing_subscriber::fmt::format::{self, FormatEvent, FormatFields};
+use tracing_subscriber::fmt::{FmtContext, FormattedFields, MakeWriter};
+use tracing_subscriber::prelude::*;
+use tracing_subscriber::registry::LookupSpan;
+
+static PROGRESS: OnceLock<MultiProgress> = OnceLock::new();
+
+thread_local! {
+    static CAPTURE: RefCell<Option<Captured>> = const { RefCell::new(None) };
+}
+
+/// Console output held back by `capture`.
+#[derive(Debug, Default)]
+pub struct Captured(Vec<(Target, Vec<u8>)>);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Target {
+    Stdout,
+    Stderr,
+}
+
+impl Captured {
+    /// Write everything captured, in the order it was produced.
+    pub fn emit(self) {
+        for (target, data) in self.0 {
+            write_to(target, &data);
+        }
+    }
+}
+
+/// Set up logging. The console shows events up to `console_level` unless
+/// overridden with `RUST_LOG`; `log_file`, if given, records everything up to
+/// debug level with timestamps and the input each event belongs to.
+pub fn init(console_level: LevelFilter, log_file: Option<&Path>) -> io::Result<()> {
+    let console_filter = EnvFilter::builder()
+        .with_default_directive(console_level.into())
+        .from_env_lossy();
+    let console = tracing_subscriber::fmt::layer()
+        .with_ansi(false)
+        .with_writer(Console)
+        .event_format(ConsoleFormat {
+            spans: console_level >= LevelFilter::DEBUG,
+        })
+        .with_filter(console_filter);
+
+    let file = match log_file {
+        Some(path) => {
+            let file = File::create(path)?;
+            output::record_written(path);
+            Some(
+                tracing_subscriber::fmt::layer()
+                    .with_ansi(false)
+                    .with_target(false)
+                    .with_writer(Mutex::new(file))
+                    .with_filter(LevelFilter::DEBUG),
+            )
+        }
+        None => None,
+    };
+
+    tracing_subscriber::registry()
+        .with(console)
+        .with(file)
+        .init();
+    Ok(())
+}
+
+/// Print messages above `multi` instead of directly to stderr.
+pub fn set_progress(multi: MultiProgress) {
+    let _ = PROGRESS.set(multi);
+}
+
+/// Run `f` with the progress display hidden, e.g. to prompt the user.
+pub fn suspend<R>(f: impl FnOnce() -> R) -> R {
+    match PROGRESS.get() {
+        Some(multi) => multi.suspend(f),
+        None => f(),
+    }
+}
+
+/// Run `f`, holding back everything it prints to the console on this thread
+/// (log messages and `print`) until `Captured::emit` is called.
+pub fn capture<R>(f: impl FnOnce() -> R) -> (R, Captured) {
+    let outer = CAPTURE.with(|c| c.replace(Some(Captured::default())));
+    let result = f();
+    let captured = CAPTURE.with(|c| c.replace(outer)).unwrap_or_default();
+    (result, captured)
+}
+
+/// Whether console output on this thread is held back by `capture`.
+pub fn capturing() -> bool {
+    CAPTURE.with(|c| c.borrow().is_some())
+}
+
+/// Print `text` to stdout, unless it's being captured.
+pub fn print(text: &str) {
+    write_to(Target::Stdout, text.as_bytes());
+}
+
+fn write_to(target: Target, data: &[u8]) {
+    let captured = CAPTURE.with(|c| match c.borrow_mut().as_mut() {
+        Some(captured) => {
+            captured.0.push((target, data.to_vec()));
+            true
+        }
+        None => false,
+    });
+    if captured {
+        return;
+    }
+    suspend(|| {
+        let _ = match target {
+            Target::Stdout => io::stdout().lock().write_all(data),
+            Target::Stderr => io::stderr().lock().write_all(data),
+        };
+    });
+}
+
+struct Console;
+
+/// A single formatted event, written out when dropped.
+struct ConsoleEvent(Vec<u8>);
+
+impl<'a> MakeWriter<'a> for Console {
+    type Writer = ConsoleEvent;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        ConsoleEvent(Vec::new())
+    }
+}
+
+impl Write for ConsoleEvent {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for ConsoleEvent {
+    fn drop(&mut self) {
+        write_to(Target::Stderr, &self.0);
+    }
+}
+
+/// Bare messages for normal use; with `spans`, prefixed by the spans they
+/// happened in, e.g. `input{path="a.amp"}: ...`.
+struct ConsoleFormat {
+    spans: bool,
+}
+
+impl<S, N> FormatEvent<S, N> for ConsoleFormat
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+{
+    fn format_event(
+        &self,
+        ctx: &FmtContext<'_, S, N>,
+        mut writer: format::Writer<'_>,
+        event: &Event<'_>,
+    ) -> fmt::Result {
+        if self.spans {
+            for span in ctx
+                .event_scope()
+                .into_iter()
+                .flat_map(|scope| scope.from_root())
+            {
+                write!(writer, "{}", span.name())?;
+                let extensions = span.extensions();
+                if let Some(fields) = extensions.get::<FormattedFields<N>>() {
+                    if !fields.is_empty() {
+                        write!(writer, "{{{}}}", fields)?;
+                    }
+                }
+                write!(writer, ": ")?;
+            }
+        }
+        ctx.field_format().format_fields(writer.by_ref(), event)?;
+        writeln!(writer)
+    }
+}
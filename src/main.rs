@@ -1,195 +1,1826 @@
-use itertools::Itertools;
+use clap::error::ErrorKind;
+use clap::{CommandFactory, Parser, Subcommand};
 use rayon::prelude::*;
-use std::convert::TryFrom;
-use std::path::Path;
-use std::{env, fs};
+use std::collections::{BTreeMap, HashMap};
+use std::ffi::OsString;
+use std::fs;
+use std::io::{self, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::{debug, error, info, info_span};
+use tracing_subscriber::filter::LevelFilter;
 
-const THRESHOLD: usize = 50 * (1 << 10); // 50 KiB
-static MP3_BIT_RATES: [u32; 14] = [
-    32000, 40000, 48000, 56000, 64000, 80000, 96000, 112000, 128000, 160000, 192000, 224000,
-    256000, 320000,
-];
-static MP3_SAMPLE_RATES: [u32; 3] = [44100, 48000, 3200];
+use archive::Archive;
+use carve::{extract, ScanOptions, Stopped};
+use config::Config;
+use deobfs::{Deobfuscator, Key, Scheme};
+use error::ExtractError;
+use input::{Filter, Input, ListFormat};
+use naming::{NameContext, Template};
+use output::{Overwrite, WriteOutcome};
+use progress::Progress;
+use report::{FileReport, OutputReport, ReportSpec, Summary};
+use state::State;
 
-fn get_bit_rate(i: u32) -> Option<u32> {
-    let min = 0b0001;
-    let i = i.checked_sub(min)?;
-    let i = usize::try_from(i).ok()?;
-    MP3_BIT_RATES.get(i).copied()
+mod adts;
+mod aiff;
+mod amr;
+mod ape;
+mod archive;
+mod asf;
+mod bench;
+mod bits;
+mod carve;
+mod checksum;
+mod config;
+#[cfg(feature = "decode")]
+mod decode;
+mod decrypt;
+mod dedupe;
+mod deobfs;
+mod entropy;
+mod error;
+mod flac;
+mod id3;
+mod input;
+mod loas;
+mod log;
+mod metadata;
+mod mp3;
+mod mp4;
+mod naming;
+mod ogg;
+mod output;
+#[cfg(feature = "plugin")]
+mod plugin;
+mod progress;
+mod prompt;
+mod remux;
+mod report;
+mod side_info;
+#[cfg(feature = "decode")]
+mod silence;
+mod size;
+mod state;
+mod stream;
+mod video;
+mod watch;
+mod wav;
+mod xing;
+
+/// Every input was processed without errors.
+const EXIT_SUCCESS: i32 = 0;
+/// At least one input (or a report) couldn't be processed, or there were no
+/// inputs at all.
+const EXIT_FAILURE: i32 = 1;
+/// Nothing failed, but none of the inputs contained any audio.
+/// (2 is left for usage errors reported by clap.)
+const EXIT_NO_AUDIO: i32 = 3;
+
+#[derive(Parser, Debug)]
+#[command(
+    version,
+    about = "Extract MP3s from obfuscated .amp files",
+    args_conflicts_with_subcommands = true
+)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    // `amp-extract FILES...` is short for `amp-extract extract FILES...`
+    #[command(flatten)]
+    args: Args,
 }
 
-fn get_sample_rate(i: u32) -> Option<u32> {
-    let min = 0b00;
-    let i = i.checked_sub(min)?;
-    let i = usize::try_from(i).ok()?;
-    MP3_SAMPLE_RATES.get(i).copied()
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Extract MP3s from the inputs (the default)
+    Extract(Args),
+    /// Only look for MP3s and list them, without writing anything
+    Scan(Args),
+    /// Show frame statistics for every MP3 found, without writing anything
+    Info(Args),
+    /// Measure deobfuscation and scanning throughput
+    Bench(bench::BenchArgs),
+    /// List the known obfuscation schemes that `--scheme` accepts by name
+    Schemes,
 }
 
-fn main() {
-    let args: Vec<_> = env::args_os().skip(1).collect();
-    args.par_iter().for_each(process_file);
+/// What a run does with the MP3s it finds.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+enum Mode {
+    #[default]
+    Extract,
+    Scan,
+    Info,
 }
 
-fn process_file(path: impl AsRef<Path>) {
-    if !path.as_ref().is_file() {
-        eprintln!("Not a file: {:?}", &path.as_ref());
-        return;
+#[derive(clap::Args, Debug)]
+struct Args {
+    /// Write extracted files to this directory instead of next to each input
+    #[arg(short, long, value_name = "DIR")]
+    output_dir: Option<PathBuf>,
+
+    /// Write the extracted MP3 to standard output; fails if an input doesn't contain exactly one
+    #[arg(long, conflicts_with = "output_dir")]
+    stdout: bool,
+
+    /// Delete each input once everything in it has been extracted and verified
+    #[arg(long, conflicts_with_all = ["stdout", "dry_run", "move_source"])]
+    remove_source: bool,
+
+    /// Move each input into DIR once everything in it has been extracted and verified
+    #[arg(long, value_name = "DIR", conflicts_with_all = ["stdout", "dry_run"])]
+    move_source: Option<PathBuf>,
+
+    /// Give extracted files the same modification time as their input
+    #[arg(long)]
+    preserve_times: bool,
+
+    /// Output filename template [default: {name}.{index}.{ext}]; fields: {name}, {stem}, {index}, {offset}, {variant}, {ext}
+    #[arg(long, value_name = "TEMPLATE")]
+    name: Option<Template>,
+
+    /// Name outputs `Artist - Title.mp3` after the ID3 tag in front of them, where there is one
+    #[arg(long)]
+    name_from_tags: bool,
+
+    /// Also write the cover art in the ID3 tag in front of a stream next to it, e.g. `track.1.jpg`
+    #[arg(long)]
+    extract_art: bool,
+
+    /// Also write what each extracted stream is (format, frames, bitrate, sample rate, duration, ...) as JSON next to it, e.g. `track.1.json`
+    #[arg(long)]
+    info_file: bool,
+
+    /// Write inputs that hold video instead of audio out as they are, deobfuscated, e.g. as `<name>.mp4`
+    #[arg(long)]
+    extract_video: bool,
+
+    /// Put a Xing/Info header in front of each extracted MP3, so players get the duration of VBR streams right
+    #[arg(long)]
+    xing_header: bool,
+
+    /// Decode each MP3 and cut a second or more of silence (nothing louder than -60 dBFS) off its start and end, at frame boundaries. Damaged streams are left as they are; needs the `decode` feature
+    #[arg(long)]
+    trim_silence: bool,
+
+    /// Read the side information of the Layer III frames of each MP3 as a decoder would, and withhold streams where much of it is garbage. Nothing is decoded, so corrupt audio (main) data behind sound side information isn't caught; `--verify` decodes
+    #[arg(long)]
+    check_side_info: bool,
+
+    /// Decode each carved stream and withhold those where more than 3 frames, and more than one in 20, don't decode. MP3, AAC (ADTS and MP4), FLAC, Vorbis, WAV and AIFF streams are decoded; needs the `decode` feature
+    #[arg(long)]
+    verify: bool,
+
+    /// Remux carved streams into another container: `m4a` puts ADTS (.aac) streams in an M4A file with a seek index
+    #[arg(long, value_name = "FORMAT")]
+    remux: Option<remux::Remux>,
+
+    /// Drop junk bytes between the frames of a stream (up to 4 KiB at a time) and carry on with the frames after, instead of splitting the stream there
+    #[arg(long)]
+    clean: bool,
+
+    /// Carry a stream on past damage, e.g. corrupted sectors: skip up to 64 KiB to the next frame of the stream, leave out frames whose CRC fails, and log each gap
+    #[arg(long)]
+    repair: bool,
+
+    /// Recreate the layout of input directories under the output directory (or archive) instead of putting everything in one place
+    #[arg(long)]
+    mirror: bool,
+
+    /// Write all extracted files into a single archive instead (.zip, .tar, .tar.gz)
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["stdout", "output_dir", "remove_source", "move_source"])]
+    archive: Option<PathBuf>,
+
+    /// Record the checksum of every extracted file in a SHA256SUMS file in its directory
+    #[arg(long, value_name = "ALGORITHM", conflicts_with = "stdout")]
+    checksums: Option<checksum::Algorithm>,
+
+    /// With --checksums, write a separate `<file>.sha256` next to each extracted file instead
+    #[arg(long, requires = "checksums")]
+    checksum_files: bool,
+
+    /// Overwrite existing output files
+    #[arg(short, long)]
+    force: bool,
+
+    /// Leave existing output files alone instead of failing
+    #[arg(long, conflicts_with = "force")]
+    skip_existing: bool,
+
+    /// Scan inputs and report what would be extracted without writing anything
+    #[arg(short = 'n', long, conflicts_with = "stdout")]
+    dry_run: bool,
+
+    /// List the MP3s in each input with their offset, size, bitrate, sample rate and duration, without writing anything (same as the scan command)
+    #[arg(long)]
+    list: bool,
+
+    /// Print a JSON report of the extraction results to standard output
+    #[arg(long, conflicts_with = "stdout")]
+    json: bool,
+
+    /// Also write the list of inputs that failed or had no audio to FILE
+    #[arg(long, value_name = "FILE")]
+    errors_to: Option<PathBuf>,
+
+    /// Write a summary report at the end of the run, e.g. `csv=report.csv`
+    #[arg(long, value_name = "FORMAT=PATH")]
+    report: Vec<ReportSpec>,
+
+    /// Only print errors
+    #[arg(short, long, conflicts_with = "verbose")]
+    quiet: bool,
+
+    /// Print diagnostics; repeat (-vv) for per-frame details
+    #[arg(short, long, action = clap::ArgAction::Count)]
+    verbose: u8,
+
+    /// Stop starting new inputs as soon as one fails, and exit with an error
+    #[arg(long)]
+    fail_fast: bool,
+
+    /// Skip input files smaller than this without reading them, e.g. `100k`
+    #[arg(long, value_name = "SIZE", default_value = "100k", value_parser = size::parse_size)]
+    min_input_size: u64,
+
+    /// Skip input files larger than this instead of reading them into memory
+    #[arg(long, value_name = "SIZE", value_parser = size::parse_size)]
+    max_input_size: Option<u64>,
+
+    /// Drop carved streams this size or smaller, e.g. `10k` for short jingles and notification sounds [default: 50k]
+    #[arg(long, value_name = "SIZE", value_parser = size::parse_size)]
+    min_stream_size: Option<u64>,
+
+    /// Only take a run of MP3 (or ADTS, LOAS) frames for audio if it starts with this many consistent frames back to back [default: 8]
+    #[arg(long, value_name = "N")]
+    min_frames: Option<usize>,
+
+    /// Scan input files larger than this many MiB straight from disk instead of reading them into memory
+    #[arg(long, value_name = "MB")]
+    max_memory: Option<u64>,
+
+    /// How the inputs are obfuscated: a name from the `schemes` command, `auto`, `swap` (e.g. `swap:period=4,offset=1`), `xor`, `rol`/`ror` (e.g. `rol:bits=3`), `nibble` or `none`
+    #[arg(long, value_name = "SCHEME", default_value = "swap")]
+    scheme: Scheme,
+
+    /// Scan the inputs as they are, for data that isn't obfuscated (same as `--scheme none`)
+    #[arg(long, conflicts_with_all = ["scheme", "swap_period", "key", "bruteforce"])]
+    no_deobfs: bool,
+
+    /// Write each whole input, deobfuscated, to `<name>.deobfs` instead of looking for MP3s in it
+    #[arg(long, conflicts_with_all = ["stdout", "archive", "remove_source", "move_source", "checksums", "name"])]
+    deobfs_only: bool,
+
+    /// Apply the scheme (which must be a single transform, e.g. `swap:offset=1`) to each input instead, writing `<stem>.amp`: turns plain MP3s into test files
+    #[arg(long, conflicts_with_all = ["stdout", "archive", "remove_source", "move_source", "checksums", "name", "deobfs_only", "bruteforce", "plugin", "aes_key", "aes_key_file"])]
+    obfuscate: bool,
+
+    /// Try a range of simple transforms (byte swaps, single byte XOR, bit rotations) on a sample of each input and extract with whichever turns up the most MP3 frames
+    #[arg(long, conflicts_with_all = ["scheme", "swap_period", "key", "all_offsets"])]
+    bruteforce: bool,
+
+    /// Decrypt every input with this AES key (16, 24 or 32 bytes) before deobfuscating it, e.g. `hex:00112233...`
+    #[arg(long, value_name = "KEY", conflicts_with = "aes_key_file")]
+    aes_key: Option<Key>,
+
+    /// Read the AES key from FILE, as raw bytes or hex digits
+    #[arg(long, value_name = "FILE")]
+    aes_key_file: Option<PathBuf>,
+
+    /// IV (or initial counter) for AES, e.g. `hex:000102...`; all zeros if not given
+    #[arg(long, value_name = "IV")]
+    aes_iv: Option<Key>,
+
+    /// AES mode, `ctr` or `cbc`
+    #[arg(long, value_name = "MODE", default_value = "ctr")]
+    aes_mode: decrypt::Mode,
+
+    /// Also try the deobfuscator in this WebAssembly module on every input; can be repeated
+    #[arg(long, value_name = "FILE")]
+    plugin: Vec<PathBuf>,
+
+    /// Swap adjacent bytes in groups of N bytes instead of 4 (shorthand for `--scheme swap:period=N`); several periods, e.g. `4,8`, are all tried
+    #[arg(long, value_name = "N", value_delimiter = ',')]
+    swap_period: Vec<usize>,
+
+    /// Key for `--scheme xor`, e.g. `hex:5a3c`; guessed from each input if not given
+    #[arg(long, value_name = "KEY")]
+    key: Option<Key>,
+
+    /// Fully scan the input with every variant of the scheme (e.g. every swap offset) instead of only the ones a quick sample suggests
+    #[arg(long)]
+    all_offsets: bool,
+
+    /// When several variants are scanned, drop one as soon as its first SIZE bytes turn up no MP3 frames; `0` scans every variant to the end
+    #[arg(long, value_name = "SIZE", default_value = "8m", value_parser = size::parse_size)]
+    abandon_after: u64,
+
+    /// Give up on an input if scanning it takes longer than SECS seconds
+    #[arg(long, value_name = "SECS")]
+    timeout: Option<f64>,
+
+    /// Number of inputs to process in parallel (defaults to the number of CPUs)
+    #[arg(short, long, value_name = "N", value_parser = clap::value_parser!(u16).range(1..))]
+    jobs: Option<u16>,
+
+    /// Record everything that happens in FILE, with timestamps, whatever the console verbosity
+    #[arg(long, value_name = "FILE")]
+    log_file: Option<PathBuf>,
+
+    /// Print messages and results in input order, as if inputs were processed one at a time
+    #[arg(long)]
+    ordered: bool,
+
+    /// Show a progress display while processing
+    #[arg(short = 'P', long)]
+    progress: bool,
+
+    /// Load defaults from this config file instead of ./amp-extract.toml
+    #[arg(long, value_name = "FILE")]
+    config: Option<PathBuf>,
+
+    /// After processing the given inputs, keep watching DIR and extract new files as they appear
+    #[arg(long, value_name = "DIR", conflicts_with_all = ["stdout", "json", "report", "progress"])]
+    watch: Option<PathBuf>,
+
+    /// Only process files in directories whose name matches PATTERN, e.g. `*.dat`
+    #[arg(long, value_name = "PATTERN")]
+    include: Vec<glob::Pattern>,
+
+    /// Skip files in directories whose name matches PATTERN, e.g. `*.json`
+    #[arg(long, value_name = "PATTERN")]
+    exclude: Vec<glob::Pattern>,
+
+    /// Also process the paths listed in FILE, one per line (`-` for stdin)
+    #[arg(long, value_name = "FILE")]
+    files_from: Option<PathBuf>,
+
+    /// Like --files-from, but paths are NUL-separated (as with `find -print0`)
+    #[arg(long, value_name = "FILE", conflicts_with = "files_from")]
+    files_from0: Option<PathBuf>,
+
+    /// Remember processed inputs in FILE and skip them on later runs
+    #[arg(long, value_name = "FILE")]
+    state: Option<PathBuf>,
+
+    /// Read obfuscated data from standard input (same as passing `-`)
+    #[arg(long)]
+    stdin: bool,
+
+    /// Files, directories or glob patterns to process; directories are searched recursively
+    files: Vec<PathBuf>,
+
+    #[arg(skip)]
+    mode: Mode,
+}
+
+/// Everything shared by the workers during a run.
+struct Run {
+    args: Args,
+    /// Directories that `--mirror` makes output paths relative to
+    base_dirs: Vec<PathBuf>,
+    /// Set by the first failure with `--fail-fast`
+    aborted: AtomicBool,
+    /// Loaded with `--plugin`, tried on every input
+    plugins: Vec<Arc<dyn Deobfuscator>>,
+    /// From `--aes-key`, applied to every input first
+    decryption: Option<decrypt::Decryption>,
+    archive: Option<Archive>,
+    state: Option<State>,
+}
+
+impl Cli {
+    /// The arguments of whichever subcommand was given, with `mode` set.
+    fn into_args(self) -> Args {
+        let (mode, mut args) = match self.command {
+            None => (Mode::Extract, self.args),
+            Some(Command::Extract(args)) => (Mode::Extract, args),
+            Some(Command::Scan(args)) => (Mode::Scan, args),
+            Some(Command::Info(args)) => (Mode::Info, args),
+            Some(Command::Bench(_)) | Some(Command::Schemes) => unreachable!("handled in main"),
+        };
+        let mode = match mode {
+            Mode::Extract if args.list => Mode::Scan,
+            mode => mode,
+        };
+        if mode != Mode::Extract {
+            let writes = [
+                ("--stdout", args.stdout),
+                ("--remove-source", args.remove_source),
+                ("--move-source", args.move_source.is_some()),
+                ("--archive", args.archive.is_some()),
+            ];
+            if let Some((flag, _)) = writes.iter().find(|(_, given)| *given) {
+                Cli::command()
+                    .error(
+                        ErrorKind::ArgumentConflict,
+                        format!("{} can only be used with the extract command", flag),
+                    )
+                    .exit();
+            }
+            args.dry_run = true;
+        }
+        if args.no_deobfs {
+            args.scheme = Scheme::None;
+        }
+        let mut scheme = Ok(args.scheme.clone());
+        if let Some(key) = args.key.take() {
+            scheme = scheme.and_then(|scheme| scheme.with_key(key));
+        }
+        if !args.swap_period.is_empty() {
+            let periods = std::mem::take(&mut args.swap_period);
+            scheme = scheme.and_then(|scheme| scheme.with_swap_periods(periods));
+        }
+        args.scheme = match scheme {
+            Ok(scheme) => scheme,
+            Err(err) => Cli::command()
+                .error(ErrorKind::ArgumentConflict, err)
+                .exit(),
+        };
+        if args.obfuscate && args.scheme.candidates(&[]).len() != 1 {
+            Cli::command()
+                .error(
+                    ErrorKind::ArgumentConflict,
+                    "--obfuscate needs a single transform, e.g. --scheme swap:offset=1",
+                )
+                .exit();
+        }
+        args.mode = mode;
+        args
     }
+}
 
-    // deobfuscate files and extract mp3s
-    let buffer: Vec<u8> = match fs::read(&path) {
-        Ok(val) => val,
-        Err(err) => {
-            eprintln!("Error opening {:?}: {}", &path.as_ref(), err);
-            return;
+impl Args {
+    /// Fill in anything not given on the command line from `config`.
+    fn merge_config(&mut self, config: Config) {
+        self.output_dir = self.output_dir.take().or(config.output_dir);
+        self.min_stream_size = self.min_stream_size.or(config.min_stream_size);
+        self.min_frames = self.min_frames.or(config.min_frames);
+        self.name = self.name.take().or(config.name);
+        self.jobs = self.jobs.or(config.jobs);
+        if self.include.is_empty() {
+            self.include = config.include;
+        }
+        if self.exclude.is_empty() {
+            self.exclude = config.exclude;
+        }
+    }
+
+    fn overwrite(&self) -> Overwrite {
+        if self.force {
+            Overwrite::Force
+        } else if self.skip_existing {
+            Overwrite::Skip
+        } else if io::stdin().is_terminal() && io::stderr().is_terminal() {
+            Overwrite::Ask
+        } else {
+            Overwrite::Fail
+        }
+    }
+}
+
+fn main() {
+    let start = Instant::now();
+    let cli = Cli::parse();
+    if let Some(Command::Bench(bench_args)) = &cli.command {
+        let _ = log::init(LevelFilter::INFO, None);
+        std::process::exit(bench::run(bench_args));
+    }
+    if let Some(Command::Schemes) = &cli.command {
+        print_schemes();
+        std::process::exit(EXIT_SUCCESS);
+    }
+    let mut args = cli.into_args();
+    let console_level = if args.quiet {
+        LevelFilter::ERROR
+    } else {
+        match args.verbose {
+            0 => LevelFilter::INFO,
+            1 => LevelFilter::DEBUG,
+            _ => LevelFilter::TRACE,
         }
     };
+    if let Err(err) = log::init(console_level, args.log_file.as_deref()) {
+        eprintln!("Error creating log file {:?}: {}", args.log_file, err);
+        std::process::exit(EXIT_FAILURE);
+    }
 
-    let extracted = (0..4)
-        .map(|i| extract_mp3(deobfs(&buffer, i)))
-        .flatten()
-        // sort extracted mp3s by the order they appear in
-        .sorted_unstable_by_key(|x| x.1);
+    match Config::load(args.config.as_deref()) {
+        Ok(config) => args.merge_config(config),
+        Err(err) => {
+            error!("{}", err);
+            std::process::exit(EXIT_FAILURE);
+        }
+    }
 
-    // write mp3s to file
-    for (i, mp3) in extracted.enumerate() {
-        let path_out = {
-            let mut filename_out = path.as_ref().file_name().to_owned().unwrap().to_owned();
-            filename_out.push(format!(".{}.mp3", i + 1));
-            path.as_ref().with_file_name(filename_out)
+    if let Some(jobs) = args.jobs {
+        if let Err(err) = rayon::ThreadPoolBuilder::new()
+            .num_threads(usize::from(jobs))
+            .build_global()
+        {
+            error!("Error creating thread pool: {}", err);
+            std::process::exit(EXIT_FAILURE);
+        }
+    }
+
+    for dir in args.output_dir.iter().chain(&args.move_source) {
+        if let Err(err) = fs::create_dir_all(dir) {
+            error!("Error creating directory {:?}: {}", dir, err);
+            std::process::exit(EXIT_FAILURE);
+        }
+    }
+
+    let mut paths = args.files.clone();
+    let file_list = match (&args.files_from, &args.files_from0) {
+        (Some(list), _) => Some((list, ListFormat::Lines)),
+        (_, Some(list)) => Some((list, ListFormat::Nul)),
+        (None, None) => None,
+    };
+    if let Some((list, format)) = file_list {
+        let reads_stdin = args.stdin || args.files.iter().any(|p| p.as_os_str() == "-");
+        if list.as_os_str() == "-" && reads_stdin {
+            error!("Can't read both the file list and data from stdin");
+            std::process::exit(EXIT_FAILURE);
+        }
+        match input::read_file_list(list, format) {
+            Ok(listed) => paths.extend(listed),
+            Err(err) => {
+                error!("Error reading file list {:?}: {}", list, err);
+                std::process::exit(EXIT_FAILURE);
+            }
+        }
+    }
+    let filter = Filter {
+        include: args.include.clone(),
+        exclude: args.exclude.clone(),
+    };
+    let inputs = input::collect_inputs(&paths, args.stdin, &filter);
+    // not the same as inputs without audio; --watch waits for inputs to come
+    if inputs.is_empty() && args.watch.is_none() {
+        error!("No inputs found: the directories given hold no files, or --include/--exclude left none");
+        std::process::exit(EXIT_FAILURE);
+    }
+
+    let progress = if args.progress {
+        let progress = match Progress::new(&inputs) {
+            Ok(progress) => progress,
+            Err(err) => {
+                error!("Error setting up --progress: {}", err);
+                std::process::exit(EXIT_FAILURE);
+            }
         };
+        log::set_progress(progress.multi().clone());
+        Some(progress)
+    } else {
+        None
+    };
 
-        println!("writing {}", &path_out.to_string_lossy());
-        fs::write(path_out, &mp3.0).unwrap();
+    let archive = match &args.archive {
+        Some(path) if !args.dry_run => match Archive::create(path, args.force) {
+            Ok(archive) => Some(archive),
+            Err(err) => {
+                error!("Error creating archive {:?}: {}", path, err);
+                std::process::exit(EXIT_FAILURE);
+            }
+        },
+        _ => None,
+    };
+
+    let state = match &args.state {
+        Some(path) => match State::open(path) {
+            Ok(state) => Some(state),
+            Err(err) => {
+                error!("Error opening state file {:?}: {}", path, err);
+                std::process::exit(EXIT_FAILURE);
+            }
+        },
+        None => None,
+    };
+
+    let mut base_dirs = input::base_dirs(&paths);
+    base_dirs.extend(args.watch.clone());
+    #[cfg(not(feature = "decode"))]
+    for (flag, given) in [
+        ("--verify", args.verify),
+        ("--trim-silence", args.trim_silence),
+    ] {
+        if given {
+            error!(
+                "{} is not supported by this build (enable the `decode` feature)",
+                flag
+            );
+            std::process::exit(EXIT_FAILURE);
+        }
+    }
+    let plugins = load_plugins(&args.plugin);
+    let decryption = decryption(&args);
+    let run = Run {
+        args,
+        base_dirs,
+        aborted: AtomicBool::new(false),
+        plugins,
+        decryption,
+        archive,
+        state,
+    };
+    let args = &run.args;
+
+    let reports = process_inputs(&inputs, &run, progress.as_ref());
+
+    if let Some(progress) = &progress {
+        progress.finish_all();
+    }
+
+    if let Some(dir) = &args.watch {
+        let result = watch::watch(dir, |inputs| {
+            let reports = process_inputs(inputs, &run, None);
+            write_checksums(&reports, &run);
+            if run.aborted.load(Ordering::Relaxed) {
+                std::process::exit(EXIT_FAILURE);
+            }
+        });
+        if let Err(err) = result {
+            error!("Error watching {:?}: {}", dir, err);
+        }
+        std::process::exit(EXIT_FAILURE);
+    }
+
+    let mut failed = reports.iter().any(|r| r.error.is_some());
+    failed |= !write_checksums(&reports, &run);
+
+    if let Some(archive) = &run.archive {
+        if let Err(err) = archive.finish() {
+            error!("Error writing archive {:?}: {}", archive.path(), err);
+            failed = true;
+        }
+    }
+
+    if args.json {
+        let stdout = io::stdout();
+        let mut stdout = stdout.lock();
+        if let Err(err) = serde_json::to_writer_pretty(&mut stdout, &reports)
+            .map_err(io::Error::from)
+            .and_then(|_| writeln!(stdout))
+        {
+            error!("Error writing JSON report: {}", err);
+            failed = true;
+        }
+    }
+
+    for spec in &args.report {
+        if let Err(err) = spec.write(&reports) {
+            error!("Error writing report {:?}: {}", spec.path, err);
+            failed = true;
+        }
     }
-}
 
-fn deobfs(buffer: &[u8], offset: usize) -> Vec<u8> {
-    let mut out: Vec<_> = buffer.iter().copied().collect();
-    for i in 0..out.len() - 1 {
-        if i % 4 == offset {
-            out.swap(i, i + 1);
+    let failures = report::failure_list(&reports);
+    if !failures.is_empty() {
+        let lines: Vec<_> = failures.lines().map(|line| format!("  {}", line)).collect();
+        error!(
+            "{} input(s) failed or had no audio:\n{}",
+            lines.len(),
+            lines.join("\n")
+        );
+    }
+    if let Some(path) = &args.errors_to {
+        if let Err(err) = fs::write(path, &failures) {
+            error!("Error writing {:?}: {}", path, err);
+            failed = true;
         }
     }
-    out
+
+    info!("{}", Summary::new(&reports, start.elapsed(), args.dry_run));
+
+    let code = if failed {
+        EXIT_FAILURE
+    } else if reports.iter().all(|r| r.outputs.is_empty() && !r.resumed) {
+        EXIT_NO_AUDIO
+    } else {
+        EXIT_SUCCESS
+    };
+    std::process::exit(code);
+}
+
+fn process_inputs(inputs: &[Input], run: &Run, progress: Option<&Progress>) -> Vec<FileReport> {
+    // with --ordered, inputs that finish early wait here for their turn
+    let pending = Mutex::new(Pending {
+        next: 0,
+        done: BTreeMap::new(),
+    });
+
+    inputs
+        .par_iter()
+        .enumerate()
+        .map(|(index, input)| {
+            let bar = progress.map(|p| p.start(input));
+            let process = || {
+                info_span!("input", path = %input).in_scope(|| {
+                    if run.aborted.load(Ordering::Relaxed) {
+                        let mut report = FileReport::new(input);
+                        report.skipped = Some("stopped by --fail-fast".to_owned());
+                        return report;
+                    }
+                    let report = process_file(input, run).unwrap_or_else(|err| {
+                        error!("Error processing {}: {}", input, err);
+                        FileReport::failed(input, err)
+                    });
+                    if run.args.fail_fast
+                        && report.error.is_some()
+                        && !run.aborted.swap(true, Ordering::Relaxed)
+                    {
+                        error!("Stopping after the first failure (--fail-fast)");
+                    }
+                    report
+                })
+            };
+            if !run.args.ordered {
+                let report = process();
+                if let (Some(progress), Some(bar)) = (progress, bar) {
+                    progress.finish(bar);
+                }
+                return report;
+            }
+
+            let (report, captured) = log::capture(process);
+            let mut guard = pending.lock().unwrap_or_else(|e| e.into_inner());
+            let pending = &mut *guard;
+            pending.done.insert(index, (bar, captured));
+            while let Some((bar, captured)) = pending.done.remove(&pending.next) {
+                captured.emit();
+                if let (Some(progress), Some(bar)) = (progress, bar) {
+                    progress.finish(bar);
+                }
+                pending.next += 1;
+            }
+            report
+        })
+        .collect()
 }
 
-fn extract_mp3(s: Vec<u8>) -> Vec<(Vec<u8>, usize)> {
-    // extract all mp3s found in data stream
-    // adapted from https://gist.github.com/RavuAlHemio/9376cf495c82be9c8778
-    let stream_iter = &mut s.iter();
-    let total_stream_len: usize = stream_iter.len();
+/// Inputs finished out of turn with `--ordered`, by index.
+struct Pending {
+    next: usize,
+    done: BTreeMap<usize, (Option<progress::Active>, log::Captured)>,
+}
 
-    // return value
-    let mut extracted_mp3s: Vec<(Vec<u8>, usize)> = Vec::new();
+/// Scan `input` and write what it holds. Failures of single outputs are kept
+/// in the report, and the others are still written.
+fn process_file(input: &Input, run: &Run) -> Result<FileReport, ExtractError> {
+    let start = Instant::now();
+    let args = &run.args;
+    let archive = run.archive.as_ref();
+    let mut report = FileReport::new(input);
+    let mut streamed = None;
 
-    let mut header: Vec<u8> = vec![0];
-    let data = stream_iter.take(3);
-    if data.len() < 3 {
-        return extracted_mp3s;
+    if let Input::File(path) = input {
+        let meta = fs::metadata(path).ok().filter(|m| m.is_file());
+        let len = match meta {
+            Some(meta) => meta.len(),
+            None => return Err(ExtractError::NotAFile),
+        };
+        if len < args.min_input_size {
+            debug!("skipping, only {} bytes", len);
+            report.skipped = Some("below --min-input-size".to_owned());
+            return Ok(report);
+        }
+        if let Some(max) = args.max_input_size.filter(|&max| len > max) {
+            info!(
+                "skipping {:?}, {} bytes is over the {} byte limit",
+                path, len, max
+            );
+            report.skipped = Some("over --max-input-size".to_owned());
+            return Ok(report);
+        }
+        let budget = args.max_memory.map(|mb| mb.saturating_mul(1 << 20));
+        if budget.is_some_and(|budget| len > budget) {
+            debug!(
+                "{} bytes is over the memory budget, streaming from disk",
+                len
+            );
+            streamed = Some(path);
+        }
     }
-    header.extend(data);
 
-    let mut mp3_stream: Vec<u8> = Vec::new();
-    mp3_stream.reserve(total_stream_len);
-    let mut is_mp3 = false;
+    // deobfuscate files and extract mp3s
+    let buffer: Vec<u8> = match streamed {
+        Some(_) => Vec::new(),
+        None => input.read(args.max_input_size)?,
+    };
+    if streamed.is_none() {
+        debug!("read {} bytes", buffer.len());
+    }
+    // nothing to deobfuscate or carve, and not worth a journal entry
+    if streamed.is_none() && buffer.is_empty() {
+        debug!("skipping, empty");
+        report.skipped = Some("empty input".to_owned());
+        return Ok(report);
+    }
 
-    loop {
-        if !is_mp3 {
-            if mp3_stream.len() > THRESHOLD {
-                let offset: usize = total_stream_len - stream_iter.len() - mp3_stream.len();
-                extracted_mp3s.push((mp3_stream.clone(), offset));
+    let journal = match (&run.state, input) {
+        (Some(state), Input::File(path)) => {
+            let hash = match streamed {
+                Some(path) => State::hash_file(path)?,
+                None => State::hash(&buffer),
+            };
+            if state.is_done(path, &hash) {
+                info!("skipping {:?}, already processed", path);
+                report.resumed = true;
+                return Ok(report);
             }
-            mp3_stream.clear();
+            Some((state, path, hash))
         }
+        _ => None,
+    };
 
-        is_mp3 = false;
+    let mut scan_options = ScanOptions {
+        threshold: args
+            .min_stream_size
+            .map_or(carve::DEFAULT_THRESHOLD, |n| n as usize),
+        min_frames: args.min_frames.unwrap_or(carve::DEFAULT_MIN_FRAMES),
+        deadline: args
+            .timeout
+            .map(|secs| start + Duration::from_secs_f64(secs)),
+        abandon_after: None,
+        clean: args.clean,
+        repair: args.repair,
+    };
+    let template = match &args.name {
+        Some(template) => template.clone(),
+        None => naming::DEFAULT_TEMPLATE
+            .parse()
+            .map_err(ExtractError::Name)?,
+    };
+    let mtime = match input {
+        Input::File(path) if args.preserve_times || archive.is_some() => {
+            fs::metadata(path).and_then(|m| m.modified()).ok()
+        }
+        _ => None,
+    };
+    let mut buffer = buffer;
+    if let Some(decryption) = &run.decryption {
+        match streamed {
+            Some(_) => Err("the whole input has to be in memory, raise --max-memory".to_owned()),
+            None => decryption.apply(&mut buffer),
+        }
+        .map_err(ExtractError::Decrypt)?;
+        debug!("decrypted with {}", decryption);
+    }
+    let buffer = buffer;
+    let head = match streamed {
+        Some(path) => stream::read_range(path, 0..deobfs::HEAD_SIZE)?,
+        None => buffer[..buffer.len().min(deobfs::HEAD_SIZE)].to_vec(),
+    };
+    if args.obfuscate {
+        // checked to be a single one in `Cli::into_args`
+        let deobfuscator = args.scheme.candidates(&[]).remove(0);
+        let obfuscator = deobfuscator
+            .inverse()
+            .ok_or_else(|| ExtractError::Scheme(format!("{} can't be reversed", deobfuscator)))?;
+        let name = |_| {
+            let name = input.file_name();
+            let mut name = Path::new(&name).file_stem().unwrap_or_default().to_owned();
+            name.push(".amp");
+            name
+        };
+        let streamed = streamed.map(PathBuf::as_path);
+        write_deobfuscated(
+            run,
+            input,
+            &[obfuscator],
+            &[0],
+            &name,
+            &buffer,
+            streamed,
+            mtime,
+            &mut report,
+        );
+        if args.dry_run && !args.json {
+            log::print(&listing(input, &report, args.mode));
+        }
+        return Ok(report);
+    }
+    let mut candidates = if args.bruteforce {
+        deobfs::bruteforce_candidates()
+    } else {
+        args.scheme.candidates(&head)
+    };
+    for plugin in &run.plugins {
+        candidates.push(Box::new(plugin.clone()));
+    }
+    let candidates = &candidates;
+    if candidates.is_empty() {
+        info!("no key could be guessed for {}, give one with --key", input);
+    }
+    for deobfuscator in candidates {
+        debug!("candidate {}", deobfuscator);
+    }
+    let variants = if args.bruteforce {
+        let streamed = streamed.map(PathBuf::as_path);
+        bruteforce(input, candidates, &buffer, streamed)?
+            .into_iter()
+            .collect()
+    } else if args.all_offsets {
+        (0..candidates.len()).collect()
+    } else {
+        // most promising first
+        let ranked = rank_variants(candidates, &head);
+        detect_variants(candidates, ranked, &buffer, streamed.map(PathBuf::as_path))
+    };
+    if args.deobfs_only {
+        // `<name>.deobfs`, or `<name>.<variant>.deobfs` for each of several
+        let name = |variant| {
+            let mut name = input.file_name();
+            if variants.len() > 1 {
+                name.push(format!(".{}", variant));
+            }
+            name.push(".deobfs");
+            name
+        };
+        let streamed = streamed.map(PathBuf::as_path);
+        write_deobfuscated(
+            run,
+            input,
+            candidates,
+            &variants,
+            &name,
+            &buffer,
+            streamed,
+            mtime,
+            &mut report,
+        );
+        if args.dry_run && !args.json {
+            log::print(&listing(input, &report, args.mode));
+        }
+        return Ok(report);
+    }
+    // video isn't scanned for audio, but can be written out as it is
+    let video = variants.iter().find_map(|&variant| {
+        let head = &head[..head.len().min(video::DETECT_LEN)];
+        let head: Vec<u8> = deobfs::DeobfuscatedSlice::new(head, &*candidates[variant]).collect();
+        Some((variant, video::detect(&head)?))
+    });
+    if let Some((variant, container)) = video {
+        if args.extract_video {
+            let name = |_| {
+                let mut name = input.file_name();
+                name.push(format!(".{}", container.extension()));
+                name
+            };
+            let streamed = streamed.map(PathBuf::as_path);
+            write_deobfuscated(
+                run,
+                input,
+                candidates,
+                &[variant],
+                &name,
+                &buffer,
+                streamed,
+                mtime,
+                &mut report,
+            );
+        } else {
+            info!("{} holds {} video, not extracted", input, container);
+            report.skipped = Some(format!("video content ({}), not extracted", container));
+        }
+        if args.dry_run && !args.json {
+            log::print(&listing(input, &report, args.mode));
+        }
+        return Ok(report);
+    }
+    // wrong variants are usually noise from the start; if every one is dropped
+    // that way, the most promising is scanned to the end after all
+    let full_options = scan_options.clone();
+    if variants.len() > 1 && !args.all_offsets && args.abandon_after > 0 {
+        scan_options.abandon_after = Some(args.abandon_after as usize);
+    }
+    // every variant is scanned in the same pass over the input; a single one
+    // is scanned on this thread
+    type Scanned = Vec<(usize, Result<Vec<carve::Stream>, Stopped>)>;
+    let scan = |variants: &[usize], options: &ScanOptions| -> Result<Scanned, ExtractError> {
+        if options.deadline.is_some_and(|d| Instant::now() > d) {
+            return Ok(variants
+                .iter()
+                .map(|&i| (i, Err(Stopped::TimedOut)))
+                .collect());
+        }
+        let deobfuscators: Vec<&dyn Deobfuscator> =
+            variants.iter().map(|&i| &*candidates[i]).collect();
+        let results = match (streamed, &deobfuscators[..]) {
+            (Some(path), &[deobfuscator]) => vec![stream::scan_file(path, deobfuscator, options)?],
+            (None, &[deobfuscator]) => {
+                vec![extract(
+                    deobfs::DeobfuscatedSlice::new(&buffer, deobfuscator),
+                    options,
+                )]
+            }
+            (Some(path), _) => stream::scan_all(fs::File::open(path)?, &deobfuscators, options)?,
+            (None, _) => stream::scan_all(io::Cursor::new(&buffer[..]), &deobfuscators, options)?,
+        };
+        Ok(variants.iter().copied().zip(results).collect())
+    };
+    let mut scanned = scan(&variants, &scan_options)?;
+    let abandoned = |(_, result): &(usize, _)| matches!(result, Err(Stopped::NoFrames));
+    if !variants.is_empty() && scanned.iter().all(abandoned) {
+        scanned.extend(scan(&variants[..1], &full_options)?);
+    }
+    let mut extracted = Vec::new();
+    for (i, result) in scanned {
+        let deobfuscator = &*candidates[i];
+        let mp3s = match result {
+            Ok(mp3s) => mp3s,
+            Err(Stopped::NoFrames) => {
+                debug!("abandoned {}, {}", deobfuscator, Stopped::NoFrames);
+                continue;
+            }
+            Err(stopped) => {
+                return Err(ExtractError::Scan {
+                    stopped,
+                    after: start.elapsed(),
+                })
+            }
+        };
+        debug!("{} found {} mp3(s)", deobfuscator, mp3s.len());
+        for mp3 in &mp3s {
+            debug!(
+                variant = i,
+                offset = mp3.offset,
+                size = mp3.data.len(),
+                frames = mp3.frames,
+                "found stream"
+            );
+        }
+        extracted.extend(mp3s.into_iter().map(|mp3| (i, mp3)));
+    }
+    // sort extracted mp3s by the order they appear in
+    extracted.sort_unstable_by_key(|(variant, mp3)| (mp3.offset, *variant));
+    if args.check_side_info {
+        extracted.retain(|(_, mp3)| match side_info::check(mp3) {
+            Some(verdict) if verdict.fails() => {
+                info!(
+                    "{}: withholding the stream at {:#x}, {} of {} frames have bad side information",
+                    input, mp3.offset, verdict.bad, verdict.frames
+                );
+                false
+            }
+            _ => true,
+        });
+    }
+    #[cfg(feature = "decode")]
+    if args.verify {
+        extracted.retain(|(_, stream)| match decode::verify(stream) {
+            Some(verdict) if verdict.fails() => {
+                info!(
+                    "{}: withholding the stream at {:#x}, {} of {} frames don't decode",
+                    input, stream.offset, verdict.bad, verdict.frames
+                );
+                false
+            }
+            _ => true,
+        });
+    }
+    let duplicates = dedupe::drop_duplicates(&mut extracted);
+    if duplicates > 0 {
+        info!(
+            "{}: dropped {} duplicate stream(s) found with another variant",
+            input, duplicates
+        );
+    }
+    let overlapping = dedupe::drop_overlaps(&mut extracted);
+    if overlapping > 0 {
+        info!(
+            "{}: dropped {} stream(s) overlapping one found with another variant",
+            input, overlapping
+        );
+    }
+    for (_, mp3) in &mut extracted {
+        #[cfg(feature = "decode")]
+        if args.trim_silence {
+            silence::trim(mp3);
+        }
+        if args.repair {
+            for gap in &mp3.gaps {
+                info!(
+                    "{}: left {} damaged bytes at {:#x} out of the stream at {:#x}",
+                    input, gap.len, gap.offset, mp3.offset
+                );
+            }
+        }
+        // a stream cut short keeps its encoder's header, with the counts fixed
+        if !xing::update_header(mp3) && args.xing_header {
+            xing::add_header(mp3);
+        }
+        mp3.gapless = mp3.gapless.or_else(|| xing::gapless(&mp3.data));
+        if let Some(remux) = args.remux {
+            if mp3.format == carve::Format::Adts && !remux.apply(mp3) {
+                debug!(
+                    "{:#x}: couldn't remux the ADTS stream, kept as it is",
+                    mp3.offset
+                );
+            }
+        }
+    }
+
+    if extracted.is_empty() {
+        let sample = if streamed.is_some() { &head } else { &buffer };
+        if run.decryption.is_some() && entropy::looks_encrypted(sample) {
+            info!(
+                "{} still looks encrypted, check the key, IV and --aes-mode",
+                input
+            );
+        } else if entropy::looks_encrypted(sample) {
+            info!(
+                "{} looks encrypted ({:.3} bits/byte), not obfuscated; a key is required",
+                input,
+                entropy::bits_per_byte(sample)
+            );
+            report.encrypted = true;
+        }
+    }
+
+    let output_report = |path: Option<PathBuf>, variant: usize, mp3: &carve::Stream| OutputReport {
+        path,
+        variant,
+        deobfuscation: candidates[variant].to_string(),
+        format: mp3.format.to_string(),
+        offset: mp3.offset,
+        size: mp3.data.len(),
+        frames: mp3.frames,
+        bit_rate: mp3.bit_rate,
+        sample_rate: mp3.sample_rate,
+        duration: mp3.duration(),
+        channel_mode: mp3.channel_mode.map(|mode| mode.to_string()),
+        truncated: mp3.truncated,
+        tags: mp3.tags.iter().map(ToString::to_string).collect(),
+        corrupt_frames: mp3.corrupt_frames,
+        dropped_bytes: mp3.gaps.iter().map(|gap| gap.len).sum(),
+        encoder_delay: mp3.gapless.map(|gapless| gapless.delay),
+        encoder_padding: mp3.gapless.map(|gapless| gapless.padding),
+        skipped: false,
+        checksum: None,
+        art: None,
+    };
 
-        // read header_num
-        header.remove(0);
-        let x = *match stream_iter.next() {
-            Some(v) => v,
-            None => break,
+    if args.stdout {
+        if extracted.len() != 1 {
+            return Err(ExtractError::StreamCount(extracted.len()));
+        }
+        let (variant, mp3) = &extracted[0];
+        info!("writing {} to stdout", input);
+        let stdout = io::stdout();
+        let mut stdout = stdout.lock();
+        stdout
+            .write_all(&mp3.data)
+            .and_then(|_| stdout.flush())
+            .map_err(|source| ExtractError::Output { path: None, source })?;
+        report.outputs.push(output_report(None, *variant, mp3));
+        return Ok(report);
+    }
+
+    // write mp3s to file
+    let mut tag_names: HashMap<OsString, usize> = HashMap::new();
+    for (i, (variant, mp3)) in extracted.iter().enumerate() {
+        let path_out = {
+            let tag = if args.name_from_tags {
+                let deobfuscator = &*candidates[*variant];
+                id3::find_at_start(&mp3.data).or_else(|| {
+                    find_tag(
+                        deobfuscator,
+                        &buffer,
+                        streamed.map(PathBuf::as_path),
+                        mp3.offset,
+                    )
+                })
+            } else {
+                None
+            };
+            let ext = mp3.format.extension();
+            let tag_name = tag.and_then(|tag| {
+                let first = naming::from_tag(&tag, 1, ext)?;
+                let copies = tag_names.entry(first).or_insert(0);
+                *copies += 1;
+                naming::from_tag(&tag, *copies, ext)
+            });
+            let filename_out = tag_name.unwrap_or_else(|| {
+                template.render(&NameContext {
+                    name: &input.file_name(),
+                    index: i + 1,
+                    offset: mp3.offset,
+                    variant: *variant,
+                    ext,
+                })
+            });
+            output_path(run, input, filename_out)
+        };
+        let output_error = |source| ExtractError::Output {
+            path: Some(path_out.clone()),
+            source,
         };
-        header.push(x);
-        let header_num = u32::from(header[0]) << 24
-            | u32::from(header[1]) << 16
-            | u32::from(header[2]) << 8
-            | u32::from(header[3]);
-
-        // frame sync
-        if header_num & 0xFFE00000 != 0xFFE00000 {
+        if let Err(err) = create_output_dir(run, &path_out) {
+            report.error.get_or_insert(output_error(err));
+            if args.fail_fast {
+                break;
+            }
             continue;
         }
 
-        // MPEG version
-        let mpeg_version = (header_num & 0x00180000) >> 19;
-        if mpeg_version == 0b01 || mpeg_version != 0b11 {
-            continue;
+        let mut output = output_report(Some(path_out.clone()), *variant, mp3);
+        if let Some(archive) = archive {
+            let name = path_out.to_string_lossy();
+            match archive.add(&name, &mp3.data, mtime) {
+                Ok(()) => info!("adding {} to {:?}", name, archive.path()),
+                Err(err) => {
+                    error!("Error adding {} to {:?}: {}", name, archive.path(), err);
+                    report.error.get_or_insert(output_error(err));
+                    if args.fail_fast {
+                        break;
+                    }
+                    continue;
+                }
+            }
+        } else if !args.dry_run {
+            match output::write_output(&path_out, &mp3.data, args.overwrite(), mtime) {
+                Ok(WriteOutcome::Written) => info!(
+                    "writing {:?} ({}, {})",
+                    path_out,
+                    output.summary(),
+                    output.deobfuscation
+                ),
+                Ok(WriteOutcome::Renamed(path)) => {
+                    info!(
+                        "writing {:?} ({}, {})",
+                        path,
+                        output.summary(),
+                        output.deobfuscation
+                    );
+                    output.path = Some(path);
+                }
+                Ok(WriteOutcome::Skipped) => {
+                    info!("skipping existing {:?}", path_out);
+                    output.skipped = true;
+                }
+                Err(err) => {
+                    error!("Error writing {:?}: {}", path_out, err);
+                    report.error.get_or_insert(output_error(err));
+                    if args.fail_fast {
+                        break;
+                    }
+                    continue;
+                }
+            }
+        }
+        if let Some(algorithm) = args.checksums.filter(|_| !args.dry_run && !output.skipped) {
+            let hash = algorithm.digest(&mp3.data);
+            if args.checksum_files {
+                if let Err(err) = write_checksum_file(&output, &hash, algorithm, archive, mtime) {
+                    error!("Error writing checksum of {:?}: {}", path_out, err);
+                    report.error.get_or_insert(output_error(err));
+                }
+            }
+            output.checksum = Some(hash);
+        }
+        if let Some(picture) = metadata::cover(&mp3.data).filter(|_| args.extract_art) {
+            match write_next_to(&output, picture.extension(), &picture.data, run, mtime) {
+                Ok(art) => output.art = art,
+                Err(err) => {
+                    error!("Error writing cover art of {:?}: {}", path_out, err);
+                    report.error.get_or_insert(output_error(err));
+                }
+            }
         }
+        if args.info_file {
+            let mut json = serde_json::to_vec_pretty(&output).map_err(io::Error::from);
+            if let Ok(json) = &mut json {
+                json.push(b'\n');
+            }
+            let written = json.and_then(|json| write_next_to(&output, "json", &json, run, mtime));
+            if let Err(err) = written {
+                error!("Error writing stream info of {:?}: {}", path_out, err);
+                report.error.get_or_insert(output_error(err));
+            }
+        }
+        report.outputs.push(output);
+    }
 
-        // MPEG layer
-        let mpeg_layer = (header_num & 0x00060000) >> 17;
-        if mpeg_layer == 0b00 || mpeg_layer != 0b01 {
-            continue;
+    if args.dry_run && !args.json {
+        log::print(&listing(input, &report, args.mode));
+    }
+
+    if let Some((state, path, hash)) = journal.filter(|_| report.error.is_none() && !args.dry_run) {
+        if let Err(err) = state.record(path, &hash) {
+            error!("Error updating state file: {}", err);
         }
+    }
 
-        // bitrate
-        let bit_rate_idx = (header_num & 0x0000F000) >> 12;
-        if bit_rate_idx == 0b0000 || bit_rate_idx == 0b1111 {
-            continue;
+    if let Input::File(path) = input {
+        if report.error.is_none() && !report.outputs.is_empty() {
+            clean_up_source(path, &mut report, args);
+        }
+    }
+
+    Ok(report)
+}
+
+/// `amp-extract schemes`: the scheme registry.
+fn print_schemes() {
+    for scheme in deobfs::REGISTRY {
+        println!("{}", scheme.name);
+        println!("    {}", scheme.description);
+        match scheme.build {
+            deobfs::Build::Alias(spec) => println!("    same as --scheme {}", spec),
+            deobfs::Build::Options { options: "", .. } => (),
+            deobfs::Build::Options { options, .. } => println!("    options: {}", options),
+        }
+    }
+    for (name, what) in deobfs::UNSUPPORTED {
+        println!("{}", name);
+        println!("    {}", what);
+        println!("    not supported");
+    }
+}
+
+/// The decryption asked for with `--aes-key` or `--aes-key-file`; exits if
+/// the key can't be used.
+fn decryption(args: &Args) -> Option<decrypt::Decryption> {
+    let key = match (&args.aes_key, &args.aes_key_file) {
+        (Some(key), _) => key.0.clone(),
+        (None, Some(path)) => match decrypt::read_key_file(path) {
+            Ok(key) => key,
+            Err(err) => {
+                error!("Error reading key file {:?}: {}", path, err);
+                std::process::exit(EXIT_FAILURE);
+            }
+        },
+        (None, None) => return None,
+    };
+    let iv = args.aes_iv.clone().map(|iv| iv.0);
+    match decrypt::Decryption::new(args.aes_mode, key, iv) {
+        Ok(decryption) => Some(decryption),
+        Err(err) => {
+            error!("{}", err);
+            std::process::exit(EXIT_FAILURE);
         }
-        let bit_rate = match get_bit_rate(bit_rate_idx) {
-            Some(val) => val,
-            None => continue,
+    }
+}
+
+/// The deobfuscators given with `--plugin`; exits if one can't be loaded.
+fn load_plugins(paths: &[PathBuf]) -> Vec<Arc<dyn Deobfuscator>> {
+    #[cfg(feature = "plugin")]
+    {
+        let mut plugins: Vec<Arc<dyn Deobfuscator>> = Vec::new();
+        for path in paths {
+            match plugin::Plugin::load(path) {
+                Ok(plugin) => plugins.push(Arc::new(plugin)),
+                Err(err) => {
+                    error!("{:?}: {}", path, err);
+                    std::process::exit(EXIT_FAILURE);
+                }
+            }
+        }
+        plugins
+    }
+    #[cfg(not(feature = "plugin"))]
+    {
+        if !paths.is_empty() {
+            error!("--plugin is not supported by this build (enable the `plugin` feature)");
+            std::process::exit(EXIT_FAILURE);
+        }
+        Vec::new()
+    }
+}
+
+/// Where an output named `filename` of `input` goes (within the archive, if
+/// there is one).
+fn output_path(run: &Run, input: &Input, filename: OsString) -> PathBuf {
+    let args = &run.args;
+    let sub_dir = input.relative_dir(&run.base_dirs).filter(|_| args.mirror);
+    match (&run.archive, &args.output_dir, sub_dir) {
+        (Some(_), _, Some(sub_dir)) => sub_dir.join(filename),
+        (Some(_), _, None) => PathBuf::from(filename),
+        (None, Some(dir), Some(sub_dir)) => dir.join(sub_dir).join(filename),
+        (None, output_dir, _) => input.output_path(&filename, output_dir.as_deref()),
+    }
+}
+
+/// Create the directory `path` goes in, if `--mirror` may need a new one.
+fn create_output_dir(run: &Run, path: &Path) -> io::Result<()> {
+    let args = &run.args;
+    let creates_dirs = args.mirror && run.archive.is_none() && !args.dry_run;
+    match path.parent().filter(|_| creates_dirs) {
+        Some(dir) => fs::create_dir_all(dir).map_err(|err| {
+            error!("Error creating directory {:?}: {}", dir, err);
+            err
+        }),
+        None => Ok(()),
+    }
+}
+
+/// For `--deobfs-only` and `--obfuscate`: write the whole input transformed
+/// by each of `variants` to a file named by `name` (given the variant).
+#[allow(clippy::too_many_arguments)]
+fn write_deobfuscated(
+    run: &Run,
+    input: &Input,
+    candidates: &[Box<dyn Deobfuscator>],
+    variants: &[usize],
+    name: &dyn Fn(usize) -> OsString,
+    buffer: &[u8],
+    streamed: Option<&Path>,
+    mtime: Option<std::time::SystemTime>,
+    report: &mut FileReport,
+) {
+    let args = &run.args;
+    let len = match streamed {
+        Some(path) => fs::metadata(path).map(|m| m.len() as usize).unwrap_or(0),
+        None => buffer.len(),
+    };
+    for &variant in variants {
+        let deobfuscator = &*candidates[variant];
+        let file_name = name(variant);
+        // `deobfs` or `amp`
+        let format = Path::new(&file_name)
+            .extension()
+            .unwrap_or_default()
+            .to_string_lossy();
+        let format = format.into_owned();
+        let path = output_path(run, input, file_name);
+        let mut output = OutputReport {
+            path: Some(path.clone()),
+            variant,
+            deobfuscation: deobfuscator.to_string(),
+            format,
+            offset: 0,
+            size: len,
+            frames: 0,
+            bit_rate: 0,
+            sample_rate: 0,
+            duration: 0.0,
+            channel_mode: None,
+            truncated: false,
+            tags: Vec::new(),
+            corrupt_frames: 0,
+            dropped_bytes: 0,
+            encoder_delay: None,
+            encoder_padding: None,
+            skipped: false,
+            checksum: None,
+            art: None,
         };
 
-        // sample rate
-        let sample_rate_idx = (header_num & 0x00000C00) >> 10;
-        if sample_rate_idx == 0b11 {
-            continue;
+        if !args.dry_run {
+            let output_error = |source| ExtractError::Output {
+                path: Some(path.clone()),
+                source,
+            };
+            if let Err(err) = create_output_dir(run, &path) {
+                report.error.get_or_insert(output_error(err));
+                return;
+            }
+            let write = |file: &mut fs::File| match streamed {
+                Some(input) => {
+                    let mut data = deobfs::DeobfuscatedFile::open(input, deobfuscator)?;
+                    io::copy(&mut data, file).map(|_| ())
+                }
+                None => file.write_all(&deobfs::deobfuscate(deobfuscator, buffer)),
+            };
+            match output::write_output_with(&path, &write, args.overwrite(), mtime) {
+                Ok(WriteOutcome::Written) => info!("writing {:?} ({})", path, deobfuscator),
+                Ok(WriteOutcome::Renamed(renamed)) => {
+                    info!("writing {:?} ({})", renamed, deobfuscator);
+                    output.path = Some(renamed);
+                }
+                Ok(WriteOutcome::Skipped) => {
+                    info!("skipping existing {:?}", path);
+                    output.skipped = true;
+                }
+                Err(err) => {
+                    error!("Error writing {:?}: {}", path, err);
+                    report.error.get_or_insert(output_error(err));
+                    return;
+                }
+            }
+        }
+        report.outputs.push(output);
+    }
+}
+
+/// Write the single-file checksum of `output`, into `archive` if there is one.
+fn write_checksum_file(
+    output: &OutputReport,
+    hash: &str,
+    algorithm: checksum::Algorithm,
+    archive: Option<&Archive>,
+    mtime: Option<std::time::SystemTime>,
+) -> io::Result<()> {
+    let path = match &output.path {
+        Some(path) => path,
+        None => return Ok(()),
+    };
+    match archive {
+        Some(archive) => {
+            let name = path.file_name().unwrap_or_default();
+            let sidecar = checksum::sidecar_path(path, algorithm);
+            let line = checksum::line(hash, name);
+            archive.add(&sidecar.to_string_lossy(), &line, mtime)
+        }
+        None => checksum::write_sidecar(path, hash, algorithm),
+    }
+}
+
+/// Write `data` next to `output`, e.g. its cover art, with the extension
+/// `extension` instead of the output's. Returns where, `None` if it wasn't
+/// written.
+fn write_next_to(
+    output: &OutputReport,
+    extension: &str,
+    data: &[u8],
+    run: &Run,
+    mtime: Option<std::time::SystemTime>,
+) -> io::Result<Option<PathBuf>> {
+    let path = match &output.path {
+        Some(path) if !output.skipped => path.with_extension(extension),
+        _ => return Ok(None),
+    };
+    match &run.archive {
+        Some(archive) => {
+            let name = path.to_string_lossy();
+            archive.add(&name, data, mtime)?;
+            info!("adding {} to {:?}", name, archive.path());
         }
-        let sample_rate = match get_sample_rate(sample_rate_idx) {
-            Some(val) => val,
-            None => continue,
+        None if run.args.dry_run => {}
+        None => match output::write_output(&path, data, run.args.overwrite(), mtime)? {
+            WriteOutcome::Written => info!("writing {:?}", path),
+            WriteOutcome::Renamed(renamed) => {
+                info!("writing {:?}", renamed);
+                return Ok(Some(renamed));
+            }
+            WriteOutcome::Skipped => {
+                info!("skipping existing {:?}", path);
+                return Ok(None);
+            }
+        },
+    }
+    Ok(Some(path))
+}
+
+/// Update the checksum files for every output in `reports` as requested with
+/// `--checksums`. Returns whether that worked.
+fn write_checksums(reports: &[FileReport], run: &Run) -> bool {
+    let algorithm = match run.args.checksums {
+        Some(algorithm) if !run.args.checksum_files => algorithm,
+        _ => return true,
+    };
+    let files = reports
+        .iter()
+        .flat_map(|r| &r.outputs)
+        .filter_map(|o| Some((o.path.as_deref()?, o.checksum.as_deref()?)));
+
+    let result = match &run.archive {
+        Some(archive) => {
+            let entries = files
+                .map(|(path, hash)| (path.as_os_str().to_owned(), hash.to_owned()))
+                .collect();
+            let sums = checksum::sums(&entries);
+            archive.add(algorithm.sums_file(), &sums, None)
+        }
+        None => checksum::update_sums(files, algorithm),
+    };
+    if let Err(err) = &result {
+        error!("Error writing {}: {}", algorithm.sums_file(), err);
+    }
+    result.is_ok()
+}
+
+/// What was found in `input`, as printed by `scan`, `info` and `--dry-run`.
+fn listing(input: &Input, report: &FileReport, mode: Mode) -> String {
+    let mut listing = format!("{}: {} mp3(s)\n", input, report.outputs.len());
+    for (i, output) in report.outputs.iter().enumerate() {
+        let line = match mode {
+            Mode::Extract => format!(
+                "offset {:#x}, {} bytes, {}, {}",
+                output.offset,
+                output.size,
+                output.summary(),
+                output.deobfuscation
+            ),
+            Mode::Scan => {
+                let mut line = format!(
+                    "offset {:#x}, {} bytes, {}, {} kbps, {} Hz, {:.1}s, {}",
+                    output.offset,
+                    output.size,
+                    output.format,
+                    output.bit_rate / 1000,
+                    output.sample_rate,
+                    output.duration,
+                    output.deobfuscation
+                );
+                if let Some(channel_mode) = &output.channel_mode {
+                    line.push_str(&format!(", {}", channel_mode));
+                }
+                if !output.tags.is_empty() {
+                    line.push_str(&format!(", {} tags", output.tags.join("+")));
+                }
+                if let (Some(delay), Some(padding)) = (output.encoder_delay, output.encoder_padding)
+                {
+                    line.push_str(&format!(", {}+{} gapless samples", delay, padding));
+                }
+                if output.corrupt_frames > 0 {
+                    line.push_str(&format!(", {} corrupt frames", output.corrupt_frames));
+                }
+                if output.dropped_bytes > 0 {
+                    line.push_str(&format!(", {} bytes left out", output.dropped_bytes));
+                }
+                if output.truncated {
+                    line.push_str(", truncated");
+                }
+                line
+            }
+            Mode::Info => format!(
+                "{} frames, {} kbps average, {} Hz, {:.1}s",
+                output.frames,
+                output.bit_rate / 1000,
+                output.sample_rate,
+                output.duration
+            ),
         };
+        listing.push_str(&format!("  {}: {}\n", i + 1, line));
+    }
+    listing
+}
 
-        // padding?
-        let has_padding = ((header_num & 0x00000200) >> 9) == 0b1;
+/// Remove or archive a fully extracted input, as requested with
+/// `--remove-source` or `--move-source`.
+fn clean_up_source(path: &Path, report: &mut FileReport, args: &Args) {
+    if !args.remove_source && args.move_source.is_none() {
+        return;
+    }
 
-        // emphasis
-        let emphasis = header_num & 0x00000003;
-        if emphasis == 0b10 {
-            continue;
+    let outputs = report
+        .outputs
+        .iter()
+        .filter_map(|o| Some((o.path.as_deref()?, o.size as u64)));
+    if let Err(err) = output::verify_outputs(outputs) {
+        error!("Keeping {:?}, could not verify outputs: {}", path, err);
+        report.error = Some(ExtractError::CleanUp(err));
+        return;
+    }
+
+    let result = match &args.move_source {
+        Some(dir) => output::move_into(path, dir).map(|dest| {
+            info!("moved {:?} to {:?}", path, dest);
+        }),
+        None => fs::remove_file(path).map(|_| info!("removed {:?}", path)),
+    };
+    if let Err(err) = result {
+        error!("Error cleaning up {:?}: {}", path, err);
+        report.error = Some(ExtractError::CleanUp(err));
+    }
+}
+
+/// Those of `variants` (indices of `candidates`) worth a full scan of the
+/// input, judged by how many runs of valid MP3 frames turn up in a sample of it
+/// with each, best first; ties keep their order. Falls back to all of them
+/// when the sample doesn't settle it.
+fn detect_variants(
+    candidates: &[Box<dyn Deobfuscator>],
+    mut variants: Vec<usize>,
+    buffer: &[u8],
+    streamed: Option<&Path>,
+) -> Vec<usize> {
+    if candidates.len() < 2 {
+        return variants;
+    }
+    let scores = match sample_scores(candidates, buffer, streamed, 32) {
+        Ok(scores) => scores,
+        Err(err) => {
+            debug!("can't sample input, trying all variants: {}", err);
+            return variants;
         }
+    };
+    debug!("frame runs found in sample per variant: {:?}", scores);
+
+    let best = scores.iter().copied().max().unwrap_or(0);
+    if best == 0 {
+        return variants;
+    }
+    variants.retain(|&v| scores[v] * 2 >= best);
+    variants.sort_by_key(|&v| std::cmp::Reverse(scores[v]));
+    variants
+}
 
-        // at this point, it's an MP3 file
-        is_mp3 = true;
+/// Indices of `candidates` in order of how likely they look to be right,
+/// judged by cheap statistics of the start of the input (`head`)
+/// deobfuscated with each: many MP3 sync-like byte pairs first, then low
+/// entropy.
+fn rank_variants(candidates: &[Box<dyn Deobfuscator>], head: &[u8]) -> Vec<usize> {
+    let stats: Vec<(usize, f64)> = candidates
+        .iter()
+        .map(|deobfuscator| {
+            let data = deobfs::deobfuscate(&**deobfuscator, head);
+            let syncs = data
+                .windows(2)
+                .filter(|pair| pair[0] == 0xFF && pair[1] & 0xE0 == 0xE0)
+                .count();
+            (syncs, entropy::bits_per_byte(&data))
+        })
+        .collect();
+    let mut ranked: Vec<usize> = (0..candidates.len()).collect();
+    ranked.sort_by(|&a, &b| {
+        let (a, b) = (stats[a], stats[b]);
+        b.0.cmp(&a.0).then(a.1.total_cmp(&b.1))
+    });
+    ranked
+}
 
-        // calculate frame length
-        let frame_length = (144 * bit_rate / sample_rate
-            + match has_padding {
-                true => 1,
-                false => 0,
-            }) as usize;
+/// For `--bruteforce`: the one of `candidates` that turns up the most runs of
+/// valid MP3 frames in a sample of the input, if any turns up some at all.
+fn bruteforce(
+    input: &Input,
+    candidates: &[Box<dyn Deobfuscator>],
+    buffer: &[u8],
+    streamed: Option<&Path>,
+) -> io::Result<Option<usize>> {
+    let scores = sample_scores(candidates, buffer, streamed, 8)?;
+    let mut ranking: Vec<usize> = (0..candidates.len()).filter(|&i| scores[i] > 0).collect();
+    ranking.sort_by_key(|&i| std::cmp::Reverse(scores[i]));
+    for &i in ranking.iter().take(5) {
+        debug!("{} frame runs with {}", scores[i], candidates[i]);
+    }
 
-        // append frame
-        let frame_data = stream_iter.take(frame_length - 4);
-        if frame_data.len() < frame_length - 4 {
-            break;
+    match ranking.first() {
+        Some(&best) => {
+            info!(
+                "{}: best of {} transforms is {} ({} frame runs in sample)",
+                input,
+                candidates.len(),
+                candidates[best],
+                scores[best]
+            );
+            Ok(Some(best))
+        }
+        None => {
+            info!(
+                "{}: none of {} transforms turned up MP3 frames",
+                input,
+                candidates.len()
+            );
+            Ok(None)
         }
-        mp3_stream.extend(header.clone());
-        mp3_stream.extend(frame_data);
+    }
+}
+
+/// How many runs of valid MP3 frames each of `candidates` turns up in up to
+/// `windows` evenly spread 64 KiB windows of the input.
+fn sample_scores(
+    candidates: &[Box<dyn Deobfuscator>],
+    buffer: &[u8],
+    streamed: Option<&Path>,
+    windows: usize,
+) -> io::Result<Vec<usize>> {
+    const WINDOW_SIZE: usize = 64 << 10;
+    // a single frame header can appear by chance; runs of three rarely do
+    const RUN: usize = 3;
+
+    let len = match streamed {
+        Some(path) => fs::metadata(path)?.len() as usize,
+        None => buffer.len(),
+    };
+    let (count, size, step) = if len <= windows * WINDOW_SIZE {
+        (1, len, 0)
+    } else {
+        (windows, WINDOW_SIZE, (len - WINDOW_SIZE) / (windows - 1))
+    };
+    let windows: Vec<_> = (0..count).map(|i| i * step..i * step + size).collect();
 
-        // prepare for next scan-read
-        header.clear();
-        header.push(0);
-        let data = stream_iter.take(3);
-        if data.len() < 3 {
-            break;
+    let mut scores = vec![0; candidates.len()];
+    for (deobfuscator, score) in candidates.iter().zip(&mut scores) {
+        for window in &windows {
+            let data =
+                deobfs::deobfuscate_range(&**deobfuscator, buffer, streamed, window.clone())?;
+            *score += mp3::count_frame_runs(&data, RUN);
         }
-        header.extend(data);
     }
+    Ok(scores)
+}
 
-    extracted_mp3s
+/// The ID3v2 tag in front of the stream at `stream_offset`, if any, for
+/// `--name-from-tags`. `streamed` is set if `buffer` wasn't read into memory.
+fn find_tag(
+    deobfuscator: &dyn Deobfuscator,
+    buffer: &[u8],
+    streamed: Option<&Path>,
+    stream_offset: usize,
+) -> Option<id3::Tag> {
+    let range = stream_offset.saturating_sub(id3::MAX_TAG_SIZE)..stream_offset;
+    match deobfs::deobfuscate_range(deobfuscator, buffer, streamed, range) {
+        Ok(data) => id3::find_before(&data),
+        Err(err) => {
+            error!("Error reading tags: {}", err);
+            None
+        }
+    }
 }
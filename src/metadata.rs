@@ -0,0 +1,95 @@
+//! Metadata carried inside carved streams, beyond what naming needs: for
+//! now the cover art of an ID3v2 tag, from its `APIC` (or v2.2 `PIC`) frames.
+
+use crate::id3;
+
+/// Picture type of the front cover, preferred over the other pictures.
+const FRONT_COVER: u8 = 3;
+
+/// An attached picture.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Picture {
+    /// MIME type as given, e.g. `image/jpeg`; `JPG` or `PNG` in ID3v2.2
+    pub mime: String,
+    /// Front cover, back cover, artist, ... as numbered by ID3v2
+    pub picture_type: u8,
+    pub data: Vec<u8>,
+}
+
+impl Picture {
+    /// File extension for the picture, from its contents or else its MIME
+    /// type; `bin` if neither is known.
+    pub fn extension(&self) -> &'static str {
+        let data = &self.data;
+        if data.starts_with(&[0xFF, 0xD8, 0xFF]) {
+            return "jpg";
+        }
+        if data.starts_with(b"\x89PNG\r\n\x1a\n") {
+            return "png";
+        }
+        if data.starts_with(b"GIF8") {
+            return "gif";
+        }
+        if data.starts_with(b"BM") {
+            return "bmp";
+        }
+        if data.starts_with(b"RIFF") && data.get(8..12) == Some(b"WEBP") {
+            return "webp";
+        }
+        let mime = self.mime.to_ascii_lowercase();
+        match mime.trim_start_matches("image/") {
+            "jpeg" | "jpg" => "jpg",
+            "png" => "png",
+            "gif" => "gif",
+            "bmp" => "bmp",
+            "webp" => "webp",
+            _ => "bin",
+        }
+    }
+}
+
+/// The cover of the ID3v2 tag `data` starts with, as a stream carved with its
+/// tag does: the front cover, or else the first picture.
+pub fn cover(data: &[u8]) -> Option<Picture> {
+    let size = id3::tag_size(data)?;
+    let pictures: Vec<Picture> = id3::frames(data.get(..size)?)?
+        .iter()
+        .filter_map(|frame| match &frame.id[..] {
+            b"APIC" => parse_apic(&frame.data, false),
+            b"PIC" => parse_apic(&frame.data, true),
+            _ => None,
+        })
+        .collect();
+    let front = pictures
+        .iter()
+        .position(|picture| picture.picture_type == FRONT_COVER);
+    pictures.into_iter().nth(front.unwrap_or(0))
+}
+
+/// Parse an `APIC` frame; `PIC` frames of ID3v2.2 give a three character
+/// image format instead of a MIME type.
+fn parse_apic(frame: &[u8], v2_2: bool) -> Option<Picture> {
+    let (&encoding, rest) = frame.split_first()?;
+    let (mime, rest) = if v2_2 {
+        (rest.get(..3)?, rest.get(3..)?)
+    } else {
+        let end = rest.iter().position(|&b| b == 0)?;
+        (&rest[..end], &rest[end + 1..])
+    };
+    let (&picture_type, rest) = rest.split_first()?;
+    // the description ends with a terminator as wide as the encoding's units
+    let description_len = match encoding {
+        0 | 3 => rest.iter().position(|&b| b == 0)? + 1,
+        1 | 2 => rest.chunks_exact(2).position(|unit| unit == [0, 0])? * 2 + 2,
+        _ => return None,
+    };
+    let data = rest[description_len..].to_vec();
+    if data.is_empty() {
+        return None;
+    }
+    Some(Picture {
+        mime: mime.iter().map(|&b| char::from(b)).collect(),
+        picture_type,
+        data,
+    })
+}
@@ -0,0 +1,658 @@
+use std::convert::TryFrom;
+use std::fmt;
+use tracing::trace;
+
+use crate::bits::Bits;
+use crate::carve::{ChannelMode, Format, FrameInfo, Framed, Source};
+
+static MP3_BIT_RATES: [u32; 14] = [
+    32000, 40000, 48000, 56000, 64000, 80000, 96000, 112000, 128000, 160000, 192000, 224000,
+    256000, 320000,
+];
+static MP1_BIT_RATES: [u32; 14] = [
+    32000, 64000, 96000, 128000, 160000, 192000, 224000, 256000, 288000, 320000, 352000, 384000,
+    416000, 448000,
+];
+static MP2_BIT_RATES: [u32; 14] = [
+    32000, 48000, 56000, 64000, 80000, 96000, 112000, 128000, 160000, 192000, 224000, 256000,
+    320000, 384000,
+];
+static MPEG2_MP1_BIT_RATES: [u32; 14] = [
+    32000, 48000, 56000, 64000, 80000, 96000, 112000, 128000, 144000, 160000, 176000, 192000,
+    224000, 256000,
+];
+// MPEG-2 and MPEG-2.5 share their Layer II and III bitrates
+static MPEG2_BIT_RATES: [u32; 14] = [
+    8000, 16000, 24000, 32000, 40000, 48000, 56000, 64000, 80000, 96000, 112000, 128000, 144000,
+    160000,
+];
+// sample rates by version (MPEG-1, MPEG-2, MPEG-2.5) and sample rate index;
+// each version halves the rates of the one before
+static SAMPLE_RATES: [[u32; 3]; 3] = [
+    [44100, 48000, 32000],
+    [22050, 24000, 16000],
+    [11025, 12000, 8000],
+];
+
+/// MPEG audio version of a frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Version {
+    Mpeg1,
+    Mpeg2,
+    /// The unofficial extension of MPEG-2 to lower sample rates
+    Mpeg25,
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Version::Mpeg1 => write!(f, "MPEG-1"),
+            Version::Mpeg2 => write!(f, "MPEG-2"),
+            Version::Mpeg25 => write!(f, "MPEG-2.5"),
+        }
+    }
+}
+
+/// MPEG audio layer of a frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Layer {
+    L1,
+    L2,
+    L3,
+}
+
+impl fmt::Display for Layer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Layer::L1 => write!(f, "Layer I"),
+            Layer::L2 => write!(f, "Layer II"),
+            Layer::L3 => write!(f, "Layer III"),
+        }
+    }
+}
+
+fn get_bit_rate(version: Version, layer: Layer, i: u32) -> Option<u32> {
+    let min = 0b0001;
+    let i = i.checked_sub(min)?;
+    let i = usize::try_from(i).ok()?;
+    let table = match (version, layer) {
+        (Version::Mpeg1, Layer::L1) => &MP1_BIT_RATES,
+        (Version::Mpeg1, Layer::L2) => &MP2_BIT_RATES,
+        (Version::Mpeg1, Layer::L3) => &MP3_BIT_RATES,
+        (_, Layer::L1) => &MPEG2_MP1_BIT_RATES,
+        (_, Layer::L2 | Layer::L3) => &MPEG2_BIT_RATES,
+    };
+    table.get(i).copied()
+}
+
+fn get_sample_rate(version: Version, i: u32) -> Option<u32> {
+    let min = 0b00;
+    let i = i.checked_sub(min)?;
+    let i = usize::try_from(i).ok()?;
+    let table = match version {
+        Version::Mpeg1 => &SAMPLE_RATES[0],
+        Version::Mpeg2 => &SAMPLE_RATES[1],
+        Version::Mpeg25 => &SAMPLE_RATES[2],
+    };
+    table.get(i).copied()
+}
+
+/// The parts of an MPEG audio frame header that matter for carving.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameHeader {
+    pub version: Version,
+    pub layer: Layer,
+    /// Whether the bitrate isn't in the header but agreed on by the stream
+    pub free_format: bool,
+    /// 0 for free format frames until `with_free_length` is known
+    pub bit_rate: u32,
+    pub sample_rate: u32,
+    pub padding: bool,
+    /// Length of the whole frame, header included; 0 for free format frames,
+    /// whose length has to be measured
+    pub length: usize,
+    /// Samples (per channel) the frame decodes to
+    pub samples: u32,
+    pub channel_mode: ChannelMode,
+    /// For joint stereo, which bands are coded jointly (Layer I and II) or
+    /// which of intensity and M/S stereo is used (Layer III)
+    pub mode_extension: u8,
+    /// Whether a CRC-16 follows the header
+    pub protected: bool,
+    /// The CRC didn't match, or the header was damaged; only known once the
+    /// frame is read, by `Mpeg`
+    pub corrupt: bool,
+}
+
+/// Samples per frame, and the factor and slot size (in bytes) turning the
+/// bitrate over the sample rate into a frame length.
+fn frame_layout(version: Version, layer: Layer) -> (u32, u32, u32) {
+    match (version, layer) {
+        (_, Layer::L1) => (384, 12, 4),
+        (_, Layer::L2) | (Version::Mpeg1, Layer::L3) => (1152, 144, 1),
+        (Version::Mpeg2 | Version::Mpeg25, Layer::L3) => (576, 72, 1),
+    }
+}
+
+impl FrameHeader {
+    pub fn is_free_format(&self) -> bool {
+        self.free_format
+    }
+
+    /// Length of the padding slot, if the frame has one.
+    fn padding_len(&self) -> usize {
+        match self.padding {
+            true => frame_layout(self.version, self.layer).2 as usize,
+            false => 0,
+        }
+    }
+
+    /// Length of the side information of a Layer III frame, which follows the
+    /// header (and CRC).
+    pub fn side_info_len(&self) -> usize {
+        match (self.version, self.channel_mode) {
+            (Version::Mpeg1, ChannelMode::Mono) => 17,
+            (Version::Mpeg1, _) => 32,
+            (_, ChannelMode::Mono) => 9,
+            (_, _) => 17,
+        }
+    }
+
+    /// Length of what follows the side information in a Layer III frame, its
+    /// main data.
+    pub fn main_data_len(&self) -> usize {
+        let crc = if self.protected { 2 } else { 0 };
+        self.length.saturating_sub(4 + crc + self.side_info_len())
+    }
+
+    /// What the side information of a Layer III frame, which `bytes` start
+    /// with, says about its main data; `None` if `bytes` are too short or
+    /// the side information is nothing an encoder writes: more than 576
+    /// spectral values, Huffman tables that don't exist, or switched windows
+    /// without a block type.
+    pub fn side_info(&self, bytes: &[u8]) -> Option<SideInfo> {
+        let start = 4 + if self.protected { 2 } else { 0 };
+        let end = start + self.side_info_len();
+        let mut bits = Bits {
+            data: bytes.get(start..end)?,
+            pos: 0,
+        };
+        let mpeg1 = self.version == Version::Mpeg1;
+        let channels = usize::from(self.channel_mode.channels());
+
+        let main_data_begin = bits.read(if mpeg1 { 9 } else { 8 })? as usize;
+        // private bits, then the scale factor selection of MPEG-1
+        bits.read(match (mpeg1, channels) {
+            (true, 1) => 5,
+            (true, _) => 3,
+            (false, 1) => 1,
+            (false, _) => 2,
+        })?;
+        if mpeg1 {
+            bits.read(4 * channels)?;
+        }
+        // Huffman tables 4 and 14 aren't defined
+        let table = |bits: &mut Bits| bits.read(5).filter(|&table| table != 4 && table != 14);
+        let granules = if mpeg1 { 2 } else { 1 };
+        let mut part2_3_bits = 0;
+        for _ in 0..granules * channels {
+            part2_3_bits += bits.read(12)? as usize;
+            // big values come in pairs, of the 576 a granule has
+            if bits.read(9)? > 288 {
+                return None;
+            }
+            // global gain, scale factor compression
+            bits.read(if mpeg1 { 8 + 4 } else { 8 + 9 })?;
+            if bits.read(1)? == 1 {
+                // window switching: block type 0 is for long blocks only
+                if bits.read(2)? == 0 {
+                    return None;
+                }
+                bits.read(1)?;
+                table(&mut bits)?;
+                table(&mut bits)?;
+                // subblock gains
+                bits.read(9)?;
+            } else {
+                table(&mut bits)?;
+                table(&mut bits)?;
+                table(&mut bits)?;
+                // region counts
+                bits.read(7)?;
+            }
+            // preflag (MPEG-1 only), scale factor scale, count1 table
+            bits.read(if mpeg1 { 3 } else { 2 })?;
+        }
+        Some(SideInfo {
+            main_data_begin,
+            part2_3_bits,
+        })
+    }
+
+    /// Whether the CRC of the frame that `bytes` starts with matches what it
+    /// covers, after the header: the side information for Layer III, and the
+    /// bit allocation for Layer I. Layer II frames, where that depends on the
+    /// allocation tables, and frames without a CRC always match, as do frames
+    /// too short to tell.
+    fn crc_matches(&self, bytes: &[u8]) -> bool {
+        if !self.protected || bytes.len() < 4 {
+            return true;
+        }
+        let covered = match self.layer {
+            Layer::L1 => {
+                // subbands from the bound on share their allocation
+                let channels = usize::from(self.channel_mode.channels());
+                let bound = match self.channel_mode {
+                    ChannelMode::JointStereo => 4 * (usize::from(self.mode_extension) + 1),
+                    _ => 32,
+                };
+                4 * (channels * bound + 32 - bound) / 8
+            }
+            Layer::L2 => return true,
+            Layer::L3 => self.side_info_len(),
+        };
+        let (stored, covered) = match (bytes.get(4..6), bytes.get(6..6 + covered)) {
+            (Some(stored), Some(covered)) => (stored, covered),
+            _ => return true,
+        };
+        let crc = crc16(crc16(0xFFFF, &bytes[2..4]), covered);
+        crc == u16::from_be_bytes([stored[0], stored[1]])
+    }
+
+    /// Whether `other` could be the next frame of a free format stream this
+    /// frame is part of.
+    fn same_free_stream(&self, other: &FrameHeader) -> bool {
+        other.is_free_format()
+            && other.version == self.version
+            && other.layer == self.layer
+            && other.sample_rate == self.sample_rate
+    }
+
+    /// This free format frame, given the length of the stream's frames
+    /// without padding.
+    fn with_free_length(self, unpadded: usize) -> FrameHeader {
+        let (_, slot_factor, slot_size) = frame_layout(self.version, self.layer);
+        let slots = (unpadded / slot_size as usize) as u64;
+        let bit_rate = slots * u64::from(self.sample_rate) / u64::from(slot_factor);
+        FrameHeader {
+            bit_rate: bit_rate as u32,
+            length: unpadded + self.padding_len(),
+            ..self
+        }
+    }
+}
+
+/// What the side information of a Layer III frame says about its main data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SideInfo {
+    /// How far back into the main data of the frames before (the bit
+    /// reservoir) this frame's starts, in bytes
+    pub main_data_begin: usize,
+    /// Length of the scale factors and Huffman data of every granule and
+    /// channel; 0 in a frame of digital silence
+    pub part2_3_bits: usize,
+}
+
+/// CRC-16 of MPEG audio frames: polynomial 0x8005, starting from all ones.
+fn crc16(crc: u16, bytes: &[u8]) -> u16 {
+    bytes.iter().fold(crc, |crc, &byte| {
+        (0..8).fold(crc ^ u16::from(byte) << 8, |crc, _| match crc & 0x8000 {
+            0 => crc << 1,
+            _ => crc << 1 ^ 0x8005,
+        })
+    })
+}
+
+/// Why `parse_header` rejected a header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rejected {
+    /// Not even a frame sync, i.e. not a header at all
+    NoSync,
+    Version(u32),
+    Layer(u32),
+    BitRate(u32),
+    SampleRate(u32),
+    Emphasis(u32),
+}
+
+impl fmt::Display for Rejected {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Rejected::NoSync => write!(f, "no frame sync"),
+            Rejected::Version(v) => write!(f, "MPEG version {:#04b}", v),
+            Rejected::Layer(v) => write!(f, "MPEG layer {:#04b}", v),
+            Rejected::BitRate(v) => write!(f, "bitrate index {:#06b}", v),
+            Rejected::SampleRate(v) => write!(f, "sample rate index {:#04b}", v),
+            Rejected::Emphasis(v) => write!(f, "emphasis {:#04b}", v),
+        }
+    }
+}
+
+/// Check whether the big-endian `header_num` is a frame header the scanner
+/// accepts (MPEG-1, MPEG-2 or MPEG-2.5, any layer).
+pub fn parse_header(header_num: u32) -> Result<FrameHeader, Rejected> {
+    // frame sync
+    if header_num & 0xFFE00000 != 0xFFE00000 {
+        return Err(Rejected::NoSync);
+    }
+
+    // MPEG version
+    let mpeg_version = (header_num & 0x00180000) >> 19;
+    let version = match mpeg_version {
+        0b11 => Version::Mpeg1,
+        0b10 => Version::Mpeg2,
+        0b00 => Version::Mpeg25,
+        _ => return Err(Rejected::Version(mpeg_version)),
+    };
+
+    // MPEG layer
+    let mpeg_layer = (header_num & 0x00060000) >> 17;
+    let layer = match mpeg_layer {
+        0b11 => Layer::L1,
+        0b10 => Layer::L2,
+        0b01 => Layer::L3,
+        _ => return Err(Rejected::Layer(mpeg_layer)),
+    };
+
+    // bitrate
+    let bit_rate_idx = (header_num & 0x0000F000) >> 12;
+    if bit_rate_idx == 0b1111 {
+        return Err(Rejected::BitRate(bit_rate_idx));
+    }
+    // free format: a fixed bitrate that isn't in the table
+    let bit_rate = match bit_rate_idx {
+        0b0000 => 0,
+        _ => get_bit_rate(version, layer, bit_rate_idx).ok_or(Rejected::BitRate(bit_rate_idx))?,
+    };
+
+    // sample rate
+    let sample_rate_idx = (header_num & 0x00000C00) >> 10;
+    if sample_rate_idx == 0b11 {
+        return Err(Rejected::SampleRate(sample_rate_idx));
+    }
+    let sample_rate =
+        get_sample_rate(version, sample_rate_idx).ok_or(Rejected::SampleRate(sample_rate_idx))?;
+
+    // a 0 protection bit means a CRC follows
+    let protected = header_num & 0x00010000 == 0;
+
+    // padding?
+    let has_padding = ((header_num & 0x00000200) >> 9) == 0b1;
+
+    // channel mode
+    let channel_mode = match (header_num & 0x000000C0) >> 6 {
+        0b00 => ChannelMode::Stereo,
+        0b01 => ChannelMode::JointStereo,
+        0b10 => ChannelMode::DualChannel,
+        _ => ChannelMode::Mono,
+    };
+    let mode_extension = ((header_num & 0x00000030) >> 4) as u8;
+
+    // emphasis
+    let emphasis = header_num & 0x00000003;
+    if emphasis == 0b10 {
+        return Err(Rejected::Emphasis(emphasis));
+    }
+
+    // calculate frame length; Layer I counts in 4 byte slots, and MPEG-2 and
+    // 2.5 Layer III frames hold half the samples
+    let (samples, slot_factor, slot_size) = frame_layout(version, layer);
+    let length = match bit_rate {
+        0 => 0,
+        _ => {
+            ((slot_factor * bit_rate / sample_rate
+                + match has_padding {
+                    true => 1,
+                    false => 0,
+                })
+                * slot_size) as usize
+        }
+    };
+
+    Ok(FrameHeader {
+        version,
+        layer,
+        free_format: bit_rate_idx == 0b0000,
+        bit_rate,
+        sample_rate,
+        padding: has_padding,
+        length,
+        samples,
+        channel_mode,
+        mode_extension,
+        protected,
+        corrupt: false,
+    })
+}
+
+/// How many runs of `run` back-to-back valid frames `data` contains; used to
+/// guess whether data is deobfuscated correctly without a full scan. Free
+/// format frames aren't counted.
+pub fn count_frame_runs(data: &[u8], run: usize) -> usize {
+    let header_at = |pos: usize| {
+        let bytes = data.get(pos..pos + 4)?;
+        parse_header(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+            .ok()
+            .filter(|frame| !frame.is_free_format())
+    };
+
+    let mut runs = 0;
+    let mut pos = 0;
+    while pos + 4 <= data.len() {
+        let mut next = pos;
+        let mut frames = 0;
+        while frames < run {
+            match header_at(next) {
+                Some(frame) => {
+                    next += frame.length;
+                    frames += 1;
+                }
+                None => break,
+            }
+        }
+        if frames == run {
+            runs += 1;
+            pos = next;
+        } else {
+            pos += 1;
+        }
+    }
+    runs
+}
+
+// longest free format frame looked for: 640 kbps at 8 kHz, the most the
+// format allows, with room to spare
+const MAX_FREE_FRAME: usize = 8 << 10;
+
+/// Length (without padding) of the free format frame `frame` at the start of
+/// the window, measured as the distance to the next one.
+fn measure_free_frame(source: &mut dyn Source, frame: &FrameHeader) -> Option<usize> {
+    let window = source.peek_up_to(MAX_FREE_FRAME + 4);
+    // a frame is more than its header, and more than its padding
+    (5..window.len().saturating_sub(3)).find_map(|at| {
+        let bytes = [window[at], window[at + 1], window[at + 2], window[at + 3]];
+        let next = parse_header(u32::from_be_bytes(bytes)).ok()?;
+        if !frame.same_free_stream(&next) {
+            return None;
+        }
+        at.checked_sub(frame.padding_len())
+            .filter(|&unpadded| unpadded > 0)
+    })
+}
+
+/// `frame`, the frame at the start of the window, marked corrupt if its CRC
+/// doesn't match.
+fn checked(source: &mut dyn Source, frame: FrameHeader) -> FrameHeader {
+    let bytes = source.peek_up_to(frame.length.min(6 + 32));
+    FrameHeader {
+        corrupt: !frame.crc_matches(bytes),
+        ..frame
+    }
+}
+
+/// MPEG audio of any version and layer, carved as runs of frames.
+pub struct Mpeg;
+
+impl Framed for Mpeg {
+    type Frame = FrameHeader;
+
+    fn format(&self) -> Format {
+        Format::Mp3
+    }
+
+    fn starts_with(&self, byte: u8) -> bool {
+        byte == 0xFF
+    }
+
+    fn frame(
+        &self,
+        source: &mut dyn Source,
+        previous: Option<&FrameHeader>,
+    ) -> Option<FrameHeader> {
+        let pos = source.position();
+        let bytes = source.peek(4)?;
+        let header_num = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        let frame = match parse_header(header_num) {
+            Ok(frame) => frame,
+            // the layer bits MP3 rejects are ADTS's
+            Err(Rejected::NoSync) | Err(Rejected::Layer(0b00)) => return None,
+            Err(rejected) => {
+                trace!("{:#x}: rejected frame, {}", pos, rejected);
+                return None;
+            }
+        };
+        if !frame.is_free_format() {
+            return Some(checked(source, frame));
+        }
+
+        // the frames of a free format stream are all as long, bar padding
+        let unpadded = match previous {
+            Some(previous) if previous.is_free_format() && previous.same_free_stream(&frame) => {
+                Some(previous.length - previous.padding_len())
+            }
+            _ => measure_free_frame(source, &frame),
+        };
+        if unpadded.is_none() {
+            trace!(
+                "{:#x}: rejected frame, free format without a next frame",
+                pos
+            );
+        }
+        unpadded.map(|unpadded| checked(source, frame.with_free_length(unpadded)))
+    }
+
+    fn info(&self, frame: &FrameHeader) -> FrameInfo {
+        FrameInfo {
+            length: frame.length,
+            samples: frame.samples,
+            sample_rate: frame.sample_rate,
+            bit_rate: frame.bit_rate,
+            channel_mode: Some(frame.channel_mode),
+            corrupt: frame.corrupt,
+        }
+    }
+
+    fn continues(&self, frame: &FrameHeader, previous: &FrameHeader) -> bool {
+        frame.version == previous.version
+            && frame.layer == previous.layer
+            && frame.sample_rate == previous.sample_rate
+    }
+
+    /// A frame as long as `previous`, with or without padding.
+    fn stand_ins(&self, previous: &FrameHeader) -> Vec<FrameHeader> {
+        let unpadded = previous.length - previous.padding_len();
+        let slot = frame_layout(previous.version, previous.layer).2 as usize;
+        [(false, unpadded), (true, unpadded + slot)]
+            .iter()
+            .map(|&(padding, length)| FrameHeader {
+                padding,
+                length,
+                corrupt: true,
+                ..*previous
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::carve::{extract, ScanOptions};
+
+    #[test]
+    fn mpeg1_layer3_at_32khz() {
+        // MPEG-1 Layer III without CRC, sample rate index 2, no padding
+        for &(bit_rate_index, bit_rate, length) in &[(7u32, 96_000, 432), (9, 128_000, 576)] {
+            let header = 0xFFFB_0800 | bit_rate_index << 12;
+            let parsed = parse_header(header).unwrap();
+            assert_eq!(parsed.sample_rate, 32000);
+            assert_eq!(parsed.bit_rate, bit_rate);
+            assert_eq!(parsed.length, length);
+
+            let mut frame = vec![0; length];
+            frame[..4].copy_from_slice(&header.to_be_bytes());
+            let mut input = vec![0; 1000];
+            for _ in 0..200 {
+                input.extend_from_slice(&frame);
+            }
+            input.extend_from_slice(&[0; 500]);
+            let streams = extract(input, &ScanOptions::default()).unwrap();
+            assert_eq!(streams.len(), 1);
+            let stream = &streams[0];
+            assert_eq!((stream.offset, stream.data.len()), (1000, 200 * length));
+            assert_eq!(stream.frames, 200);
+            assert_eq!(stream.samples, 200 * 1152);
+            assert_eq!((stream.sample_rate, stream.bit_rate), (32000, bit_rate));
+        }
+    }
+
+    #[test]
+    fn crc16_check_value() {
+        assert_eq!(crc16(0xFFFF, b"123456789"), 0xAEE7);
+    }
+
+    #[test]
+    fn frames_with_a_wrong_crc_are_counted() {
+        // MPEG-1 Layer III at 128 kbps and 44.1 kHz, stereo, with CRC
+        let header = 0xFFFA_9000u32.to_be_bytes();
+        let mut frame = vec![0; 417];
+        frame[..4].copy_from_slice(&header);
+        let crc = crc16(crc16(0xFFFF, &header[2..4]), &frame[6..6 + 32]);
+        frame[4..6].copy_from_slice(&crc.to_be_bytes());
+        assert!(parse_header(u32::from_be_bytes(header))
+            .unwrap()
+            .crc_matches(&frame));
+
+        let mut input = vec![0; 1000];
+        for n in 0..200 {
+            let start = input.len();
+            input.extend_from_slice(&frame);
+            if n % 30 == 10 {
+                // the count1 table bit of the last granule
+                input[start + 6 + 31] ^= 0x01;
+            }
+        }
+        input.extend_from_slice(&[0; 500]);
+        let streams = extract(input, &ScanOptions::default()).unwrap();
+        assert_eq!(streams.len(), 1);
+        assert_eq!(streams[0].frames, 200);
+        assert_eq!(streams[0].corrupt_frames, 7);
+    }
+
+    #[test]
+    fn free_format_frame_with_a_sync_right_after_its_header() {
+        // a padded Layer I free format frame, with a sync word where its
+        // padding ends
+        let mut input = vec![0; 16];
+        input.extend_from_slice(&[0xFF, 0xFF, 0x02, 0x00]);
+        for _ in 0..4 {
+            input.extend_from_slice(&[0xFF, 0xFF, 0x00, 0x00]);
+        }
+        input.extend_from_slice(&[0; 64]);
+        let options = ScanOptions {
+            threshold: 0,
+            ..ScanOptions::default()
+        };
+        assert!(extract(input, &options).unwrap().is_empty());
+    }
+}
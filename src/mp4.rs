@@ -0,0 +1,371 @@
+//! MP4/M4A containers: a sequence of boxes (`ftyp`, `moov`, `mdat`, ...),
+//! each starting with its size and a four character type. Complete
+//! containers are carved as they are instead of being frame-parsed.
+
+use std::convert::TryInto;
+use tracing::trace;
+
+use crate::carve::{Carver, Deadline, Format, Gapless, ScanOptions, Source, Stopped, Stream};
+
+/// Length of a box header without a 64-bit size.
+pub const HEADER_LEN: usize = 8;
+
+/// Largest `ftyp` box taken for the start of a container; real ones list a
+/// handful of brands.
+const MAX_FTYP: u32 = 1 << 10;
+
+/// What a box header says.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BoxHeader {
+    pub kind: [u8; 4],
+    /// Length of the whole box, header included, once known; `None` for the
+    /// last box, which extends to the end of the file
+    pub size: Option<u64>,
+    /// Length of the header, 16 with a 64-bit size
+    pub header_len: usize,
+}
+
+/// Parse the box header at the start of `bytes`, which needs 16 bytes for
+/// boxes with a 64-bit size.
+pub fn parse_box_header(bytes: &[u8]) -> Option<BoxHeader> {
+    let size = u32::from_be_bytes(bytes.get(..4)?.try_into().ok()?);
+    let kind: [u8; 4] = bytes.get(4..8)?.try_into().ok()?;
+    // types are four printable characters, e.g. `mdat`, `(c)nam`'s `\xA9nam`
+    // or the `----` of freeform metadata
+    if !kind
+        .iter()
+        .all(|&c| c.is_ascii_alphanumeric() || matches!(c, b' ' | b'-' | 0xA9))
+    {
+        return None;
+    }
+    let (size, header_len) = match size {
+        0 => (None, HEADER_LEN),
+        1 => {
+            let size = u64::from_be_bytes(bytes.get(8..16)?.try_into().ok()?);
+            (Some(size), 16)
+        }
+        size => (Some(u64::from(size)), HEADER_LEN),
+    };
+    if size.is_some_and(|size| size < header_len as u64) {
+        return None;
+    }
+    Some(BoxHeader {
+        kind,
+        size,
+        header_len,
+    })
+}
+
+/// Whether a box of type `kind` can be at the top level of a container, after
+/// the `ftyp`; anything else ends the container.
+pub fn is_top_level(kind: &[u8; 4]) -> bool {
+    matches!(
+        kind,
+        b"moov"
+            | b"mdat"
+            | b"free"
+            | b"skip"
+            | b"wide"
+            | b"uuid"
+            | b"udta"
+            | b"meta"
+            | b"pdin"
+            | b"moof"
+            | b"mfra"
+            | b"sidx"
+            | b"emsg"
+            | b"prft"
+    )
+}
+
+/// Whether `bytes` start with an `ftyp` box, i.e. an MP4 container.
+pub fn is_container_start(bytes: &[u8]) -> bool {
+    match bytes.get(..4) {
+        Some(size) => {
+            let size = u32::from_be_bytes([size[0], size[1], size[2], size[3]]);
+            (16..=MAX_FTYP).contains(&size) && bytes.get(4..8) == Some(b"ftyp")
+        }
+        None => false,
+    }
+}
+
+/// What `moov` says about the audio in a container.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Info {
+    /// Time scale of the audio track, which is its sample rate
+    pub sample_rate: u32,
+    /// Duration of the audio track, in samples
+    pub samples: u64,
+    /// Number of samples in the track's sample table, i.e. AAC frames
+    pub frames: usize,
+    /// From iTunes' `iTunSMPB` tag, if there is one
+    pub gapless: Option<Gapless>,
+}
+
+/// The boxes directly inside `data` (the contents of a box), as their header
+/// and contents.
+fn children(data: &[u8]) -> impl Iterator<Item = (BoxHeader, &[u8])> {
+    let mut rest = data;
+    std::iter::from_fn(move || {
+        let header = parse_box_header(rest)?;
+        let size = header.size.map_or(rest.len(), |size| size as usize);
+        if size > rest.len() {
+            return None;
+        }
+        let (this, next) = rest.split_at(size);
+        rest = next;
+        Some((header, &this[header.header_len..]))
+    })
+}
+
+fn child<'a>(data: &'a [u8], kind: &[u8; 4]) -> Option<&'a [u8]> {
+    children(data)
+        .find(|(header, _)| &header.kind == kind)
+        .map(|(_, data)| data)
+}
+
+fn be_u32(data: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(data.get(at..at + 4)?.try_into().ok()?))
+}
+
+fn be_u64(data: &[u8], at: usize) -> Option<u64> {
+    Some(u64::from_be_bytes(data.get(at..at + 8)?.try_into().ok()?))
+}
+
+/// The encoder delay and padding in the `iTunSMPB` tag of `moov`'s metadata
+/// (`udta`, `meta`, `ilst`), which iTunes writes for gapless playback.
+fn parse_itunsmpb(moov: &[u8]) -> Option<Gapless> {
+    // `meta` is a full box: version and flags come before its children
+    let meta = child(child(moov, b"udta")?, b"meta")?.get(4..)?;
+    let value = children(child(meta, b"ilst")?)
+        .filter(|(header, _)| &header.kind == b"----")
+        .find_map(|(_, item)| {
+            // `name` and `data` are full boxes too, and `data` has a type
+            // and a locale before the value
+            if child(item, b"name")?.get(4..)? != b"iTunSMPB" {
+                return None;
+            }
+            child(item, b"data")?.get(8..)
+        })?;
+    // " 00000000 00000840 000001CA 0000000000XXXXXX ...", in hex
+    let value = std::str::from_utf8(value).ok()?;
+    let mut fields = value.split_whitespace().skip(1);
+    let mut field = || u32::from_str_radix(fields.next()?, 16).ok();
+    Some(Gapless {
+        delay: field()?,
+        padding: field()?,
+    })
+}
+
+/// The first sound track described in the contents of `moov`.
+pub fn parse_moov(moov: &[u8]) -> Option<Info> {
+    let gapless = parse_itunsmpb(moov);
+    children(moov)
+        .filter(|(header, _)| &header.kind == b"trak")
+        .find_map(|(_, trak)| {
+            let mdia = child(trak, b"mdia")?;
+            let hdlr = child(mdia, b"hdlr")?;
+            // version and flags, pre_defined, then the handler type
+            if hdlr.get(8..12)? != b"soun" {
+                return None;
+            }
+            let mdhd = child(mdia, b"mdhd")?;
+            let (sample_rate, samples) = match mdhd.first()? {
+                0 => (be_u32(mdhd, 12)?, u64::from(be_u32(mdhd, 16)?)),
+                _ => (be_u32(mdhd, 20)?, be_u64(mdhd, 24)?),
+            };
+            let frames = child(mdia, b"minf")
+                .and_then(|minf| child(minf, b"stbl"))
+                .and_then(|stbl| child(stbl, b"stsz"))
+                .and_then(|stsz| be_u32(stsz, 8))
+                .unwrap_or(0);
+            Some(Info {
+                sample_rate,
+                samples,
+                frames: frames as usize,
+                gapless,
+            })
+        })
+}
+
+/// Brands of audio-only files, e.g. iTunes' `M4A `.
+const AUDIO_BRANDS: &[&[u8; 4]] = &[b"M4A ", b"M4B ", b"M4P ", b"F4A ", b"F4B "];
+
+/// Whether the container `data` starts with has a video track; `None` if that
+/// can't be told because its `moov` isn't in `data`. Containers with an
+/// audio brand never do.
+pub fn has_video_track(data: &[u8]) -> Option<bool> {
+    let mut boxes = children(data);
+    let (_, ftyp) = boxes.next().filter(|(header, _)| &header.kind == b"ftyp")?;
+    // the major brand, the minor version, then the compatible brands
+    let mut brands = ftyp.chunks_exact(4).enumerate().filter(|&(i, _)| i != 1);
+    if brands.any(|(_, brand)| AUDIO_BRANDS.iter().any(|audio| &audio[..] == brand)) {
+        return Some(false);
+    }
+    let (_, moov) = boxes.find(|(header, _)| &header.kind == b"moov")?;
+    let video = children(moov)
+        .filter(|(header, _)| &header.kind == b"trak")
+        .any(|(_, trak)| {
+            let hdlr = child(trak, b"mdia").and_then(|mdia| child(mdia, b"hdlr"));
+            hdlr.and_then(|hdlr| hdlr.get(8..12)) == Some(b"vide")
+        });
+    Some(video)
+}
+
+/// Whole MP4 containers, carved box by box: the `ftyp` box and every box after
+/// it that can follow at the top level.
+pub struct Mp4;
+
+impl Carver for Mp4 {
+    fn starts_with(&self, byte: u8) -> bool {
+        // the `ftyp` box is far shorter than 16 MiB
+        byte == 0x00
+    }
+
+    /// `None` if there is no `moov` in the container, which any playable one
+    /// has.
+    fn carve(
+        &self,
+        source: &mut dyn Source,
+        _options: &ScanOptions,
+        _deadline: &mut Deadline,
+    ) -> Result<Option<Stream>, Stopped> {
+        if !source.peek(HEADER_LEN).is_some_and(is_container_start) {
+            return Ok(None);
+        }
+        let offset = source.position();
+        let mut data = Vec::new();
+        let mut info = None;
+        let mut has_moov = false;
+        loop {
+            let header = match parse_box_header(source.peek_up_to(16)) {
+                Some(header) if data.is_empty() || is_top_level(&header.kind) => header,
+                _ => break,
+            };
+            let start = data.len();
+            let size = header.size.map_or(usize::MAX, |size| size as usize);
+            let taken = source.take_into(size, &mut data);
+            if header.kind == *b"moov" {
+                has_moov = true;
+                info = parse_moov(&data[start + header.header_len.min(taken)..]);
+            }
+            if taken < size {
+                if header.size.is_some() {
+                    trace!("{:#x}: MP4 container cut short", offset);
+                }
+                break;
+            }
+        }
+        if !has_moov {
+            trace!("{:#x}: rejected MP4 container without moov", offset);
+            return Ok(None);
+        }
+
+        let info = info.unwrap_or_default();
+        let bit_rate = match info.samples {
+            0 => 0,
+            samples => data.len() as u64 * 8 * u64::from(info.sample_rate) / samples,
+        };
+        Ok(Some(Stream {
+            data,
+            format: Format::Mp4,
+            offset,
+            frames: info.frames,
+            samples: info.samples,
+            sample_rate: info.sample_rate,
+            bit_rate: bit_rate as u32,
+            channel_mode: None,
+            truncated: false,
+            tags: Vec::new(),
+            gapless: info.gapless,
+            corrupt_frames: 0,
+            gaps: Vec::new(),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::carve::extract;
+
+    fn boxed(kind: &[u8; 4], contents: &[&[u8]]) -> Vec<u8> {
+        let contents = contents.concat();
+        let mut out = ((8 + contents.len()) as u32).to_be_bytes().to_vec();
+        out.extend_from_slice(kind);
+        out.extend_from_slice(&contents);
+        out
+    }
+
+    /// A container with a `moov` for a `handler` track of 431 frames at
+    /// 44.1 kHz, and an `iTunSMPB` tag.
+    fn container(brand: &[u8; 4], handler: &[u8; 4]) -> Vec<u8> {
+        let hdlr = boxed(b"hdlr", &[&[0; 8], handler, &[0; 13]]);
+        let mdhd = boxed(
+            b"mdhd",
+            &[
+                &[0; 12],
+                &44100u32.to_be_bytes(),
+                &441_000u32.to_be_bytes(),
+                &[0; 4],
+            ],
+        );
+        let stsz = boxed(b"stsz", &[&[0; 8], &431u32.to_be_bytes()]);
+        let minf = boxed(b"minf", &[&boxed(b"stbl", &[&stsz])]);
+        let trak = boxed(b"trak", &[&boxed(b"mdia", &[&hdlr, &mdhd, &minf])]);
+        let smpb = b" 00000000 00000840 000001CA 0000000000069C76 00000000";
+        let item = boxed(
+            b"----",
+            &[
+                &boxed(b"name", &[&[0; 4], b"iTunSMPB"]),
+                &boxed(b"data", &[&[0, 0, 0, 1, 0, 0, 0, 0], smpb]),
+            ],
+        );
+        let meta = boxed(b"meta", &[&[0; 4], &boxed(b"ilst", &[&item])]);
+        let moov = boxed(b"moov", &[&trak, &boxed(b"udta", &[&meta])]);
+        [
+            boxed(b"ftyp", &[brand, &[0; 4], b"isom"]),
+            moov,
+            boxed(b"mdat", &[&[0x5A; 100_000]]),
+        ]
+        .concat()
+    }
+
+    #[test]
+    fn whole_container_carved() {
+        let m4a = container(b"M4A ", b"soun");
+        let mut input = vec![0xFF; 3000];
+        input.extend_from_slice(&m4a);
+        input.extend_from_slice(&[0xFF; 2000]);
+        let streams = extract(input, &ScanOptions::default()).unwrap();
+        assert_eq!(streams.len(), 1);
+        let stream = &streams[0];
+        assert_eq!(stream.format, Format::Mp4);
+        assert_eq!((stream.offset, stream.data.len()), (3000, m4a.len()));
+        assert_eq!((stream.frames, stream.samples), (431, 441_000));
+        assert_eq!(stream.sample_rate, 44100);
+        assert_eq!(
+            stream.gapless,
+            Some(Gapless {
+                delay: 0x840,
+                padding: 0x1CA
+            })
+        );
+    }
+
+    #[test]
+    fn container_without_moov_rejected() {
+        let mut input = vec![0xFF; 100];
+        input.extend_from_slice(&boxed(b"ftyp", &[b"M4A ", &[0; 4], b"isom"]));
+        input.extend_from_slice(&boxed(b"mdat", &[&[0x5A; 100_000]]));
+        assert!(extract(input, &ScanOptions::default()).unwrap().is_empty());
+    }
+
+    #[test]
+    fn video_tracks() {
+        assert_eq!(has_video_track(&container(b"isom", b"vide")), Some(true));
+        assert_eq!(has_video_track(&container(b"isom", b"soun")), Some(false));
+        // audio brands are taken at their word
+        assert_eq!(has_video_track(&container(b"M4A ", b"vide")), Some(false));
+    }
+}
@@ -0,0 +1,270 @@
+use serde::{Deserialize, Deserializer};
+use std::ffi::{OsStr, OsString};
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::id3::Tag;
+
+/// Default template, matching the historical `<file>.<n>.mp3` naming.
+pub const DEFAULT_TEMPLATE: &str = "{name}.{index}.{ext}";
+
+/// Output filename template given with `--name`, e.g. `{stem}_{index:02}.mp3`.
+///
+/// Fields are `{name}` (input file name), `{stem}` (input file name without
+/// extension), `{index}` (1-based extraction index), `{offset}` (byte offset in
+/// the input), `{variant}` (deobfuscation variant) and `{ext}` (the extension
+/// for the stream's format, e.g. `mp3` or `aac`). Numeric fields accept a
+/// width, optionally zero padded, and an `x` suffix for hex: `{offset:08x}`.
+/// Use `{{` and `}}` for literal braces.
+#[derive(Debug, Clone)]
+pub struct Template {
+    parts: Vec<Part>,
+}
+
+#[derive(Debug, Clone)]
+enum Part {
+    Literal(String),
+    Name,
+    Stem,
+    Ext,
+    Number {
+        field: NumberField,
+        width: usize,
+        zero: bool,
+        hex: bool,
+    },
+}
+
+#[derive(Debug, Clone, Copy)]
+enum NumberField {
+    Index,
+    Offset,
+    Variant,
+}
+
+/// Values available to a template for a single output.
+pub struct NameContext<'a> {
+    pub name: &'a OsStr,
+    pub index: usize,
+    pub offset: usize,
+    pub variant: usize,
+    pub ext: &'a str,
+}
+
+impl<'de> Deserialize<'de> for Template {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+impl FromStr for Template {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        let mut chars = s.chars();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.as_str().starts_with('{') => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '}' if chars.as_str().starts_with('}') => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '{' => {
+                    let rest = chars.as_str();
+                    let end = rest
+                        .find('}')
+                        .ok_or_else(|| format!("unclosed '{{' in {:?}", s))?;
+                    if !literal.is_empty() {
+                        parts.push(Part::Literal(std::mem::take(&mut literal)));
+                    }
+                    parts.push(parse_field(&rest[..end])?);
+                    chars = rest[end + 1..].chars();
+                }
+                '}' => return Err(format!("unmatched '}}' in {:?}", s)),
+                c => literal.push(c),
+            }
+        }
+        if !literal.is_empty() {
+            parts.push(Part::Literal(literal));
+        }
+
+        if parts.is_empty() {
+            return Err("empty template".to_owned());
+        }
+        Ok(Self { parts })
+    }
+}
+
+fn parse_field(field: &str) -> Result<Part, String> {
+    let (name, spec) = match field.split_once(':') {
+        Some((name, spec)) => (name, Some(spec)),
+        None => (field, None),
+    };
+
+    let number = match name {
+        "name" | "stem" | "ext" if spec.is_some() => {
+            return Err(format!("{{{}}} does not take a format spec", name))
+        }
+        "name" => return Ok(Part::Name),
+        "stem" => return Ok(Part::Stem),
+        "ext" => return Ok(Part::Ext),
+        "index" => NumberField::Index,
+        "offset" => NumberField::Offset,
+        "variant" => NumberField::Variant,
+        _ => return Err(format!("unknown template field {{{}}}", name)),
+    };
+
+    let spec = spec.unwrap_or("");
+    let (spec, hex) = match spec.strip_suffix('x') {
+        Some(spec) => (spec, true),
+        None => (spec, false),
+    };
+    let zero = spec.starts_with('0');
+    let width = if spec.is_empty() {
+        0
+    } else {
+        spec.parse()
+            .map_err(|_| format!("invalid format spec in {{{}}}", field))?
+    };
+
+    Ok(Part::Number {
+        field: number,
+        width,
+        zero,
+        hex,
+    })
+}
+
+impl Template {
+    pub fn render(&self, ctx: &NameContext) -> OsString {
+        let mut out = OsString::new();
+        for part in &self.parts {
+            match part {
+                Part::Literal(s) => out.push(s),
+                Part::Name => out.push(ctx.name),
+                Part::Stem => out.push(Path::new(ctx.name).file_stem().unwrap_or(ctx.name)),
+                Part::Ext => out.push(ctx.ext),
+                Part::Number {
+                    field,
+                    width,
+                    zero,
+                    hex,
+                } => {
+                    let n = match field {
+                        NumberField::Index => ctx.index,
+                        NumberField::Offset => ctx.offset,
+                        NumberField::Variant => ctx.variant,
+                    };
+                    let s = match (hex, zero) {
+                        (false, false) => format!("{:w$}", n, w = width),
+                        (false, true) => format!("{:0w$}", n, w = width),
+                        (true, false) => format!("{:w$x}", n, w = width),
+                        (true, true) => format!("{:0w$x}", n, w = width),
+                    };
+                    out.push(s);
+                }
+            }
+        }
+        out
+    }
+}
+
+/// `Artist - Title.<ext>` (or just `Title.<ext>`) for `--name-from-tags`,
+/// with characters that aren't allowed in file names replaced. `copy` > 1 is
+/// appended as ` (2)` etc. to tell tracks with the same tags apart.
+pub fn from_tag(tag: &Tag, copy: usize, ext: &str) -> Option<OsString> {
+    let mut name = match (&tag.artist, &tag.title) {
+        (Some(artist), Some(title)) => format!("{} - {}", artist, title),
+        (None, Some(title)) => title.clone(),
+        _ => return None,
+    };
+    if copy > 1 {
+        name.push_str(&format!(" ({})", copy));
+    }
+    let name: String = name
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect();
+    // names like `..` would escape the output directory
+    let name = name.trim_start_matches('.');
+    if name.is_empty() {
+        return None;
+    }
+    Some(OsString::from(format!("{}.{}", name, ext)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render(template: &str) -> Result<OsString, String> {
+        let template: Template = template.parse()?;
+        Ok(template.render(&NameContext {
+            name: OsStr::new("cache.bin"),
+            index: 3,
+            offset: 0x1f40,
+            variant: 1,
+            ext: "mp3",
+        }))
+    }
+
+    #[test]
+    fn fields_and_formats() {
+        assert_eq!(render(DEFAULT_TEMPLATE).unwrap(), "cache.bin.3.mp3");
+        assert_eq!(
+            render("{stem}_{index:02}_{offset:08x}.{variant}.{ext}").unwrap(),
+            "cache_03_00001f40.1.mp3"
+        );
+        assert_eq!(render("{index:3}|{offset:x}").unwrap(), "  3|1f40");
+        assert_eq!(render("{{{stem}}}.{ext}").unwrap(), "{cache}.mp3");
+    }
+
+    #[test]
+    fn bad_templates() {
+        for template in [
+            "",
+            "{stem",
+            "stem}",
+            "{title}.mp3",
+            "{name:02}",
+            "{index:two}",
+        ] {
+            assert!(render(template).is_err(), "{:?}", template);
+        }
+    }
+
+    fn tag(artist: Option<&str>, title: Option<&str>) -> Tag {
+        Tag {
+            artist: artist.map(str::to_owned),
+            title: title.map(str::to_owned),
+        }
+    }
+
+    #[test]
+    fn names_from_tags() {
+        let both = tag(Some("AC/DC"), Some("T.N.T."));
+        assert_eq!(from_tag(&both, 1, "mp3").unwrap(), "AC_DC - T.N.T..mp3");
+        assert_eq!(from_tag(&both, 2, "mp3").unwrap(), "AC_DC - T.N.T. (2).mp3");
+        let title = tag(None, Some("What? \"Why\"\t"));
+        assert_eq!(from_tag(&title, 1, "aac").unwrap(), "What_ _Why__.aac");
+        assert_eq!(from_tag(&tag(Some("Artist"), None), 1, "mp3"), None);
+    }
+
+    #[test]
+    fn names_from_tags_stay_in_the_directory() {
+        assert_eq!(from_tag(&tag(None, Some("..")), 1, "mp3"), None);
+        let hidden = tag(None, Some("../../etc/passwd"));
+        assert_eq!(from_tag(&hidden, 1, "mp3").unwrap(), "_.._etc_passwd.mp3");
+    }
+}
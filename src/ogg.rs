@@ -0,0 +1,358 @@
+//! Ogg streams: a sequence of pages, each starting with the `OggS` capture
+//! pattern, a header naming the logical stream it belongs to and a segment
+//! table giving the length of its body, and checked by a CRC-32. A physical
+//! stream interleaves one or more logical streams, e.g. the Vorbis audio of
+//! a preview clip, from their first (BOS) page to their last (EOS) page.
+//!
+//! Streams whose audio is Opus are told apart by the OpusHead packet that
+//! starts it, and written as `.opus`.
+
+use std::convert::TryInto;
+use tracing::trace;
+
+use crate::carve::{Carver, Deadline, Format, ScanOptions, Source, Stopped, Stream};
+
+const CAPTURE_PATTERN: &[u8; 4] = b"OggS";
+
+/// Length of a page header up to the segment table.
+const HEADER_LEN: usize = 27;
+
+// header type flags
+const BOS: u8 = 0x02;
+const EOS: u8 = 0x04;
+
+/// The parts of a page header that matter for carving.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PageHeader {
+    pub header_type: u8,
+    /// Codec-defined position at the end of the page, e.g. samples for
+    /// Vorbis; `u64::MAX` if no packet ends on it
+    pub granule: u64,
+    pub serial: u32,
+    pub sequence: u32,
+    /// Length of the header, segment table included
+    pub header_len: usize,
+    /// Length of the whole page, header included
+    pub length: usize,
+}
+
+/// Check whether `bytes` start with a page header, and parse it; `bytes`
+/// need to include the segment table.
+pub fn parse_page_header(bytes: &[u8]) -> Option<PageHeader> {
+    let header = bytes.get(..HEADER_LEN)?;
+    if !header.starts_with(CAPTURE_PATTERN) || header[4] != 0 || header[5] & !0x07 != 0 {
+        return None;
+    }
+    let segments = usize::from(header[26]);
+    let table = bytes.get(HEADER_LEN..HEADER_LEN + segments)?;
+    let header_len = HEADER_LEN + segments;
+    Some(PageHeader {
+        header_type: header[5],
+        granule: u64::from_le_bytes(header[6..14].try_into().ok()?),
+        serial: u32::from_le_bytes(header[14..18].try_into().ok()?),
+        sequence: u32::from_le_bytes(header[18..22].try_into().ok()?),
+        header_len,
+        length: header_len + table.iter().map(|&len| usize::from(len)).sum::<usize>(),
+    })
+}
+
+const fn crc32_table() -> [u32; 256] {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = (i as u32) << 24;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = match crc & 0x8000_0000 {
+                0 => crc << 1,
+                _ => crc << 1 ^ 0x04C1_1DB7,
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+static CRC32_TABLE: [u32; 256] = crc32_table();
+
+/// Whether the CRC-32 of `page` checks out; it is computed with the checksum
+/// field zeroed.
+fn checksum_matches(page: &[u8]) -> bool {
+    let expected = u32::from_le_bytes([page[22], page[23], page[24], page[25]]);
+    let crc = page.iter().enumerate().fold(0u32, |crc, (i, &byte)| {
+        let byte = if (22..26).contains(&i) { 0 } else { byte };
+        crc << 8 ^ CRC32_TABLE[usize::from((crc >> 24) as u8 ^ byte)]
+    });
+    crc == expected
+}
+
+/// Opus granule positions count samples at 48 kHz, whatever the input was.
+const OPUS_SAMPLE_RATE: u32 = 48000;
+
+/// The audio codec of a logical stream.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Codec {
+    Vorbis {
+        sample_rate: u32,
+    },
+    /// `pre_skip` samples at the start are decoder warm-up, not audio
+    Opus {
+        pre_skip: u16,
+    },
+}
+
+/// The codec of a logical stream, from the identification header that is
+/// the first packet of its BOS page.
+fn identify(body: &[u8]) -> Option<Codec> {
+    if body.starts_with(b"\x01vorbis") {
+        let sample_rate = u32::from_le_bytes(body.get(12..16)?.try_into().ok()?);
+        return Some(Codec::Vorbis { sample_rate }).filter(|_| sample_rate != 0);
+    }
+    if body.starts_with(b"OpusHead") {
+        return parse_opus_head(body);
+    }
+    None
+}
+
+/// Check an OpusHead packet: only the major version 0 is understood, and
+/// the channel mapping has to fit the channel count.
+fn parse_opus_head(body: &[u8]) -> Option<Codec> {
+    let head = body.get(..19)?;
+    let version = head[8];
+    let channels = head[9];
+    let pre_skip = u16::from_le_bytes([head[10], head[11]]);
+    let mapping_family = head[18];
+    if version >> 4 != 0 || channels == 0 {
+        return None;
+    }
+    match mapping_family {
+        // mono or stereo, no mapping table
+        0 if channels <= 2 => {}
+        0 => return None,
+        _ => {
+            let table = body.get(19..21 + usize::from(channels))?;
+            let (streams, coupled) = (table[0], table[1]);
+            if streams == 0 || coupled > streams || mapping_family == 1 && channels > 8 {
+                return None;
+            }
+            let decoded = u16::from(streams) + u16::from(coupled);
+            if table[2..]
+                .iter()
+                .any(|&index| index != 255 && u16::from(index) >= decoded)
+            {
+                return None;
+            }
+        }
+    }
+    Some(Codec::Opus { pre_skip })
+}
+
+/// A logical stream being carved.
+struct Logical {
+    serial: u32,
+    /// Sequence number of the last page
+    sequence: u32,
+    ended: bool,
+    codec: Option<Codec>,
+    /// Granule position of the last page that had one
+    granule: u64,
+}
+
+/// Ogg streams, carved page by page from the BOS pages of their logical
+/// streams to their EOS pages.
+pub struct Ogg;
+
+impl Carver for Ogg {
+    fn starts_with(&self, byte: u8) -> bool {
+        byte == CAPTURE_PATTERN[0]
+    }
+
+    /// A page that doesn't check out, is missing from its logical stream or
+    /// belongs to no logical stream started on the first pages ends the
+    /// stream, which is truncated if that was before all EOS pages; `None`
+    /// if it doesn't start with a BOS page.
+    fn carve(
+        &self,
+        source: &mut dyn Source,
+        _options: &ScanOptions,
+        deadline: &mut Deadline,
+    ) -> Result<Option<Stream>, Stopped> {
+        let offset = source.position();
+        let mut data = Vec::new();
+        let mut logical: Vec<Logical> = Vec::new();
+        let mut pages = 0;
+        loop {
+            deadline.check(source.position())?;
+            let page = match parse_page_header(source.peek_up_to(HEADER_LEN + 255)) {
+                Some(page) => page,
+                None => break,
+            };
+            let bytes = match source.peek(page.length) {
+                Some(bytes) if checksum_matches(bytes) => bytes,
+                _ => break,
+            };
+
+            let index = if page.header_type & BOS != 0 {
+                // all BOS pages come first; another one after that starts
+                // the next stream of a chain
+                if pages != logical.len() {
+                    break;
+                }
+                logical.push(Logical {
+                    serial: page.serial,
+                    sequence: page.sequence,
+                    ended: false,
+                    codec: identify(&bytes[page.header_len..]),
+                    granule: 0,
+                });
+                logical.len() - 1
+            } else {
+                match logical
+                    .iter()
+                    .position(|l| l.serial == page.serial && !l.ended)
+                {
+                    Some(index) if page.sequence == logical[index].sequence.wrapping_add(1) => {
+                        index
+                    }
+                    _ => break,
+                }
+            };
+            let stream = &mut logical[index];
+            stream.sequence = page.sequence;
+            if page.granule != u64::MAX {
+                stream.granule = page.granule;
+            }
+            stream.ended = page.header_type & EOS != 0;
+
+            source.take_into(page.length, &mut data);
+            pages += 1;
+            if logical.iter().all(|l| l.ended) {
+                break;
+            }
+        }
+        if logical.is_empty() {
+            return Ok(None);
+        }
+
+        // the first logical stream that is audio; an Opus stream is also
+        // truncated if it ends before the pre-skip does
+        let mut truncated = !logical.iter().all(|l| l.ended);
+        let audio = logical.iter().find_map(|l| Some((l.codec?, l)));
+        let (format, sample_rate, samples) = match audio {
+            Some((Codec::Vorbis { sample_rate }, l)) => (Format::Ogg, sample_rate, l.granule),
+            Some((Codec::Opus { pre_skip }, l)) => {
+                truncated |= l.granule <= u64::from(pre_skip);
+                let samples = l.granule.saturating_sub(u64::from(pre_skip));
+                (Format::Opus, OPUS_SAMPLE_RATE, samples)
+            }
+            None => (Format::Ogg, 0, 0),
+        };
+        if truncated {
+            trace!("{:#x}: Ogg stream cut short", offset);
+        }
+        let bit_rate = match samples {
+            0 => 0,
+            samples => data.len() as u64 * 8 * u64::from(sample_rate) / samples,
+        };
+        Ok(Some(Stream {
+            data,
+            format,
+            offset,
+            frames: pages,
+            samples,
+            sample_rate,
+            bit_rate: bit_rate as u32,
+            channel_mode: None,
+            truncated,
+            tags: Vec::new(),
+            gapless: None,
+            corrupt_frames: 0,
+            gaps: Vec::new(),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::carve::extract;
+
+    fn page(header_type: u8, granule: u64, sequence: u32, body: &[u8]) -> Vec<u8> {
+        let mut page = CAPTURE_PATTERN.to_vec();
+        page.extend_from_slice(&[0, header_type]);
+        page.extend_from_slice(&granule.to_le_bytes());
+        page.extend_from_slice(&0x1234u32.to_le_bytes());
+        page.extend_from_slice(&sequence.to_le_bytes());
+        page.extend_from_slice(&[0; 4]);
+        let mut segments = vec![255; body.len() / 255];
+        segments.push((body.len() % 255) as u8);
+        page.push(segments.len() as u8);
+        page.extend_from_slice(&segments);
+        page.extend_from_slice(body);
+        let crc = page.iter().fold(0u32, |crc, &byte| {
+            crc << 8 ^ CRC32_TABLE[usize::from((crc >> 24) as u8 ^ byte)]
+        });
+        page[22..26].copy_from_slice(&crc.to_le_bytes());
+        page
+    }
+
+    /// A BOS page with `ident`, then 100 pages of noise, each a tenth of a
+    /// second at `rate`; the last is the EOS page.
+    fn stream(ident: &[u8], rate: u64) -> Vec<u8> {
+        let mut out = page(BOS, 0, 0, ident);
+        for n in 1..=100 {
+            let body: Vec<u8> = (0..1500)
+                .map(|i: usize| ((n * 1500 + i).wrapping_mul(2_654_435_761) >> 16) as u8)
+                .collect();
+            let header_type = if n == 100 { EOS } else { 0 };
+            out.extend_from_slice(&page(header_type, n as u64 * rate / 10, n as u32, &body));
+        }
+        out
+    }
+
+    fn carve(ogg: &[u8]) -> Vec<Stream> {
+        let mut input = vec![0xEE; 2222];
+        input.extend_from_slice(ogg);
+        input.extend_from_slice(&[0xEE; 1111]);
+        extract(input, &ScanOptions::default()).unwrap()
+    }
+
+    #[test]
+    fn vorbis_stream_carved_to_its_eos_page() {
+        let mut ident = b"\x01vorbis".to_vec();
+        ident.extend_from_slice(&[0, 0, 0, 0, 2]);
+        ident.extend_from_slice(&44100u32.to_le_bytes());
+        ident.extend_from_slice(&[0, 0, 0, 0, 0x00, 0xF4, 0x01, 0x00, 0, 0, 0, 0, 0xB8, 1]);
+        let ogg = stream(&ident, 44100);
+        let streams = carve(&ogg);
+        assert_eq!(streams.len(), 1);
+        let stream = &streams[0];
+        assert_eq!(stream.format, Format::Ogg);
+        assert_eq!((stream.offset, stream.data.len()), (2222, ogg.len()));
+        assert_eq!((stream.frames, stream.samples), (101, 441_000));
+        assert_eq!(stream.sample_rate, 44100);
+        assert!(!stream.truncated);
+    }
+
+    #[test]
+    fn opus_stream_cut_before_its_eos_page() {
+        // stereo, pre-skip 312, no channel mapping
+        let mut head = b"OpusHead\x01\x02".to_vec();
+        head.extend_from_slice(&312u16.to_le_bytes());
+        head.extend_from_slice(&44100u32.to_le_bytes());
+        head.extend_from_slice(&[0, 0, 0]);
+        let opus = stream(&head, 48000);
+        // the last two pages and part of the one before
+        let page_len = HEADER_LEN + 6 + 1500;
+        let streams = carve(&opus[..opus.len() - 2 * page_len - 100]);
+        assert_eq!(streams.len(), 1);
+        let stream = &streams[0];
+        assert_eq!(stream.format, Format::Opus);
+        assert_eq!(stream.frames, 98);
+        assert_eq!(stream.samples, 97 * 4800 - 312);
+        assert_eq!(stream.sample_rate, 48000);
+        assert!(stream.truncated);
+    }
+}
@@ -0,0 +1,198 @@
+use crate::prompt::{self, Conflict};
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+/// Every file this run has written, canonicalized, so `--watch` can tell its
+/// own outputs and sidecars from new inputs.
+static WRITTEN: Mutex<Option<HashSet<PathBuf>>> = Mutex::new(None);
+
+/// Remember that this run wrote `path`.
+pub fn record_written(path: &Path) {
+    let path = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    let mut written = WRITTEN.lock().unwrap_or_else(|e| e.into_inner());
+    written.get_or_insert_with(HashSet::new).insert(path);
+}
+
+/// Whether this run wrote `path` (as canonicalized).
+pub fn was_written(path: &Path) -> bool {
+    let written = WRITTEN.lock().unwrap_or_else(|e| e.into_inner());
+    written.as_ref().is_some_and(|w| w.contains(path))
+}
+
+/// What to do when an output file already exists.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Overwrite {
+    /// Report an error and leave the existing file alone
+    Fail,
+    /// Replace the existing file
+    Force,
+    /// Keep the existing file and move on
+    Skip,
+    /// Ask the user interactively
+    Ask,
+}
+
+/// Result of writing a single output.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WriteOutcome {
+    Written,
+    Skipped,
+    /// Written under a different name chosen by the user
+    Renamed(PathBuf),
+}
+
+/// Write `data` to `path`, optionally setting its modification time to `mtime`.
+///
+/// The data goes to `<path>.part` first and is only renamed to `path` once it
+/// has been written completely, so an interrupted run never leaves a truncated
+/// file under the final name.
+pub fn write_output(
+    path: &Path,
+    data: &[u8],
+    overwrite: Overwrite,
+    mtime: Option<SystemTime>,
+) -> io::Result<WriteOutcome> {
+    write_output_with(path, &|file| file.write_all(data), overwrite, mtime)
+}
+
+/// Like `write_output`, for data that `write` produces as it goes.
+pub fn write_output_with(
+    path: &Path,
+    write: &dyn Fn(&mut File) -> io::Result<()>,
+    overwrite: Overwrite,
+    mtime: Option<SystemTime>,
+) -> io::Result<WriteOutcome> {
+    if overwrite == Overwrite::Force || fs::symlink_metadata(path).is_err() {
+        write_atomic(path, write, mtime, overwrite == Overwrite::Force)?;
+        return Ok(WriteOutcome::Written);
+    }
+
+    match overwrite {
+        Overwrite::Skip => Ok(WriteOutcome::Skipped),
+        Overwrite::Ask => match prompt::ask_conflict(path)? {
+            Conflict::Overwrite => {
+                write_atomic(path, write, mtime, true)?;
+                Ok(WriteOutcome::Written)
+            }
+            Conflict::Skip => Ok(WriteOutcome::Skipped),
+            Conflict::Rename(new_path) => {
+                match write_output_with(&new_path, write, overwrite, mtime)? {
+                    WriteOutcome::Written => Ok(WriteOutcome::Renamed(new_path)),
+                    written => Ok(written),
+                }
+            }
+        },
+        _ => Err(already_exists()),
+    }
+}
+
+fn already_exists() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::AlreadyExists,
+        "output already exists, use --force to overwrite or --skip-existing",
+    )
+}
+
+/// Write to a temporary file next to `path`, then move it into place.
+///
+/// Unless `replace` is set, an existing file at `path` is never clobbered,
+/// even if it appeared while the data was being written.
+fn write_atomic(
+    path: &Path,
+    write_data: &dyn Fn(&mut File) -> io::Result<()>,
+    mtime: Option<SystemTime>,
+    replace: bool,
+) -> io::Result<()> {
+    let mut part = path.as_os_str().to_owned();
+    part.push(".part");
+    let part = PathBuf::from(part);
+
+    let write = || {
+        let mut file = File::create(&part)?;
+        record_written(&part);
+        write_data(&mut file)?;
+        if let Some(mtime) = mtime {
+            file.set_modified(mtime)?;
+        }
+        file.sync_all()?;
+        if replace {
+            return fs::rename(&part, path);
+        }
+        // a hard link fails instead of replacing an existing file; not every
+        // filesystem has them, though
+        match fs::hard_link(&part, path) {
+            Ok(()) => fs::remove_file(&part),
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists => Err(already_exists()),
+            Err(_) if fs::symlink_metadata(path).is_ok() => Err(already_exists()),
+            Err(_) => fs::rename(&part, path),
+        }
+    };
+
+    let result = write();
+    match result {
+        Ok(()) => record_written(path),
+        Err(_) => {
+            let _ = fs::remove_file(&part);
+        }
+    }
+    result
+}
+
+/// Check that every file in `outputs` exists with the expected size.
+pub fn verify_outputs<'a>(outputs: impl IntoIterator<Item = (&'a Path, u64)>) -> io::Result<()> {
+    for (path, size) in outputs {
+        let len = fs::metadata(path)?.len();
+        if len != size {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{:?} is {} bytes, expected {}", path, len, size),
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Move `path` into `dir`, falling back to copy and delete across filesystems.
+pub fn move_into(path: &Path, dir: &Path) -> io::Result<PathBuf> {
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "no file name"))?;
+    let dest = dir.join(file_name);
+    if dest.exists() {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("{:?} already exists", dest),
+        ));
+    }
+
+    if fs::rename(path, &dest).is_err() {
+        fs::copy(path, &dest)?;
+        fs::remove_file(path)?;
+    }
+    Ok(dest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::checksum::{self, Algorithm};
+
+    #[test]
+    fn outputs_and_sidecars_are_recorded() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("stream.mp3");
+        write_output(&path, b"data", Overwrite::Fail, None).unwrap();
+        checksum::write_sidecar(&path, "00", Algorithm::Sha256).unwrap();
+        checksum::update_sums(vec![(path.as_path(), "00")], Algorithm::Sha256).unwrap();
+
+        let dir = fs::canonicalize(dir.path()).unwrap();
+        assert!(was_written(&dir.join("stream.mp3")));
+        assert!(was_written(&dir.join("stream.mp3.sha256")));
+        assert!(was_written(&dir.join(Algorithm::Sha256.sums_file())));
+        assert!(!was_written(&dir.join("input.bin")));
+    }
+}
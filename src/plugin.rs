@@ -0,0 +1,162 @@
+//! Deobfuscators loaded from WebAssembly modules with `--plugin`, for schemes
+//! too exotic to be built in.
+//!
+//! A plugin exports its `memory` and these functions:
+//!
+//! - `alloc(len: i32) -> i32`: where in memory to put `len` bytes of input
+//! - `transform(ptr: i32, len: i32, pos: i64)`: deobfuscate the `len` bytes
+//!   at `ptr` in place; `pos` is where they start in the input
+//! - optionally `block_size() -> i32` and `phase() -> i32`, if the transform
+//!   works on blocks of more than one byte (see `Deobfuscator`)
+//!
+//! Modules can be given as binary `.wasm` or as text `.wat`.
+
+use std::convert::TryFrom;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::sync::Mutex;
+use tracing::error;
+use wasmi::{Engine, Linker, Memory, Module, Store, TypedFunc};
+
+use crate::deobfs::{self, Deobfuscator};
+use crate::error::ExtractError;
+
+/// Input is handed to plugins this much at a time.
+const CHUNK_SIZE: usize = 1 << 20; // 1 MiB
+
+pub struct Plugin {
+    name: String,
+    block_size: usize,
+    phase: usize,
+    // calls need exclusive access to the instance
+    instance: Mutex<Instance>,
+}
+
+struct Instance {
+    store: Store<()>,
+    memory: Memory,
+    alloc: TypedFunc<i32, i32>,
+    transform: TypedFunc<(i32, i32, i64), ()>,
+}
+
+impl Plugin {
+    pub fn load(path: &Path) -> Result<Self, ExtractError> {
+        let wasm = fs::read(path)?;
+        let name = path
+            .file_stem()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .into_owned();
+        Self::instantiate(name, &wasm).map_err(ExtractError::Plugin)
+    }
+
+    fn instantiate(name: String, wasm: &[u8]) -> Result<Self, String> {
+        let engine = Engine::default();
+        let module = Module::new(&engine, wasm).map_err(|err| err.to_string())?;
+        let mut store = Store::new(&engine, ());
+        let instance = <Linker<()>>::new(&engine)
+            .instantiate_and_start(&mut store, &module)
+            .map_err(|err| err.to_string())?;
+
+        let memory = instance
+            .get_memory(&store, "memory")
+            .ok_or("no exported memory")?;
+        let alloc = instance
+            .get_typed_func(&store, "alloc")
+            .map_err(|err| format!("alloc: {}", err))?;
+        let transform = instance
+            .get_typed_func(&store, "transform")
+            .map_err(|err| format!("transform: {}", err))?;
+        let mut number = |name: &str| -> Result<Option<usize>, String> {
+            match instance.get_typed_func::<(), i32>(&store, name) {
+                Ok(f) => {
+                    let n = f.call(&mut store, ()).map_err(|err| err.to_string())?;
+                    usize::try_from(n)
+                        .map(Some)
+                        .map_err(|_| format!("invalid {} {}", name, n))
+                }
+                Err(_) => Ok(None),
+            }
+        };
+        let block_size = number("block_size")?.unwrap_or(1);
+        let phase = number("phase")?.unwrap_or(0);
+        if block_size == 0 {
+            return Err("block_size must not be 0".to_owned());
+        }
+
+        Ok(Self {
+            name,
+            block_size,
+            phase,
+            instance: Mutex::new(Instance {
+                store,
+                memory,
+                alloc,
+                transform,
+            }),
+        })
+    }
+}
+
+impl Instance {
+    fn transform(&mut self, data: &mut [u8], pos: usize) -> Result<(), String> {
+        let len = i32::try_from(data.len()).map_err(|_| "chunk too large")?;
+        let ptr = self
+            .alloc
+            .call(&mut self.store, len)
+            .map_err(|err| err.to_string())?;
+        let offset = usize::try_from(ptr).map_err(|_| format!("invalid pointer {}", ptr))?;
+        self.memory
+            .write(&mut self.store, offset, data)
+            .map_err(|err| err.to_string())?;
+        self.transform
+            .call(&mut self.store, (ptr, len, pos as i64))
+            .map_err(|err| err.to_string())?;
+        self.memory
+            .read(&self.store, offset, data)
+            .map_err(|err| err.to_string())
+    }
+}
+
+impl fmt::Display for Plugin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "plugin({})", self.name)
+    }
+}
+
+impl Deobfuscator for Plugin {
+    fn block_size(&self) -> usize {
+        self.block_size
+    }
+
+    fn phase(&self) -> usize {
+        self.phase
+    }
+
+    fn apply(&self, data: &mut [u8], pos: usize) {
+        let mut instance = self.instance.lock().unwrap_or_else(|e| e.into_inner());
+        let mut start = 0;
+        while start < data.len() {
+            // chunks end on block boundaries
+            let next = pos + start + CHUNK_SIZE.max(self.block_size);
+            let boundary = deobfs::align(self, next..next, usize::MAX).start - pos;
+            let end = if boundary > start {
+                boundary.min(data.len())
+            } else {
+                data.len()
+            };
+            if let Err(err) = instance.transform(&mut data[start..end], pos + start) {
+                // the data is left as it is, so nothing will be found in it
+                error!("Error running {}: {}", self, err);
+                return;
+            }
+            start = end;
+        }
+    }
+
+    // plugins only know how to deobfuscate
+    fn inverse(&self) -> Option<Box<dyn Deobfuscator>> {
+        None
+    }
+}
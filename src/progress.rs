@@ -0,0 +1,72 @@
+use crate::input::Input;
+use indicatif::style::TemplateError;
+use indicatif::{HumanBytes, MultiProgress, ProgressBar, ProgressStyle};
+use std::fs;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Progress display for a batch run: one overall bar plus a spinner for every
+/// input currently being processed.
+pub struct Progress {
+    multi: MultiProgress,
+    overall: ProgressBar,
+    /// Style of the spinners of inputs being processed
+    spinner: ProgressStyle,
+    bytes: AtomicU64,
+}
+
+/// The spinner of an input being processed.
+pub struct Active {
+    bar: ProgressBar,
+    /// Size of the input
+    len: u64,
+}
+
+impl Progress {
+    pub fn new(inputs: &[Input]) -> Result<Self, TemplateError> {
+        let multi = MultiProgress::new();
+        let overall = multi.add(ProgressBar::new(inputs.len() as u64));
+        overall.set_style(ProgressStyle::with_template(
+            "[{elapsed_precise}] {bar:40} {pos}/{len} files, {msg} processed",
+        )?);
+        overall.set_message(HumanBytes(0).to_string());
+        Ok(Self {
+            multi,
+            overall,
+            spinner: ProgressStyle::with_template("  {spinner} {msg}")?,
+            bytes: AtomicU64::new(0),
+        })
+    }
+
+    pub fn multi(&self) -> &MultiProgress {
+        &self.multi
+    }
+
+    /// Show `input` as active until `finish` is called with the returned spinner.
+    pub fn start(&self, input: &Input) -> Active {
+        let bar = self.multi.add(ProgressBar::new_spinner());
+        bar.set_style(self.spinner.clone());
+        bar.set_message(input.to_string());
+        bar.enable_steady_tick(std::time::Duration::from_millis(100));
+        // the size when processing starts: by the end, `--move-source` or
+        // `--remove-source` may have taken the file away
+        let len = match input {
+            Input::File(path) => fs::metadata(path).map(|m| m.len()).unwrap_or(0),
+            Input::Stdin => 0,
+        };
+        Active { bar, len }
+    }
+
+    pub fn finish(&self, active: Active) {
+        let Active { bar, len } = active;
+        bar.finish_and_clear();
+        self.multi.remove(&bar);
+
+        let bytes = self.bytes.fetch_add(len, Ordering::Relaxed) + len;
+        self.overall.set_message(HumanBytes(bytes).to_string());
+        self.overall.inc(1);
+    }
+
+    pub fn finish_all(&self) {
+        self.overall.finish();
+    }
+}
@@ -0,0 +1,64 @@
+use crate::log;
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// How the user chose to resolve an existing output file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Conflict {
+    Overwrite,
+    Skip,
+    Rename(PathBuf),
+}
+
+// answer to remember for the rest of the run after [A]ll or [N]one; the lock
+// also keeps prompts from parallel workers from interleaving
+static REMEMBERED: Mutex<Option<Conflict>> = Mutex::new(None);
+
+/// Ask the user what to do about `path` already existing, like `unzip` does.
+///
+/// Only call this when stdin and stderr are terminals. End of input is taken
+/// as "skip".
+pub fn ask_conflict(path: &Path) -> io::Result<Conflict> {
+    let mut remembered = REMEMBERED.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(answer) = &*remembered {
+        return Ok(answer.clone());
+    }
+
+    log::suspend(|| loop {
+        let answer = read_answer(&format!(
+            "replace {:?}? [y]es, [n]o, [A]ll, [N]one, [r]ename: ",
+            path
+        ))?;
+        let answer = match answer.as_deref() {
+            None => Conflict::Skip,
+            Some("y") => Conflict::Overwrite,
+            Some("n") => Conflict::Skip,
+            Some("A") => {
+                *remembered = Some(Conflict::Overwrite);
+                Conflict::Overwrite
+            }
+            Some("N") => {
+                *remembered = Some(Conflict::Skip);
+                Conflict::Skip
+            }
+            Some("r") => match read_answer("new name: ")? {
+                Some(name) if !name.is_empty() => Conflict::Rename(path.with_file_name(name)),
+                _ => continue,
+            },
+            Some(_) => continue,
+        };
+        return Ok(answer);
+    })
+}
+
+fn read_answer(prompt: &str) -> io::Result<Option<String>> {
+    eprint!("{}", prompt);
+    io::stderr().flush()?;
+    let mut line = String::new();
+    if io::stdin().lock().read_line(&mut line)? == 0 {
+        eprintln!();
+        return Ok(None);
+    }
+    Ok(Some(line.trim().to_owned()))
+}
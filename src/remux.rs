@@ -0,0 +1,282 @@
+//! Remuxing carved streams into another container, asked for with `--remux`:
+//! for now ADTS streams into M4A files, which unlike bare `.aac` files have a
+//! sample table to seek with and play nearly everywhere.
+
+use std::str::FromStr;
+
+use crate::adts::{self, AdtsHeader};
+use crate::carve::{audio_start, Format, Stream};
+
+/// Container asked for with `--remux`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Remux {
+    M4a,
+}
+
+impl FromStr for Remux {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "m4a" => Ok(Remux::M4a),
+            _ => Err(format!("can't remux into {:?}, only m4a", s)),
+        }
+    }
+}
+
+impl Remux {
+    /// Remux `stream` in place, if it is of a format this container takes.
+    /// Returns whether it was; tags around the stream are left out.
+    pub fn apply(self, stream: &mut Stream) -> bool {
+        match (self, stream.format) {
+            (Remux::M4a, Format::Adts) => match adts_to_m4a(&stream.data) {
+                Some(data) => {
+                    stream.data = data;
+                    stream.format = Format::Mp4;
+                    stream.tags.clear();
+                    true
+                }
+                None => false,
+            },
+            _ => false,
+        }
+    }
+}
+
+/// A box of type `kind` holding `contents`.
+fn boxed(kind: &[u8; 4], contents: &[&[u8]]) -> Vec<u8> {
+    let size = 8 + contents.iter().map(|part| part.len()).sum::<usize>();
+    let mut out = Vec::with_capacity(size);
+    out.extend_from_slice(&(size as u32).to_be_bytes());
+    out.extend_from_slice(kind);
+    contents.iter().for_each(|part| out.extend_from_slice(part));
+    out
+}
+
+/// A full box: one with a version (always 0 here) and flags before its
+/// contents.
+fn full_box(kind: &[u8; 4], flags: u32, contents: &[&[u8]]) -> Vec<u8> {
+    let flags = flags.to_be_bytes();
+    let mut parts = vec![&flags[..]];
+    parts.extend_from_slice(contents);
+    boxed(kind, &parts)
+}
+
+/// An MPEG-4 descriptor, as in `esds`, for contents under 128 bytes.
+fn descriptor(tag: u8, contents: &[&[u8]]) -> Vec<u8> {
+    let len = contents.iter().map(|part| part.len()).sum::<usize>();
+    let mut out = vec![tag, len as u8];
+    contents.iter().for_each(|part| out.extend_from_slice(part));
+    out
+}
+
+/// The identity matrix of `mvhd` and `tkhd`, in 16.16 and 2.30 fixed point.
+fn matrix() -> Vec<u8> {
+    [0x00010000u32, 0, 0, 0, 0x00010000, 0, 0, 0, 0x40000000]
+        .iter()
+        .flat_map(|n| n.to_be_bytes())
+        .collect()
+}
+
+fn be(n: u32) -> [u8; 4] {
+    n.to_be_bytes()
+}
+
+/// An M4A file with the AAC frames of the ADTS stream `data`. `None` if the
+/// stream can't be put in one as it is: frames with more than one raw AAC
+/// frame, or a channel configuration given in the stream.
+pub fn adts_to_m4a(data: &[u8]) -> Option<Vec<u8>> {
+    let mut rest = &data[audio_start(data)..];
+    let mut first: Option<AdtsHeader> = None;
+    let mut sizes = Vec::new();
+    let mut samples = Vec::new();
+    while let Some(frame) = adts::parse_header(rest).filter(|frame| frame.length <= rest.len()) {
+        if frame.blocks() != 1 || frame.channels == 0 || frame.header_len >= frame.length {
+            return None;
+        }
+        if first.is_some_and(|first| !frame.continues(&first)) {
+            break;
+        }
+        first.get_or_insert(frame);
+        samples.extend_from_slice(&rest[frame.header_len..frame.length]);
+        sizes.push((frame.length - frame.header_len) as u32);
+        rest = &rest[frame.length..];
+    }
+    let first = first?;
+    let rate = first.sample_rate;
+    let count = sizes.len() as u32;
+    let duration = count * 1024;
+    let bit_rate = (samples.len() as u64 * 8 * u64::from(rate) / u64::from(duration)) as u32;
+
+    let ftyp = boxed(b"ftyp", &[b"M4A ", &be(0), b"M4A mp42isom"]);
+    let mvhd = full_box(
+        b"mvhd",
+        0,
+        &[
+            &be(0),
+            &be(0),
+            &be(rate),
+            &be(duration),
+            &be(0x00010000),
+            &[1, 0],
+            &[0; 10],
+            &matrix(),
+            &[0; 24],
+            &be(2),
+        ],
+    );
+    // enabled and in the movie
+    let tkhd = full_box(
+        b"tkhd",
+        0x000003,
+        &[
+            &be(0),
+            &be(0),
+            &be(1),
+            &be(0),
+            &be(duration),
+            &[0; 8],
+            &[0; 4],
+            &[1, 0],
+            &[0; 2],
+            &matrix(),
+            &be(0),
+            &be(0),
+        ],
+    );
+    // the language is `und`, packed into three five bit letters
+    let mdhd = full_box(
+        b"mdhd",
+        0,
+        &[
+            &be(0),
+            &be(0),
+            &be(rate),
+            &be(duration),
+            &[0x55, 0xC4],
+            &[0; 2],
+        ],
+    );
+    let hdlr = full_box(b"hdlr", 0, &[&be(0), b"soun", &[0; 12], b"SoundHandler\0"]);
+    let smhd = full_box(b"smhd", 0, &[&[0; 4]]);
+    let dinf = boxed(
+        b"dinf",
+        &[&full_box(b"dref", 0, &[&be(1), &full_box(b"url ", 1, &[])])],
+    );
+
+    // AAC (object type indication 0x40) in an audio stream (5, shifted, with
+    // the reserved bit set)
+    let config = first.audio_specific_config();
+    let decoder_config = descriptor(
+        0x04,
+        &[
+            &[0x40, 0x15],
+            &[0; 3],
+            &be(bit_rate),
+            &be(bit_rate),
+            &descriptor(0x05, &[&config]),
+        ],
+    );
+    let es = descriptor(
+        0x03,
+        &[&[0, 1, 0], &decoder_config, &descriptor(0x06, &[&[0x02]])],
+    );
+    let esds = full_box(b"esds", 0, &[&es]);
+    let channels = u16::from(first.channels.min(2)).to_be_bytes();
+    // 16.16 fixed point, where 88.2 and 96 kHz don't fit; those are left 0,
+    // the timescale and the decoder config have the rate too
+    let fixed_rate = if rate > 0xFFFF { 0 } else { rate << 16 };
+    let mp4a = boxed(
+        b"mp4a",
+        &[
+            &[0; 6],
+            &[0, 1],
+            &[0; 8],
+            &channels,
+            &[0, 16],
+            &[0; 4],
+            &be(fixed_rate),
+            &esds,
+        ],
+    );
+    let stsd = full_box(b"stsd", 0, &[&be(1), &mp4a]);
+    let stts = full_box(b"stts", 0, &[&be(1), &be(count), &be(1024)]);
+    // every frame in a single chunk
+    let stsc = full_box(b"stsc", 0, &[&be(1), &be(1), &be(count), &be(1)]);
+    let table: Vec<u8> = sizes.iter().flat_map(|size| size.to_be_bytes()).collect();
+    let stsz = full_box(b"stsz", 0, &[&be(0), &be(count), &table]);
+
+    // `moov` goes first, so the chunk offset is known once its size is
+    let moov = |chunk_offset: u32| {
+        let stco = full_box(b"stco", 0, &[&be(1), &be(chunk_offset)]);
+        let stbl = boxed(b"stbl", &[&stsd, &stts, &stsc, &stsz, &stco]);
+        let minf = boxed(b"minf", &[&smhd, &dinf, &stbl]);
+        let mdia = boxed(b"mdia", &[&mdhd, &hdlr, &minf]);
+        let trak = boxed(b"trak", &[&tkhd, &mdia]);
+        boxed(b"moov", &[&mvhd, &trak])
+    };
+    let chunk_offset = ftyp.len() + moov(0).len() + 8;
+    let mut out = ftyp;
+    out.extend_from_slice(&moov(chunk_offset as u32));
+    out.extend_from_slice(&boxed(b"mdat", &[&samples]));
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::carve::{extract, ScanOptions};
+    use std::convert::TryInto;
+
+    /// `count` ADTS frames of AAC LC at 44.1 kHz, stereo, `length` bytes each.
+    fn adts_frames(count: usize, length: usize) -> Vec<u8> {
+        let mut frame = vec![
+            0xFF,
+            0xF1,
+            (1 << 6) | (4 << 2),
+            (2 << 6) | (length >> 11) as u8,
+            (length >> 3) as u8,
+            ((length & 7) << 5) as u8 | 0x1F,
+            0xFC,
+        ];
+        frame.resize(length, 0);
+        frame.repeat(count)
+    }
+
+    #[test]
+    fn adts_remuxed_into_an_m4a_that_carves_whole() {
+        let m4a = adts_to_m4a(&adts_frames(300, 200)).unwrap();
+        assert_eq!(&m4a[4..12], b"ftypM4A ");
+
+        // the chunk offset points at the frames in `mdat`
+        let stco = m4a.windows(4).position(|w| w == b"stco").unwrap();
+        let chunk = u32::from_be_bytes(m4a[stco + 12..stco + 16].try_into().unwrap()) as usize;
+        assert_eq!(&m4a[chunk - 4..chunk], b"mdat");
+        assert_eq!(m4a.len() - chunk, 300 * 193);
+
+        let mut input = vec![0; 1000];
+        input.extend_from_slice(&m4a);
+        input.extend_from_slice(&[0; 1000]);
+        let options = ScanOptions {
+            threshold: 0,
+            ..ScanOptions::default()
+        };
+        let streams = extract(input, &options).unwrap();
+        assert_eq!(streams.len(), 1);
+        let stream = &streams[0];
+        assert_eq!(stream.format, Format::Mp4);
+        assert_eq!((stream.offset, stream.data.len()), (1000, m4a.len()));
+        assert_eq!(stream.sample_rate, 44100);
+        assert_eq!(stream.samples, 300 * 1024);
+    }
+
+    #[test]
+    fn channel_configuration_in_the_stream_is_left_alone() {
+        let mut data = adts_frames(10, 200);
+        for frame in data.chunks_mut(200) {
+            frame[2] &= !0x01;
+            frame[3] &= 0x3F;
+        }
+        assert_eq!(adts_to_m4a(&data), None);
+    }
+}
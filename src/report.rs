@@ -0,0 +1,280 @@
+use crate::error::ExtractError;
+use crate::input::Input;
+use serde::{Serialize, Serializer};
+use std::borrow::Cow;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+/// What happened to a single input.
+#[derive(Debug, Serialize)]
+pub struct FileReport {
+    pub input: Input,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<ExtractError>,
+    pub outputs: Vec<OutputReport>,
+    /// Skipped because `--state` says it was already processed
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub resumed: bool,
+    /// Why the input wasn't scanned at all, e.g. because of its size
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub skipped: Option<String>,
+    /// Nothing was found and the input looks encrypted rather than obfuscated
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub encrypted: bool,
+}
+
+/// A single extracted stream.
+#[derive(Debug, Serialize)]
+pub struct OutputReport {
+    /// Where the stream was (or would be) written, `None` for stdout
+    #[serde(serialize_with = "serialize_path")]
+    pub path: Option<PathBuf>,
+    /// Which of the scheme's deobfuscators found the stream; for plain `swap`
+    /// this is the swap offset
+    pub variant: usize,
+    /// The deobfuscator itself, e.g. `swap(period 4, offset 1)`
+    pub deobfuscation: String,
+    /// Format of the stream, as its file extension, e.g. `mp3` or `aac`
+    pub format: String,
+    pub offset: usize,
+    pub size: usize,
+    pub frames: usize,
+    pub bit_rate: u32,
+    pub sample_rate: u32,
+    /// Estimated duration in seconds
+    pub duration: f64,
+    /// E.g. `joint stereo`, for MPEG audio
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub channel_mode: Option<String>,
+    /// The stream ends before its format says it should
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub truncated: bool,
+    /// Tags extracted with the stream, e.g. `ID3v2`
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    /// Frames whose CRC doesn't match
+    #[serde(skip_serializing_if = "is_zero")]
+    pub corrupt_frames: usize,
+    /// Bytes left out from between frames, with `--clean` or `--repair`
+    #[serde(skip_serializing_if = "is_zero")]
+    pub dropped_bytes: usize,
+    /// Samples of encoder delay at the start, from a LAME or `iTunSMPB` tag
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub encoder_delay: Option<u32>,
+    /// Samples of padding at the end, likewise
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub encoder_padding: Option<u32>,
+    /// The output already existed and was left alone
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub skipped: bool,
+    /// Hex digest of the written file, with `--checksums`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub checksum: Option<String>,
+    /// Where the cover art was written, with `--extract-art`
+    #[serde(
+        serialize_with = "serialize_path",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub art: Option<PathBuf>,
+}
+
+impl FileReport {
+    pub fn new(input: &Input) -> Self {
+        Self {
+            input: input.clone(),
+            error: None,
+            outputs: Vec::new(),
+            resumed: false,
+            skipped: None,
+            encrypted: false,
+        }
+    }
+
+    /// Why this input didn't produce anything, if it should have.
+    pub fn failure(&self) -> Option<Cow<'_, str>> {
+        match &self.error {
+            Some(err) => Some(err.to_string().into()),
+            None if self.encrypted => Some("likely AES-encrypted, key required".into()),
+            None if self.outputs.is_empty() && !self.resumed && self.skipped.is_none() => {
+                Some("no audio found".into())
+            }
+            None => None,
+        }
+    }
+
+    /// The report of an input that failed with `err`.
+    pub fn failed(input: &Input, err: ExtractError) -> Self {
+        Self {
+            error: Some(err),
+            ..Self::new(input)
+        }
+    }
+}
+
+impl OutputReport {
+    /// What the stream is, e.g. `mp3, 150 frames, 128 kbps, 44100 Hz, 3.9s`.
+    pub fn summary(&self) -> String {
+        format!(
+            "{}, {} frames, {} kbps, {} Hz, {:.1}s",
+            self.format,
+            self.frames,
+            self.bit_rate / 1000,
+            self.sample_rate,
+            self.duration
+        )
+    }
+}
+
+/// One line per input that failed or had no audio, e.g. `"a.amp": not a file`.
+pub fn failure_list(reports: &[FileReport]) -> String {
+    reports
+        .iter()
+        .filter_map(|r| Some(format!("{}: {}\n", r.input, r.failure()?)))
+        .collect()
+}
+
+/// Paths as (lossy) strings; report formats are text, and serde would fail on
+/// names that aren't valid UTF-8 instead.
+fn serialize_path<S: Serializer>(path: &Option<PathBuf>, serializer: S) -> Result<S::Ok, S::Error> {
+    match path {
+        Some(path) => serializer.serialize_some(&path.to_string_lossy()),
+        None => serializer.serialize_none(),
+    }
+}
+
+fn is_zero(n: &usize) -> bool {
+    *n == 0
+}
+
+impl Serialize for Input {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Input::File(path) => serializer.serialize_str(&path.to_string_lossy()),
+            Input::Stdin => serializer.serialize_str("-"),
+        }
+    }
+}
+
+/// Totals for a whole run, printed once it finishes.
+#[derive(Debug, Default)]
+pub struct Summary {
+    pub files: usize,
+    pub files_with_audio: usize,
+    pub mp3s: usize,
+    pub bytes_written: u64,
+    pub failures: usize,
+    pub elapsed: Duration,
+    /// Nothing was actually written (`--dry-run`)
+    pub dry_run: bool,
+}
+
+impl Summary {
+    pub fn new(reports: &[FileReport], elapsed: Duration, dry_run: bool) -> Self {
+        let mut summary = Self {
+            elapsed,
+            dry_run,
+            ..Self::default()
+        };
+        for report in reports {
+            summary.files += 1;
+            if !report.outputs.is_empty() {
+                summary.files_with_audio += 1;
+            }
+            if report.error.is_some() {
+                summary.failures += 1;
+            }
+            for output in report.outputs.iter().filter(|o| !o.skipped) {
+                summary.mp3s += 1;
+                summary.bytes_written += output.size as u64;
+            }
+        }
+        summary
+    }
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} file(s) processed, {} with audio, {} mp3(s) {}extracted ({} bytes), {} failure(s) in {:.2}s",
+            self.files,
+            self.files_with_audio,
+            self.mp3s,
+            if self.dry_run { "would be " } else { "" },
+            self.bytes_written,
+            self.failures,
+            self.elapsed.as_secs_f64()
+        )
+    }
+}
+
+/// A report file requested with `--report <format>=<path>`.
+#[derive(Debug, Clone)]
+pub struct ReportSpec {
+    pub format: ReportFormat,
+    pub path: PathBuf,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReportFormat {
+    Csv,
+}
+
+impl FromStr for ReportSpec {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (format, path) = s
+            .split_once('=')
+            .ok_or_else(|| format!("expected <format>=<path>, got {:?}", s))?;
+        let format = match format {
+            "csv" => ReportFormat::Csv,
+            _ => return Err(format!("unknown report format {:?}", format)),
+        };
+        if path.is_empty() {
+            return Err("missing report path".to_owned());
+        }
+        Ok(Self {
+            format,
+            path: PathBuf::from(path),
+        })
+    }
+}
+
+impl ReportSpec {
+    pub fn write(&self, reports: &[FileReport]) -> Result<(), csv::Error> {
+        match self.format {
+            ReportFormat::Csv => write_csv(&self.path, reports),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct CsvRow<'a> {
+    source: &'a Input,
+    output: Option<Cow<'a, str>>,
+    offset: usize,
+    size: usize,
+    duration: f64,
+    deobfuscation: &'a str,
+}
+
+fn write_csv(path: &Path, reports: &[FileReport]) -> Result<(), csv::Error> {
+    let mut writer = csv::Writer::from_path(path)?;
+    for report in reports {
+        for output in &report.outputs {
+            writer.serialize(CsvRow {
+                source: &report.input,
+                output: output.path.as_deref().map(Path::to_string_lossy),
+                offset: output.offset,
+                size: output.size,
+                duration: output.duration,
+                deobfuscation: &output.deobfuscation,
+            })?;
+        }
+    }
+    writer.flush()?;
+    Ok(())
+}
@@ -0,0 +1,126 @@
+//! `--check-side-info`: a closer look at carved MP3 streams than the scan's
+//! header checks, to weed out runs of plausible headers around garbage. The
+//! side information of each Layer III frame is read as a decoder reads it,
+//! and checked for what no encoder writes: more Huffman data than the frame
+//! and the bit reservoir hold, more than 576 spectral values, Huffman tables
+//! that don't exist, or switched windows without a block type. This is no
+//! decode: the main data the side information points at isn't looked at, so
+//! a frame with sound side information and corrupt audio passes.
+
+use tracing::trace;
+
+use crate::carve::{audio_start, Format, Stream};
+use crate::mp3::{self, Layer};
+
+/// Frames of a stream that may fail the check without it being withheld, as
+/// a stream cut out of the middle of another starts with a few that need the
+/// bit reservoir of frames that weren't carved.
+const MAX_BAD_FRAMES: usize = 3;
+
+/// Beyond `MAX_BAD_FRAMES`, the stream is withheld once more than one in this
+/// many of its frames fail the check.
+const MAX_BAD_SHARE: usize = 20;
+
+/// How the frames of a stream fared, here or when decoded with `--verify`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Verdict {
+    /// Frames checked, up to the first header that doesn't parse
+    pub frames: usize,
+    /// Frames that failed the check
+    pub bad: usize,
+}
+
+impl Verdict {
+    /// Whether the stream is more likely garbage than audio.
+    pub fn fails(&self) -> bool {
+        self.bad > MAX_BAD_FRAMES && self.bad * MAX_BAD_SHARE > self.frames
+    }
+}
+
+/// Check the frames of `stream`; `None` for streams that can't be checked,
+/// i.e. anything but Layer III frames with a bitrate in their headers.
+pub fn check(stream: &Stream) -> Option<Verdict> {
+    if stream.format != Format::Mp3 {
+        return None;
+    }
+    let data = &stream.data;
+    let mut pos = audio_start(data);
+    let mut verdict = Verdict { frames: 0, bad: 0 };
+    let mut reservoir = 0;
+    while let Some(bytes) = data.get(pos..pos + 4) {
+        let header = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        let frame = match mp3::parse_header(header) {
+            Ok(frame) if frame.length <= data.len() - pos => frame,
+            _ => break,
+        };
+        if frame.layer != Layer::L3 || frame.is_free_format() {
+            return None;
+        }
+        verdict.frames += 1;
+        let side_info = frame.side_info(&data[pos..pos + frame.length]);
+        // the frame's main data starts in the reservoir and fits in it and
+        // the frame
+        let sound = side_info.filter(|side_info| {
+            side_info.main_data_begin <= reservoir
+                && side_info.part2_3_bits <= 8 * (side_info.main_data_begin + frame.main_data_len())
+        });
+        if sound.is_none() {
+            trace!("{:#x}: bad side information", stream.offset + pos);
+            verdict.bad += 1;
+        }
+        // the reservoir goes back no further than `main_data_begin` reaches
+        reservoir = (reservoir + frame.main_data_len()).min(511);
+        pos += frame.length;
+    }
+    Some(verdict).filter(|verdict| verdict.frames > 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::carve::{extract, ScanOptions};
+
+    /// 200 MPEG-1 Layer III frames at 128 kbps and 44.1 kHz, stereo, each
+    /// made of `fill` after the header.
+    fn carved(fill: impl Fn(usize) -> u8) -> Stream {
+        let mut input = Vec::new();
+        for n in 0..200 {
+            let mut frame: Vec<u8> = (0..417).map(|i| fill(n * 417 + i)).collect();
+            frame[..4].copy_from_slice(&0xFFFB_9000u32.to_be_bytes());
+            input.extend_from_slice(&frame);
+        }
+        let mut streams = extract(input, &ScanOptions::default()).unwrap();
+        assert_eq!(streams.len(), 1);
+        streams.remove(0)
+    }
+
+    #[test]
+    fn empty_side_information_is_sound() {
+        let verdict = check(&carved(|_| 0)).unwrap();
+        assert_eq!(
+            verdict,
+            Verdict {
+                frames: 200,
+                bad: 0
+            }
+        );
+        assert!(!verdict.fails());
+    }
+
+    #[test]
+    fn garbage_side_information_fails() {
+        let verdict = check(&carved(|i| (i.wrapping_mul(2_654_435_761) >> 16) as u8)).unwrap();
+        assert_eq!(verdict.frames, 200);
+        assert!(verdict.fails(), "{:?}", verdict);
+    }
+
+    #[test]
+    fn layer_two_streams_are_not_checked() {
+        let mut stream = carved(|_| 0);
+        for frame in stream.data.chunks_mut(417) {
+            // Layer II, the bitrate index kept
+            frame[1] = 0xFD;
+        }
+        assert_eq!(check(&stream), None);
+    }
+}
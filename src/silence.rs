@@ -0,0 +1,235 @@
+//! `--trim-silence`: cutting the silence apps pad downloads with off the
+//! start and end of carved MP3 streams. Each frame is decoded, and runs of
+//! frames whose samples all stay below `SILENCE_PEAK` are cut at frame
+//! boundaries; that takes in dithered silence and the low hiss some encoders
+//! leave, not only digital zeros.
+
+use tracing::debug;
+
+use crate::carve::{audio_start, Format, Stream};
+use crate::decode::MpegDecoder;
+use crate::mp3::{self, FrameHeader, Layer};
+use crate::{ape, id3, xing};
+
+/// Shortest run of silence cut off, in seconds; shorter ones are part of the
+/// track.
+const MIN_SILENCE: f64 = 1.0;
+
+/// Loudest sample of a frame taken for silence, -60 dBFS.
+const SILENCE_PEAK: f32 = 0.001;
+
+/// A frame of the stream, where it starts in it.
+struct Frame {
+    pos: usize,
+    header: FrameHeader,
+    /// Decodes to nothing louder than `SILENCE_PEAK`; a frame that doesn't
+    /// decode isn't silent
+    silent: bool,
+    /// From the side information of a Layer III frame, 0 where there is none
+    main_data_begin: usize,
+}
+
+/// The frames of the stream `data` from `pos` on, decoded in order, up to
+/// the first header that doesn't parse; `None` if there are free format
+/// frames.
+fn frames(data: &[u8], mut pos: usize) -> Option<Vec<Frame>> {
+    let mut frames = Vec::new();
+    let mut decoder = None;
+    while let Some(bytes) = data.get(pos..pos + 4) {
+        let header = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        let header = match mp3::parse_header(header) {
+            Ok(header) if header.length <= data.len() - pos => header,
+            _ => break,
+        };
+        if header.is_free_format() {
+            return None;
+        }
+        let bytes = &data[pos..pos + header.length];
+        let decoder = decoder.get_or_insert_with(|| MpegDecoder::new(header.layer));
+        let side_info = Some(header)
+            .filter(|header| header.layer == Layer::L3)
+            .and_then(|header| header.side_info(bytes));
+        frames.push(Frame {
+            pos,
+            header,
+            silent: decoder.peak(bytes).is_some_and(|peak| peak < SILENCE_PEAK),
+            main_data_begin: side_info.map_or(0, |side_info| side_info.main_data_begin),
+        });
+        pos += header.length;
+    }
+    Some(frames)
+}
+
+/// Whether nothing but the tags carved after a stream's frames, APEv2 then
+/// ID3v1, come after `pos` in `data`.
+fn only_tags_after(data: &[u8], pos: usize) -> bool {
+    let mut rest = &data[pos..];
+    if let Some(size) = ape::tag_size(rest).filter(|&size| size <= rest.len()) {
+        rest = &rest[size..];
+    }
+    rest.is_empty() || (rest.len() == id3::V1_SIZE && id3::is_v1(rest))
+}
+
+/// Playing time of `frames`, in seconds.
+fn duration(frames: &[Frame]) -> f64 {
+    frames
+        .iter()
+        .map(|frame| f64::from(frame.header.samples) / f64::from(frame.header.sample_rate))
+        .sum()
+}
+
+/// Cut a second or more of silence off either end of `stream`, an MP3 stream;
+/// nothing is done for other streams, free format ones, ones that are silent
+/// all through, or damaged ones, whose frames can't all be followed. A silent frame is kept next to the audio at either end, for the
+/// decoder's overlap, and at the start as many as the first audible frame
+/// takes main data from. Tags and a Xing/Info frame stay. Returns whether
+/// anything was cut.
+pub fn trim(stream: &mut Stream) -> bool {
+    if stream.format != Format::Mp3 {
+        return false;
+    }
+    if stream.corrupt_frames > 0 || !stream.gaps.is_empty() {
+        debug!(
+            "{:#x}: not looking for silence in a damaged stream",
+            stream.offset
+        );
+        return false;
+    }
+    let mut start = audio_start(&stream.data);
+    if xing::is_header_frame(&stream.data[start..]) {
+        let header = stream.data.get(start..start + 4).and_then(|bytes| {
+            mp3::parse_header(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])).ok()
+        });
+        start += header.map_or(0, |header| header.length);
+    }
+    let frames = match frames(&stream.data, start) {
+        Some(frames) => frames,
+        None => return false,
+    };
+    let (first, last) = match (
+        frames.iter().position(|frame| !frame.silent),
+        frames.iter().rposition(|frame| !frame.silent),
+    ) {
+        (Some(first), Some(last)) => (first, last),
+        _ => return false,
+    };
+
+    let mut keep_from = 0;
+    if duration(&frames[..first]) >= MIN_SILENCE {
+        keep_from = first.saturating_sub(1);
+        let mut reservoir: usize = frames[keep_from..first]
+            .iter()
+            .map(|frame| frame.header.main_data_len())
+            .sum();
+        while keep_from > 0 && reservoir < frames[first].main_data_begin {
+            keep_from -= 1;
+            reservoir += frames[keep_from].header.main_data_len();
+        }
+    }
+    let audio_end = frames
+        .last()
+        .map_or(start, |frame| frame.pos + frame.header.length);
+    // the end is only cut if the frames found reach it
+    let mut keep_to = frames.len();
+    if only_tags_after(&stream.data, audio_end) && duration(&frames[last + 1..]) >= MIN_SILENCE {
+        keep_to = last + 2;
+    }
+    if keep_from == 0 && keep_to == frames.len() {
+        return false;
+    }
+    debug!(
+        "{:#x}: trimming {:.1}s of silence at the start and {:.1}s at the end",
+        stream.offset,
+        duration(&frames[..keep_from]),
+        duration(&frames[keep_to..])
+    );
+
+    let kept = &frames[keep_from..keep_to];
+    let cut_from = kept[0].pos;
+    let cut_to = kept
+        .last()
+        .map_or(cut_from, |frame| frame.pos + frame.header.length);
+    stream.data.drain(cut_to..audio_end);
+    stream.data.drain(start..cut_from);
+
+    let removed = frames[..keep_from].iter().chain(&frames[keep_to..]);
+    let (count, samples, bits) = removed.fold((0, 0, 0), |(count, samples, bits), frame| {
+        let header = &frame.header;
+        (
+            count + 1,
+            samples + u64::from(header.samples),
+            bits + u64::from(header.bit_rate),
+        )
+    });
+    let total_bits = u64::from(stream.bit_rate) * stream.frames as u64;
+    stream.frames -= count;
+    stream.samples = stream.samples.saturating_sub(samples);
+    stream.bit_rate = (total_bits.saturating_sub(bits) / stream.frames as u64) as u32;
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::carve::{extract, ScanOptions};
+
+    /// An MPEG-1 Layer III frame at 128 kbps and 44.1 kHz, mono, whose two
+    /// granules each hold four spectral values of 1 at `gain`, or nothing at
+    /// all.
+    fn frame(gain: Option<u8>) -> Vec<u8> {
+        let mut frame = vec![0; 417];
+        frame[..4].copy_from_slice(&0xFFFB_90C0u32.to_be_bytes());
+        if let Some(gain) = gain {
+            // main data begin, private bits and scale factor selection, then
+            // for each granule: 8 bits of Huffman data, no big values, the
+            // gain, and count1 table B, where `0000` is four values of 1
+            let granule = format!("{:012b}{:09b}{:08b}{:030b}", 8, 0, gain, 1);
+            let bits = "0".repeat(18) + &granule + &granule;
+            for (i, bit) in bits.bytes().enumerate() {
+                frame[4 + i / 8] |= (bit - b'0') << (7 - i % 8);
+            }
+        }
+        frame
+    }
+
+    fn carved(frames: &[(usize, Option<u8>)]) -> Stream {
+        let mut input = Vec::new();
+        for &(count, gain) in frames {
+            input.extend_from_slice(&frame(gain).repeat(count));
+        }
+        let mut streams = extract(input, &ScanOptions::default()).unwrap();
+        assert_eq!(streams.len(), 1);
+        streams.remove(0)
+    }
+
+    #[test]
+    fn quiet_and_silent_ends_are_cut() {
+        // a second and a half of frames too quiet to hear, audio, and a
+        // second and a half of digital silence
+        let header = mp3::parse_header(0xFFFB_90C0).unwrap();
+        let quiet = header.side_info(&frame(Some(160))).unwrap();
+        assert_eq!(quiet.part2_3_bits, 16);
+        let mut stream = carved(&[(60, Some(160)), (100, Some(200)), (60, None)]);
+        assert!(trim(&mut stream));
+        // a quiet frame is kept before the audio; after it, the first silent
+        // frame still has the audio's overlap in it, and one more is kept
+        assert_eq!(stream.frames, 1 + 100 + 2);
+        assert_eq!(stream.data.len(), 103 * 417);
+        assert_eq!(stream.data[..417], frame(Some(160))[..]);
+        assert_eq!(stream.data[417..2 * 417], frame(Some(200))[..]);
+        assert_eq!(stream.data[102 * 417..], frame(None)[..]);
+        assert!(!trim(&mut stream));
+    }
+
+    #[test]
+    fn short_silence_and_damaged_streams_are_left_alone() {
+        let mut stream = carved(&[(20, None), (100, Some(200)), (20, None)]);
+        assert!(!trim(&mut stream));
+        assert_eq!(stream.frames, 140);
+
+        let mut stream = carved(&[(60, None), (100, Some(200)), (60, None)]);
+        stream.corrupt_frames = 1;
+        assert!(!trim(&mut stream));
+        assert_eq!(stream.frames, 220);
+    }
+}
@@ -0,0 +1,28 @@
+/// Parse a byte count such as `51200`, `50k`, `1.5M` or `2GiB`.
+///
+/// Suffixes are binary multiples and case-insensitive; a trailing `b`/`ib` is
+/// optional.
+pub fn parse_size(s: &str) -> Result<u64, String> {
+    let s = s.trim();
+    let lower = s.to_ascii_lowercase();
+    let lower = lower
+        .strip_suffix("ib")
+        .or_else(|| lower.strip_suffix('b'))
+        .unwrap_or(&lower);
+
+    let (number, multiplier) = match lower.chars().last() {
+        Some('k') => (&lower[..lower.len() - 1], 1u64 << 10),
+        Some('m') => (&lower[..lower.len() - 1], 1 << 20),
+        Some('g') => (&lower[..lower.len() - 1], 1 << 30),
+        _ => (lower, 1),
+    };
+
+    let invalid = || format!("invalid size {:?}, expected e.g. 51200, 50k or 1m", s);
+    if let Ok(n) = number.parse::<u64>() {
+        return n.checked_mul(multiplier).ok_or_else(invalid);
+    }
+    match number.parse::<f64>() {
+        Ok(n) if n.is_finite() && n >= 0.0 => Ok((n * multiplier as f64) as u64),
+        _ => Err(invalid()),
+    }
+}
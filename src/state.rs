@@ -0,0 +1,92 @@
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use crate::output;
+
+/// Journal of inputs that were already processed, given with `--state`, so an
+/// interrupted run can pick up where it left off.
+///
+/// Inputs are identified by path and content hash, so a file that changed
+/// since it was processed is processed again. Every finished input is appended
+/// to the file straight away.
+pub struct State {
+    done: HashSet<Entry>,
+    file: Mutex<File>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+struct Entry {
+    path: PathBuf,
+    sha256: String,
+}
+
+impl State {
+    /// Open the journal at `path`, creating it if it doesn't exist yet.
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let mut done = HashSet::new();
+        if path.exists() {
+            for line in BufReader::new(File::open(path)?).lines() {
+                let line = line?;
+                if line.trim().is_empty() {
+                    continue;
+                }
+                // a run killed mid-write can leave a truncated last line
+                if let Ok(entry) = serde_json::from_str(&line) {
+                    done.insert(entry);
+                }
+            }
+        }
+
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        output::record_written(path);
+        Ok(Self {
+            done,
+            file: Mutex::new(file),
+        })
+    }
+
+    pub fn hash(data: &[u8]) -> String {
+        hex::encode(Sha256::digest(data))
+    }
+
+    /// Like `hash`, but reads the file at `path` in chunks.
+    pub fn hash_file(path: &Path) -> io::Result<String> {
+        let mut file = File::open(path)?;
+        let mut hasher = Sha256::new();
+        let mut chunk = vec![0; 1 << 20];
+        loop {
+            match file.read(&mut chunk) {
+                Ok(0) => break,
+                Ok(n) => hasher.update(&chunk[..n]),
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(hex::encode(hasher.finalize()))
+    }
+
+    pub fn is_done(&self, path: &Path, sha256: &str) -> bool {
+        self.done.contains(&Entry {
+            path: path.to_path_buf(),
+            sha256: sha256.to_owned(),
+        })
+    }
+
+    pub fn record(&self, path: &Path, sha256: &str) -> io::Result<()> {
+        let entry = Entry {
+            path: path.to_path_buf(),
+            sha256: sha256.to_owned(),
+        };
+        let mut line = serde_json::to_string(&entry)?;
+        line.push('\n');
+
+        let mut file = self.file.lock().unwrap_or_else(|e| e.into_inner());
+        file.write_all(line.as_bytes())?;
+        file.flush()
+    }
+}
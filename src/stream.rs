@@ -0,0 +1,214 @@
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::ops::Range;
+use std::path::Path;
+use std::sync::{mpsc, Arc, Condvar, Mutex, OnceLock};
+use std::thread;
+
+use crate::carve::{extract, ScanOptions, Stopped, Stream};
+use crate::deobfs::{self, DeobfuscatedChunks, DeobfuscatedFile, Deobfuscator};
+use crate::log;
+
+/// Chunks read ahead of the slowest scanner in `scan_all`.
+const CHUNKS_IN_FLIGHT: usize = 2;
+
+/// Scanner threads `scan_all` may run at once, over all inputs.
+struct Permits {
+    free: Mutex<usize>,
+    freed: Condvar,
+}
+
+/// Permits taken by one `scan_all` pass, given back when it is done.
+struct Taken<'a> {
+    permits: &'a Permits,
+    n: usize,
+}
+
+impl Permits {
+    /// As many as the thread pool has threads, i.e. `--jobs`.
+    fn get() -> &'static Permits {
+        static PERMITS: OnceLock<Permits> = OnceLock::new();
+        PERMITS.get_or_init(|| Permits {
+            free: Mutex::new(rayon::current_num_threads()),
+            freed: Condvar::new(),
+        })
+    }
+
+    /// Up to `n` permits, as many as are free once any are; a pass never
+    /// waits for more while holding some, so passes can't hold each other up
+    /// for good.
+    fn take(&self, n: usize) -> Taken<'_> {
+        let mut free = self.free.lock().unwrap_or_else(|e| e.into_inner());
+        while *free == 0 {
+            free = self.freed.wait(free).unwrap_or_else(|e| e.into_inner());
+        }
+        let n = n.min(*free);
+        *free -= n;
+        Taken { permits: self, n }
+    }
+}
+
+impl Drop for Taken<'_> {
+    fn drop(&mut self) {
+        *self.permits.free.lock().unwrap_or_else(|e| e.into_inner()) += self.n;
+        self.permits.freed.notify_all();
+    }
+}
+
+/// Scan `input` with each of `deobfuscators`, reading it as few times as
+/// possible: each pass reads it a chunk at a time, and every chunk is
+/// deobfuscated and scanned with several of them, each on a thread of its
+/// own. There are no more scanner threads over all inputs than `--jobs`, so
+/// with fewer free than there are deobfuscators, the input is read again for
+/// the rest. A scanner that stops early, e.g. abandoning a wrong variant,
+/// stops holding up the others. Results are in the order of `deobfuscators`.
+pub fn scan_all(
+    mut input: impl Read + Seek,
+    deobfuscators: &[&dyn Deobfuscator],
+    options: &ScanOptions,
+) -> io::Result<Vec<Result<Vec<Stream>, Stopped>>> {
+    let mut results = Vec::with_capacity(deobfuscators.len());
+    let mut rest = deobfuscators;
+    while !rest.is_empty() {
+        let taken = Permits::get().take(rest.len());
+        if rest.len() < deobfuscators.len() {
+            input.seek(SeekFrom::Start(0))?;
+        }
+        let (pass, later) = rest.split_at(taken.n);
+        results.extend(scan_pass(&mut input, pass, options)?);
+        rest = later;
+    }
+    Ok(results)
+}
+
+/// A single pass of `scan_all`, with a scanner thread per deobfuscator.
+fn scan_pass(
+    mut input: impl Read,
+    deobfuscators: &[&dyn Deobfuscator],
+    options: &ScanOptions,
+) -> io::Result<Vec<Result<Vec<Stream>, Stopped>>> {
+    // the scanners log as part of the input, and are held back with it
+    let span = tracing::Span::current();
+    let capturing = log::capturing();
+    thread::scope(|scope| {
+        let mut senders = Vec::new();
+        let scanners: Vec<_> = deobfuscators
+            .iter()
+            .map(|&deobfuscator| {
+                let (sender, chunks) = mpsc::sync_channel(CHUNKS_IN_FLIGHT);
+                senders.push(sender);
+                let span = span.clone();
+                scope.spawn(move || {
+                    let _entered = span.enter();
+                    let scan = || extract(DeobfuscatedChunks::new(chunks, deobfuscator), options);
+                    match capturing {
+                        true => {
+                            let (result, captured) = log::capture(scan);
+                            (result, Some(captured))
+                        }
+                        false => (scan(), None),
+                    }
+                })
+            })
+            .collect();
+
+        let mut read = Ok(());
+        while !senders.is_empty() {
+            let mut chunk = Vec::with_capacity(deobfs::CHUNK_SIZE);
+            match input
+                .by_ref()
+                .take(deobfs::CHUNK_SIZE as u64)
+                .read_to_end(&mut chunk)
+            {
+                Ok(0) => break,
+                Ok(_) => {
+                    let chunk = Arc::new(chunk);
+                    senders.retain(|sender| sender.send(Arc::clone(&chunk)).is_ok());
+                }
+                Err(err) => {
+                    read = Err(err);
+                    break;
+                }
+            }
+        }
+        drop(senders);
+
+        let results = scanners
+            .into_iter()
+            .map(|scanner| {
+                let (result, captured) = scanner
+                    .join()
+                    .unwrap_or_else(|panic| std::panic::resume_unwind(panic));
+                if let Some(captured) = captured {
+                    captured.emit();
+                }
+                result
+            })
+            .collect();
+        read.map(|_| results)
+    })
+}
+
+/// Deobfuscate the file at `path` and scan it, without holding more than a
+/// chunk of it in memory (plus whatever streams are found).
+pub fn scan_file(
+    path: &Path,
+    deobfuscator: &dyn Deobfuscator,
+    options: &ScanOptions,
+) -> io::Result<Result<Vec<Stream>, Stopped>> {
+    let mut bytes = DeobfuscatedFile::open(path, deobfuscator)?;
+    let result = extract(&mut bytes, options);
+    match bytes.take_error() {
+        Some(err) => Err(err),
+        None => Ok(result),
+    }
+}
+
+/// Read `range` of the file at `path`.
+pub fn read_range(path: &Path, range: Range<usize>) -> io::Result<Vec<u8>> {
+    let mut file = File::open(path)?;
+    file.seek(SeekFrom::Start(range.start as u64))?;
+    let mut data = Vec::with_capacity(range.len());
+    file.take(range.len() as u64).read_to_end(&mut data)?;
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::deobfs::{DeobfuscatedSlice, SwapAdjacent};
+    use std::io::Cursor;
+
+    #[test]
+    fn scan_all_finds_what_scanning_each_finds() {
+        // MPEG-1 Layer III frames, 128 kbps at 44.1 kHz, swapped at offset 1
+        let mut frame = vec![0x55; 417];
+        frame[..4].copy_from_slice(&[0xFF, 0xFB, 0x90, 0x00]);
+        let mut plain = vec![0; 1001];
+        for _ in 0..300 {
+            plain.extend_from_slice(&frame);
+        }
+        let swapped = SwapAdjacent {
+            period: 4,
+            offset: 1,
+        };
+        let input = deobfs::deobfuscate(&swapped, &plain);
+
+        let candidates: Vec<SwapAdjacent> = (0..4)
+            .map(|offset| SwapAdjacent { period: 4, offset })
+            .collect();
+        let deobfuscators: Vec<&dyn Deobfuscator> =
+            candidates.iter().map(|c| c as &dyn Deobfuscator).collect();
+        let options = ScanOptions::default();
+        let results = scan_all(Cursor::new(&input), &deobfuscators, &options).unwrap();
+        let spans = |streams: &[Stream]| -> Vec<(usize, usize)> {
+            streams.iter().map(|s| (s.offset, s.data.len())).collect()
+        };
+        assert_eq!(results.len(), deobfuscators.len());
+        assert_eq!(spans(results[1].as_ref().unwrap()), [(1001, 300 * 417)]);
+        for (d, result) in deobfuscators.iter().zip(results) {
+            let expected = extract(DeobfuscatedSlice::new(&input, *d), &options).unwrap();
+            assert_eq!(spans(&result.unwrap()), spans(&expected), "{}", d);
+        }
+    }
+}
@@ -0,0 +1,82 @@
+//! Video containers, which some cache entries hold instead of audio. They are
+//! recognized from their first bytes, so the scan for audio frames that
+//! aren't there can be skipped.
+
+use std::fmt;
+
+use crate::mp4;
+
+/// Bytes of the input `detect` looks at, at most.
+pub const DETECT_LEN: usize = 64 << 10;
+
+// MPEG-TS packets, each starting with a sync byte
+const TS_PACKET_LEN: usize = 188;
+const TS_PACKETS: usize = 4;
+
+/// A kind of video container.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Container {
+    /// MP4 or QuickTime with a video track
+    Mp4,
+    WebM,
+    Matroska,
+    Flv,
+    Avi,
+    MpegTs,
+}
+
+impl Container {
+    /// File name extension for the container.
+    pub fn extension(self) -> &'static str {
+        match self {
+            Container::Mp4 => "mp4",
+            Container::WebM => "webm",
+            Container::Matroska => "mkv",
+            Container::Flv => "flv",
+            Container::Avi => "avi",
+            Container::MpegTs => "ts",
+        }
+    }
+}
+
+impl fmt::Display for Container {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Container::Mp4 => write!(f, "MP4"),
+            Container::WebM => write!(f, "WebM"),
+            Container::Matroska => write!(f, "Matroska"),
+            Container::Flv => write!(f, "FLV"),
+            Container::Avi => write!(f, "AVI"),
+            Container::MpegTs => write!(f, "MPEG-TS"),
+        }
+    }
+}
+
+/// The video container `head`, the start of a deobfuscated input, starts
+/// with. An MP4 container only counts as video if its `moov` is in `head`
+/// and has a video track; one with an audio brand never does.
+pub fn detect(head: &[u8]) -> Option<Container> {
+    if head.starts_with(&[0x1A, 0x45, 0xDF, 0xA3]) {
+        // the EBML header's DocType tells the two apart
+        let header = &head[..head.len().min(64)];
+        let webm = header.windows(4).any(|window| window == b"webm");
+        return Some(if webm {
+            Container::WebM
+        } else {
+            Container::Matroska
+        });
+    }
+    if head.starts_with(b"FLV\x01") {
+        return Some(Container::Flv);
+    }
+    if head.starts_with(b"RIFF") && head.get(8..12) == Some(b"AVI ") {
+        return Some(Container::Avi);
+    }
+    if (0..TS_PACKETS).all(|packet| head.get(packet * TS_PACKET_LEN) == Some(&0x47)) {
+        return Some(Container::MpegTs);
+    }
+    if mp4::is_container_start(head) && mp4::has_video_track(head) == Some(true) {
+        return Some(Container::Mp4);
+    }
+    None
+}
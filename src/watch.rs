@@ -0,0 +1,69 @@
+use crate::input::Input;
+use crate::output;
+use notify_debouncer_mini::notify::RecursiveMode;
+use notify_debouncer_mini::{new_debouncer, DebounceEventResult};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::time::{Duration, SystemTime};
+use tracing::{error, info};
+
+/// How long a file has to stay unchanged before it's picked up, so files that
+/// are still being copied in aren't processed half-written.
+const SETTLE_TIME: Duration = Duration::from_secs(2);
+
+/// Watch `dir` for new or changed files and hand them to `process` as they
+/// settle. Runs until the watcher fails.
+///
+/// Files this run wrote itself (outputs, sidecars, checksum files, the log and
+/// state files) are ignored, so extracting into the watched directory doesn't
+/// loop.
+pub fn watch(
+    dir: &Path,
+    mut process: impl FnMut(&[Input]),
+) -> notify_debouncer_mini::notify::Result<()> {
+    let dir = fs::canonicalize(dir)?;
+    let (tx, rx) = mpsc::channel::<DebounceEventResult>();
+    let mut debouncer = new_debouncer(SETTLE_TIME, tx)?;
+    debouncer.watcher().watch(&dir, RecursiveMode::Recursive)?;
+    info!("watching {:?} for new files", dir);
+
+    let mut processed: HashMap<PathBuf, SystemTime> = HashMap::new();
+
+    for result in rx {
+        let events = match result {
+            Ok(events) => events,
+            Err(err) => {
+                error!("Error watching {:?}: {}", dir, err);
+                continue;
+            }
+        };
+
+        let mut inputs = Vec::new();
+        for event in events {
+            let path = event.path;
+            if output::was_written(&path) {
+                continue;
+            }
+            let modified = match fs::metadata(&path) {
+                Ok(meta) if meta.is_file() => meta.modified().ok(),
+                _ => continue,
+            };
+            if modified.is_some() && processed.get(&path) == modified.as_ref() {
+                continue;
+            }
+            if let Some(modified) = modified {
+                processed.insert(path.clone(), modified);
+            }
+            inputs.push(Input::File(path));
+        }
+        if inputs.is_empty() {
+            continue;
+        }
+
+        process(&inputs);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,232 @@
+//! WAV files: a RIFF chunk of form type `WAVE` holding a `fmt ` chunk that
+//! describes the audio (PCM, ADPCM, ...) and a `data` chunk with the audio
+//! itself, among others. Chunk lengths are little-endian, and chunks are
+//! padded to an even length.
+
+use std::convert::TryInto;
+use tracing::trace;
+
+use crate::carve::{Carver, Deadline, Format, ScanOptions, Source, Stopped, Stream};
+
+/// Length of a chunk header: its id and length.
+const CHUNK_HEADER_LEN: usize = 8;
+
+/// Length of the RIFF header including the form type.
+const RIFF_HEADER_LEN: usize = CHUNK_HEADER_LEN + 4;
+
+/// What the `fmt ` chunk says about the audio.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WaveFormat {
+    /// 1 for PCM, 2 for Microsoft ADPCM, ...
+    pub format_tag: u16,
+    pub channels: u16,
+    pub sample_rate: u32,
+    pub byte_rate: u32,
+    /// Bytes per block of samples, one per channel for PCM
+    pub block_align: u16,
+}
+
+/// Parse the contents of a `fmt ` chunk.
+pub fn parse_fmt(bytes: &[u8]) -> Option<WaveFormat> {
+    let bytes = bytes.get(..14)?;
+    let le_u16 = |at: usize| u16::from_le_bytes([bytes[at], bytes[at + 1]]);
+    let format = WaveFormat {
+        format_tag: le_u16(0),
+        channels: le_u16(2),
+        sample_rate: u32::from_le_bytes(bytes[4..8].try_into().ok()?),
+        byte_rate: u32::from_le_bytes(bytes[8..12].try_into().ok()?),
+        block_align: le_u16(12),
+    };
+    if format.channels == 0 || format.sample_rate == 0 || format.block_align == 0 {
+        return None;
+    }
+    Some(format)
+}
+
+/// Whether `id` looks like a chunk id: four printable characters.
+fn is_chunk_id(id: &[u8]) -> bool {
+    id.iter().all(|&c| c.is_ascii_graphic() || c == b' ')
+}
+
+/// WAV files, carved chunk by chunk to the end of the RIFF chunk.
+pub struct Wav;
+
+impl Carver for Wav {
+    fn starts_with(&self, byte: u8) -> bool {
+        byte == b'R'
+    }
+
+    /// The lengths of chunks are trusted, but a file cut short ends where the
+    /// input does and is truncated; `None` without a `fmt ` chunk before the
+    /// `data` chunk.
+    fn carve(
+        &self,
+        source: &mut dyn Source,
+        _options: &ScanOptions,
+        _deadline: &mut Deadline,
+    ) -> Result<Option<Stream>, Stopped> {
+        let riff_len = match source.peek(RIFF_HEADER_LEN) {
+            Some(header) if header.starts_with(b"RIFF") && &header[8..12] == b"WAVE" => {
+                let len = u32::from_le_bytes([header[4], header[5], header[6], header[7]]);
+                CHUNK_HEADER_LEN as u64 + u64::from(len)
+            }
+            _ => return Ok(None),
+        };
+        let offset = source.position();
+        let mut data = Vec::new();
+        source.take_into(RIFF_HEADER_LEN, &mut data);
+
+        let mut format = None;
+        let mut audio_len = None;
+        let mut truncated = false;
+        while (data.len() as u64) < riff_len {
+            let (id, len) = match source.peek(CHUNK_HEADER_LEN) {
+                Some(header) if is_chunk_id(&header[..4]) => {
+                    let len = u32::from_le_bytes([header[4], header[5], header[6], header[7]]);
+                    ([header[0], header[1], header[2], header[3]], len as usize)
+                }
+                _ => break,
+            };
+            match &id {
+                b"fmt " => {
+                    let chunk = source.peek(CHUNK_HEADER_LEN + len);
+                    format = chunk.and_then(|chunk| parse_fmt(&chunk[CHUNK_HEADER_LEN..]));
+                }
+                b"data" if format.is_none() => {
+                    trace!("{:#x}: rejected WAV file without fmt chunk", offset);
+                    return Ok(None);
+                }
+                _ => {}
+            }
+            let padded = CHUNK_HEADER_LEN + len + len % 2;
+            let taken = source.take_into(padded, &mut data);
+            if &id == b"data" {
+                audio_len = Some(taken.saturating_sub(CHUNK_HEADER_LEN).min(len));
+            }
+            if taken < padded {
+                trace!("{:#x}: WAV file cut short", offset);
+                truncated = true;
+                break;
+            }
+        }
+        let (format, audio_len) = match (format, audio_len) {
+            (Some(format), Some(audio_len)) => (format, audio_len),
+            _ => {
+                trace!("{:#x}: rejected WAV file without audio", offset);
+                return Ok(None);
+            }
+        };
+
+        let blocks = audio_len / usize::from(format.block_align);
+        let samples = match format.format_tag {
+            // PCM and IEEE float (also as WAVE_FORMAT_EXTENSIBLE) hold one
+            // sample per channel in a block; for the compressed formats this
+            // is a guess from the byte rate
+            1 | 3 | 0xFFFE => blocks as u64,
+            _ if format.byte_rate != 0 => {
+                audio_len as u64 * u64::from(format.sample_rate) / u64::from(format.byte_rate)
+            }
+            _ => 0,
+        };
+        Ok(Some(Stream {
+            data,
+            format: Format::Wav,
+            offset,
+            frames: blocks,
+            samples,
+            sample_rate: format.sample_rate,
+            bit_rate: format.byte_rate.saturating_mul(8),
+            channel_mode: None,
+            truncated,
+            tags: Vec::new(),
+            gapless: None,
+            corrupt_frames: 0,
+            gaps: Vec::new(),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::carve::extract;
+
+    fn chunk(id: &[u8; 4], body: &[u8]) -> Vec<u8> {
+        let mut chunk = id.to_vec();
+        chunk.extend_from_slice(&(body.len() as u32).to_le_bytes());
+        chunk.extend_from_slice(body);
+        if body.len() % 2 == 1 {
+            chunk.push(0);
+        }
+        chunk
+    }
+
+    /// 16 bit stereo PCM at 22.05 kHz; one second of noise, then an odd
+    /// length LIST chunk.
+    fn wav(data_first: bool) -> Vec<u8> {
+        let mut fmt = Vec::new();
+        for field in &[1u16, 2] {
+            fmt.extend_from_slice(&field.to_le_bytes());
+        }
+        fmt.extend_from_slice(&22050u32.to_le_bytes());
+        fmt.extend_from_slice(&88200u32.to_le_bytes());
+        for field in &[4u16, 16] {
+            fmt.extend_from_slice(&field.to_le_bytes());
+        }
+        let pcm: Vec<u8> = (0..88200usize)
+            .map(|i| (i.wrapping_mul(2_654_435_761) >> 16) as u8)
+            .collect();
+        let (fmt, pcm) = (chunk(b"fmt ", &fmt), chunk(b"data", &pcm));
+        let mut body = b"WAVE".to_vec();
+        if data_first {
+            body.extend_from_slice(&pcm);
+            body.extend_from_slice(&fmt);
+        } else {
+            body.extend_from_slice(&fmt);
+            body.extend_from_slice(&pcm);
+        }
+        body.extend_from_slice(&chunk(b"LIST", b"INFOx"));
+        chunk(b"RIFF", &body)
+    }
+
+    fn carve(input: Vec<u8>) -> Vec<Stream> {
+        let options = ScanOptions {
+            threshold: 0,
+            ..ScanOptions::default()
+        };
+        extract(input, &options).unwrap()
+    }
+
+    #[test]
+    fn file_carved_to_the_end_of_the_riff_chunk() {
+        let wav = wav(false);
+        let mut input = vec![0xEE; 3333];
+        input.extend_from_slice(&wav);
+        input.extend_from_slice(&[0xEE; 444]);
+        let streams = carve(input);
+        assert_eq!(streams.len(), 1);
+        let stream = &streams[0];
+        assert_eq!(stream.format, Format::Wav);
+        assert_eq!((stream.offset, stream.data.len()), (3333, wav.len()));
+        assert_eq!((stream.frames, stream.samples), (22050, 22050));
+        assert_eq!((stream.sample_rate, stream.bit_rate), (22050, 705_600));
+        assert!(!stream.truncated);
+    }
+
+    #[test]
+    fn file_cut_short() {
+        let mut input = vec![0xEE; 3333];
+        input.extend_from_slice(&wav(false)[..40_000]);
+        let streams = carve(input);
+        assert_eq!(streams.len(), 1);
+        assert_eq!(streams[0].data.len(), 40_000);
+        // the headers take 44 bytes
+        assert_eq!(streams[0].samples, (40_000 - 44) / 4);
+        assert!(streams[0].truncated);
+    }
+
+    #[test]
+    fn file_without_fmt_before_data_rejected() {
+        assert!(carve(wav(true)).is_empty());
+    }
+}
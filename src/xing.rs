@@ -0,0 +1,378 @@
+//! Xing/Info headers: a Layer III frame without audio in front of a stream
+//! that gives its frame and byte counts and a table of contents for seeking.
+//! Players need one for the duration of a VBR stream; `Xing` marks a VBR
+//! stream and `Info` a CBR one.
+
+use std::convert::TryInto;
+use tracing::trace;
+
+use crate::carve::{audio_start, Format, Gapless, Stream};
+use crate::mp3::{self, FrameHeader, Layer};
+
+// flags of the optional fields, in the order they come in
+const HAS_FRAMES: u32 = 0x01;
+const HAS_BYTES: u32 = 0x02;
+const HAS_TOC: u32 = 0x04;
+const HAS_QUALITY: u32 = 0x08;
+
+/// The fields written: frames, bytes and TOC.
+const FLAGS: u32 = HAS_FRAMES | HAS_BYTES | HAS_TOC;
+
+/// Length of the header from its tag on: tag, flags, frame count, byte count
+/// and the 100 byte TOC.
+const HEADER_LEN: usize = 4 + 4 + 4 + 4 + 100;
+
+/// Length of the LAME tag LAME and most encoders since append to the
+/// fields, with the encoder delay and padding, ending with the CRC-16 of the
+/// frame up to there.
+const LAME_TAG_LEN: usize = 36;
+
+/// Offset of the Xing/Info tag inside `frame`: it follows the CRC, if there
+/// is one, and the side information.
+fn tag_offset(frame: &FrameHeader) -> usize {
+    let crc = if frame.protected { 2 } else { 0 };
+    4 + crc + frame.side_info_len()
+}
+
+/// Whether `frame` is a Xing/Info frame rather than audio.
+pub fn is_header_frame(frame: &[u8]) -> bool {
+    let header = match frame.get(..4) {
+        Some(bytes) => u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+        None => return false,
+    };
+    let parsed = match mp3::parse_header(header) {
+        Ok(parsed) if parsed.layer == Layer::L3 => parsed,
+        _ => return false,
+    };
+    let at = tag_offset(&parsed);
+    matches!(frame.get(at..at + 4), Some(b"Xing") | Some(b"Info"))
+}
+
+/// Offsets of the back-to-back frames `audio` starts with, where the last
+/// one ends, and whether their bitrates differ. Free format frames aren't
+/// followed.
+fn frame_offsets(audio: &[u8]) -> (Vec<usize>, usize, bool) {
+    let mut offsets = Vec::new();
+    let mut bit_rate = None;
+    let mut vbr = false;
+    let mut pos = 0;
+    while let Some(bytes) = audio.get(pos..pos + 4) {
+        let header = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        let frame = match mp3::parse_header(header) {
+            Ok(frame) if !frame.is_free_format() && frame.length <= audio.len() - pos => frame,
+            _ => break,
+        };
+        vbr |= *bit_rate.get_or_insert(frame.bit_rate) != frame.bit_rate;
+        offsets.push(pos);
+        pos += frame.length;
+    }
+    (offsets, pos, vbr)
+}
+
+/// The TOC for frames at `offsets` after a Xing frame `lead` bytes long, of
+/// `bytes` in all: where in the file each percent of the playing time
+/// starts, in 1/256ths of the file.
+fn toc(offsets: &[usize], lead: usize, bytes: usize) -> impl Iterator<Item = u8> + '_ {
+    (0..100).map(move |percent| {
+        let offset = lead + offsets[percent * offsets.len() / 100];
+        (offset * 256 / bytes).min(255) as u8
+    })
+}
+
+/// CRC-16 as the LAME tag uses it (CRC-16/ARC).
+fn crc16(data: &[u8], crc: u16) -> u16 {
+    data.iter().fold(crc, |crc, &b| {
+        (0..8).fold(crc ^ u16::from(b), |crc, _| match crc & 1 {
+            1 => crc >> 1 ^ 0xA001,
+            _ => crc >> 1,
+        })
+    })
+}
+
+fn be_u32(data: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(data.get(at..at + 4)?.try_into().ok()?))
+}
+
+/// Where the fields of a Xing/Info frame are, from the start of the frame;
+/// `None` for those it doesn't have.
+struct Fields {
+    /// Length of the frame
+    lead: usize,
+    frames: Option<usize>,
+    bytes: Option<usize>,
+    toc: Option<usize>,
+    lame_tag: Option<usize>,
+}
+
+/// The fields of the Xing/Info frame `audio` starts with, if it does.
+fn fields(audio: &[u8]) -> Option<Fields> {
+    if !is_header_frame(audio) {
+        return None;
+    }
+    let frame = mp3::parse_header(be_u32(audio, 0)?).ok()?;
+    let frame_data = audio.get(..frame.length)?;
+    let tag_at = tag_offset(&frame);
+    let flags = be_u32(frame_data, tag_at + 4)?;
+    let mut at = tag_at + 8;
+    // a field that doesn't fit in the frame isn't taken, or updating it would
+    // write over the frame after
+    let mut field = |flag: u32, len: usize| {
+        let field = Some(at).filter(|_| flags & flag != 0);
+        at += field.map_or(0, |_| len);
+        field.filter(|_| at <= frame_data.len())
+    };
+    let frames = field(HAS_FRAMES, 4);
+    let bytes = field(HAS_BYTES, 4);
+    let toc = field(HAS_TOC, 100);
+    field(HAS_QUALITY, 4);
+    // what follows is only taken for a LAME tag if its CRC shows it is one
+    let lame_tag = frame_data.get(..at + LAME_TAG_LEN).and_then(|data| {
+        let stored = u16::from_be_bytes([data[at + 34], data[at + 35]]);
+        Some(at).filter(|_| crc16(&data[..at + 34], 0) == stored)
+    });
+    Some(Fields {
+        lead: frame.length,
+        frames,
+        bytes,
+        toc,
+        lame_tag,
+    })
+}
+
+/// The encoder delay and padding in the LAME tag of the MP3 stream `data`,
+/// if it starts with one.
+pub fn gapless(data: &[u8]) -> Option<Gapless> {
+    let audio = &data[audio_start(data)..];
+    let at = fields(audio)?.lame_tag? + 21;
+    // twelve bits each
+    let bytes = &audio[at..at + 3];
+    Some(Gapless {
+        delay: u32::from(bytes[0]) << 4 | u32::from(bytes[1] >> 4),
+        padding: u32::from(bytes[1] & 0x0F) << 8 | u32::from(bytes[2]),
+    })
+}
+
+/// Keep the Xing/Info frame `stream` starts with (and the LAME tag in it, with
+/// the encoder delay and padding for gapless playback) true to a stream that
+/// was cut short: when it counts more frames or bytes than were carved, the
+/// counts, TOC and the LAME tag's music length and CRCs are recomputed. The
+/// padding is cleared, as the end it was for is gone. Returns whether the
+/// frame was changed.
+pub fn update_header(stream: &mut Stream) -> bool {
+    if stream.format != Format::Mp3 {
+        return false;
+    }
+    let start = audio_start(&stream.data);
+    let audio = &mut stream.data[start..];
+    let fields = match fields(audio) {
+        Some(fields) => fields,
+        None => return false,
+    };
+    let lead = fields.lead;
+    let (offsets, end, _) = frame_offsets(&audio[lead..]);
+    let frames = offsets.len();
+    let bytes = lead + end;
+
+    let trimmed = fields
+        .frames
+        .and_then(|at| be_u32(audio, at))
+        .is_some_and(|n| (frames as u32) < n)
+        || fields
+            .bytes
+            .and_then(|at| be_u32(audio, at))
+            .is_some_and(|n| (bytes as u32) < n);
+    if !trimmed || frames == 0 {
+        return false;
+    }
+    trace!(
+        "{:#x}: updating Xing header for {} frames, {} bytes",
+        stream.offset,
+        frames,
+        bytes
+    );
+
+    if let Some(at) = fields.frames {
+        audio[at..at + 4].copy_from_slice(&(frames as u32).to_be_bytes());
+    }
+    if let Some(at) = fields.bytes {
+        audio[at..at + 4].copy_from_slice(&(bytes as u32).to_be_bytes());
+    }
+    if let Some(at) = fields.toc {
+        let toc: Vec<u8> = toc(&offsets, lead, bytes).collect();
+        audio[at..at + 100].copy_from_slice(&toc);
+    }
+    if let Some(at) = fields.lame_tag {
+        audio[at + 22] &= 0xF0;
+        audio[at + 23] = 0;
+        let music_crc = crc16(&audio[lead..bytes], 0);
+        audio[at + 28..at + 32].copy_from_slice(&(bytes as u32).to_be_bytes());
+        audio[at + 32..at + 34].copy_from_slice(&music_crc.to_be_bytes());
+        let tag_crc = crc16(&audio[..at + 34], 0);
+        audio[at + 34..at + 36].copy_from_slice(&tag_crc.to_be_bytes());
+    }
+    true
+}
+
+/// Put a Xing/Info frame in front of the frames of `stream`, an MP3 stream of
+/// Layer III frames; nothing is done for other streams, for free format ones
+/// and for streams that already start with one. Returns whether it was added.
+pub fn add_header(stream: &mut Stream) -> bool {
+    if stream.format != Format::Mp3 {
+        return false;
+    }
+    let start = audio_start(&stream.data);
+    let audio = &stream.data[start..];
+    if is_header_frame(audio) {
+        return false;
+    }
+
+    let (offsets, end, vbr) = frame_offsets(audio);
+    let first = audio.get(..4).and_then(|bytes| {
+        let header = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        Some((header, mp3::parse_header(header).ok()?)).filter(|_| !offsets.is_empty())
+    });
+    let (header, frame) = match first {
+        Some((header, frame)) if frame.layer == Layer::L3 => (header, frame),
+        _ => {
+            trace!(
+                "{:#x}: no Xing header for a stream without Layer III frames",
+                stream.offset
+            );
+            return false;
+        }
+    };
+
+    // the lowest bitrate whose frames have room for the header, keeping the
+    // rest of the first frame's header but without CRC or padding
+    let tag_at = tag_offset(&FrameHeader {
+        protected: false,
+        ..frame
+    });
+    let xing = (1..15).find_map(|index| {
+        let header = header & !0x0000F200 | 0x00010000 | index << 12;
+        let frame = mp3::parse_header(header).ok()?;
+        Some((header, frame)).filter(|(_, frame)| frame.length >= tag_at + HEADER_LEN)
+    });
+    let (xing_header, xing_frame) = match xing {
+        Some(xing) => xing,
+        None => return false,
+    };
+
+    let frames = offsets.len();
+    let bytes = xing_frame.length + end;
+    let mut out = vec![0; xing_frame.length];
+    out[..4].copy_from_slice(&xing_header.to_be_bytes());
+    let mut fields = Vec::with_capacity(HEADER_LEN);
+    fields.extend_from_slice(if vbr { b"Xing" } else { b"Info" });
+    fields.extend_from_slice(&FLAGS.to_be_bytes());
+    fields.extend_from_slice(&(frames as u32).to_be_bytes());
+    fields.extend_from_slice(&(bytes as u32).to_be_bytes());
+    fields.extend(toc(&offsets, xing_frame.length, bytes));
+    out[tag_at..tag_at + HEADER_LEN].copy_from_slice(&fields);
+
+    stream.data.splice(start..start, out);
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mp3_stream(data: Vec<u8>) -> Stream {
+        Stream {
+            data,
+            format: Format::Mp3,
+            offset: 0,
+            frames: 0,
+            samples: 0,
+            sample_rate: 0,
+            bit_rate: 0,
+            channel_mode: None,
+            truncated: false,
+            tags: Vec::new(),
+            gapless: None,
+            corrupt_frames: 0,
+            gaps: Vec::new(),
+        }
+    }
+
+    /// `count` frames with the header `header`, and nothing in them.
+    fn frames(header: u32, count: usize) -> Vec<u8> {
+        let length = mp3::parse_header(header).unwrap().length;
+        let mut frame = vec![0; length];
+        frame[..4].copy_from_slice(&header.to_be_bytes());
+        frame.repeat(count)
+    }
+
+    #[test]
+    fn header_added_in_front_of_cbr_frames() {
+        // MPEG-1 Layer III at 128 kbps and 44.1 kHz, stereo
+        let mut stream = mp3_stream(frames(0xFFFB_9000, 50));
+        assert!(add_header(&mut stream));
+        assert!(!add_header(&mut stream));
+
+        let fields = fields(&stream.data).unwrap();
+        // the lowest bitrate with room, 48 kbps
+        assert_eq!(fields.lead, 156);
+        assert_eq!(&stream.data[36..40], b"Info");
+        let frames = be_u32(&stream.data, fields.frames.unwrap());
+        let bytes = be_u32(&stream.data, fields.bytes.unwrap());
+        assert_eq!((frames, bytes), (Some(50), Some(156 + 50 * 417)));
+        let toc = fields.toc.unwrap();
+        assert_eq!(stream.data[toc], (156 * 256 / (156 + 50 * 417)) as u8);
+        assert_eq!(stream.data.len(), 156 + 50 * 417);
+    }
+
+    #[test]
+    fn fields_past_a_short_frame_are_left_alone() {
+        // MPEG-2 Layer III at 8 kbps and 24 kHz, mono: 24 bytes, with the
+        // frame count from byte 21 on
+        let mut data = frames(0xFFF3_14C0, 11);
+        data[13..17].copy_from_slice(b"Xing");
+        data[17..21].copy_from_slice(&FLAGS.to_be_bytes());
+        data.truncate(24 * 6);
+        let mut stream = mp3_stream(data.clone());
+        assert!(!update_header(&mut stream));
+        assert_eq!(stream.data, data);
+    }
+
+    #[test]
+    fn counts_and_lame_tag_updated_for_a_cut_stream() {
+        let header = 0xFFFB_9000;
+        let mut data = frames(header, 51);
+        data[36..40].copy_from_slice(b"Xing");
+        data[40..44].copy_from_slice(&FLAGS.to_be_bytes());
+        data[44..48].copy_from_slice(&50u32.to_be_bytes());
+        data[48..52].copy_from_slice(&(51 * 417u32).to_be_bytes());
+        // LAME tag, with a delay of 576 and padding of 1000 samples
+        data[152..161].copy_from_slice(b"LAME3.100");
+        data[173..176].copy_from_slice(&[0x24, 0x03, 0xE8]);
+        let crc = crc16(&data[..186], 0);
+        data[186..188].copy_from_slice(&crc.to_be_bytes());
+        let mut stream = mp3_stream(data);
+        assert_eq!(
+            gapless(&stream.data),
+            Some(Gapless {
+                delay: 576,
+                padding: 1000
+            })
+        );
+
+        stream.data.truncate(21 * 417 + 100);
+        assert!(update_header(&mut stream));
+        let fields = fields(&stream.data).unwrap();
+        let frames = be_u32(&stream.data, fields.frames.unwrap());
+        let bytes = be_u32(&stream.data, fields.bytes.unwrap());
+        assert_eq!((frames, bytes), (Some(20), Some(21 * 417)));
+        assert_eq!(fields.lame_tag, Some(152));
+        assert_eq!(be_u32(&stream.data, 180), Some(21 * 417));
+        assert_eq!(
+            gapless(&stream.data),
+            Some(Gapless {
+                delay: 576,
+                padding: 0
+            })
+        );
+        assert!(!update_header(&mut stream));
+    }
+}